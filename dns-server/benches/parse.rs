@@ -0,0 +1,20 @@
+// Times DNSMessage::from_bytes on a typical query and response.
+// Run with: cargo bench --bench parse
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use dns_starter_rust::{build_query, parse_and_reply, DNSMessage, Type};
+
+fn parse(c: &mut Criterion) {
+    let query = build_query("codecrafters.io", Type::A, 1234).expect("valid query");
+    let response = parse_and_reply(&query).expect("valid response");
+    c.bench_function("parse query", |b| {
+        b.iter(|| DNSMessage::from_bytes(black_box(&query)).unwrap())
+    });
+    c.bench_function("parse response", |b| {
+        b.iter(|| DNSMessage::from_bytes(black_box(&response)).unwrap())
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::domains::Domains;
+use crate::message::{DNSMessage, ResponseCode};
+
+// Names that are answered with NXDOMAIN (NameError), like an ad or tracker sinkhole.
+// A listed name blocks its subdomains too.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Blocklist {
+    names: Domains,
+}
+
+impl Blocklist {
+    // Loads the names from a file with one per line, empty lines and # comments are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(Self::from_names(content.lines().filter(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })))
+    }
+
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            names: Domains::from_names(names),
+        }
+    }
+
+    // If the name or any of its parent domains is listed, ignoring the case.
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    // The NameError reply if the message asks for a blocked name, None if it has to be served.
+    pub fn sinkhole(&self, buf: &[u8]) -> Option<Vec<u8>> {
+        let message = DNSMessage::from_bytes(buf).ok()?;
+        let blocked = message.question_names().any(|name| self.contains(name));
+        if !blocked {
+            return None;
+        }
+        message
+            .build_error_reply(ResponseCode::NameError)
+            .to_bytes()
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_query, parse_and_reply, Type};
+
+    #[test]
+    fn test_contains() {
+        let blocklist = Blocklist::from_names(["ads.example.com", "Tracker.io."]);
+        assert!(blocklist.contains("ads.example.com"));
+        assert!(blocklist.contains("ADS.Example.com."));
+        assert!(blocklist.contains("a.b.ads.example.com"));
+        assert!(blocklist.contains("tracker.io"));
+        assert!(!blocklist.contains("example.com"));
+        assert!(!blocklist.contains("badads.example.com"));
+    }
+
+    #[test]
+    fn test_sinkhole() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("dns_blocklist_{}", std::process::id()));
+        fs::write(&path, "# trackers\nads.example.com\n\n")?;
+        let blocklist = Blocklist::load(&path)?;
+        fs::remove_file(&path)?;
+
+        let query = build_query("Pixel.ADS.example.com", Type::A, 7)?;
+        let reply = blocklist.sinkhole(&query).expect("not sinkholed");
+        let message = DNSMessage::from_bytes(&reply)?;
+        assert_eq!(ResponseCode::NameError, message.header.response_code);
+        assert_eq!(1, message.questions());
+        assert_eq!(0, message.answers());
+
+        // Not listed, answered normally
+        let query = build_query("example.com", Type::A, 8)?;
+        assert_eq!(None, blocklist.sinkhole(&query));
+        let message = DNSMessage::from_bytes(&parse_and_reply(&query)?)?;
+        assert_eq!(1, message.answers());
+        Ok(())
+    }
+}
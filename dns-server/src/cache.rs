@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::message::{DNSMessage, ResourceRecord, Type};
+
+// How long the expired records are kept, to serve them if the resolver is down
+const STALE_GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+// The TTL of a stale record, so the client asks again soon
+const STALE_TTL: u32 = 30;
+
+// The records answered by the resolver, served until their TTL expires.
+// The names are case-insensitive, the records are stored by their lowercase name.
+#[derive(Debug, Clone, Default)]
+pub struct Cache {
+    entries: HashMap<(String, Type), Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    record: ResourceRecord,
+    inserted: Instant,
+}
+
+impl Cache {
+    pub(crate) fn insert(&mut self, record: ResourceRecord, now: Instant) {
+        // It would expire right away
+        if record.ttl == 0 {
+            return;
+        }
+        let key = (record.name.to_ascii_lowercase(), record.atype);
+        let entry = Entry {
+            record,
+            inserted: now,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    // The cached record with the TTL it has left, None once it expired.
+    pub(crate) fn get(&mut self, name: &str, qtype: Type, now: Instant) -> Option<ResourceRecord> {
+        match self.get_stale(name, qtype, now)? {
+            (_, true) => None,
+            (record, false) => Some(record),
+        }
+    }
+
+    // The record even if it expired, then with a short TTL and true. Only for when the
+    // resolver fails, the records past the stale grace period are removed.
+    pub(crate) fn get_stale(
+        &mut self,
+        name: &str,
+        qtype: Type,
+        now: Instant,
+    ) -> Option<(ResourceRecord, bool)> {
+        let key = (name.to_ascii_lowercase(), qtype);
+        let entry = self.entries.get(&key)?;
+        let ttl = Duration::from_secs(entry.record.ttl as u64);
+        let elapsed = now.duration_since(entry.inserted);
+        if elapsed >= ttl + STALE_GRACE {
+            self.entries.remove(&key);
+            return None;
+        }
+        let mut record = entry.record.clone();
+        let remaining = (entry.record.ttl as u64).saturating_sub(elapsed.as_secs());
+        record.ttl = match remaining {
+            0 => STALE_TTL,
+            remaining => remaining as u32,
+        };
+        Some((record, remaining == 0))
+    }
+
+    // The reply for the query if all its questions are cached.
+    pub fn reply(&mut self, buf: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let message = DNSMessage::from_bytes(buf).ok()?;
+        self.reply_message(message, now, false)
+            .and_then(|reply| reply.to_bytes().ok())
+    }
+
+    // The reply with the cached answers for all the questions, with the expired ones if stale.
+    // The answers have the name as it was asked.
+    pub(crate) fn reply_message(
+        &mut self,
+        message: DNSMessage,
+        now: Instant,
+        stale: bool,
+    ) -> Option<DNSMessage> {
+        let questions = message.question.as_ref()?;
+        let answers = questions
+            .iter()
+            .map(|q| {
+                let record = match stale {
+                    true => self
+                        .get_stale(&q.name, q.qtype, now)
+                        .map(|(record, _)| record),
+                    false => self.get(&q.name, q.qtype, now),
+                };
+                record.map(|mut record| {
+                    record.name = q.name.clone();
+                    record
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(message.build_reply_with(answers))
+    }
+
+    // A line for each cached record with its type and the TTL it has left, the expired ones
+    // are marked as stale.
+    pub fn list(&self, now: Instant) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|((name, qtype), entry)| {
+                let elapsed = now.duration_since(entry.inserted).as_secs();
+                match (entry.record.ttl as u64).saturating_sub(elapsed) {
+                    0 => format!("{name} {qtype:?} stale"),
+                    remaining => format!("{name} {qtype:?} {remaining}"),
+                }
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_query;
+
+    fn record(name: &str, ttl: u32) -> ResourceRecord {
+        let mut record = ResourceRecord::answer_by_type(Type::A, name).unwrap();
+        record.ttl = ttl;
+        record
+    }
+
+    #[test]
+    fn test_ttl_decrement() {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        cache.insert(record("codecrafters.io", 60), start);
+
+        let cached = cache.get("codecrafters.io", Type::A, start + Duration::from_secs(10));
+        assert_eq!(Some(50), cached.map(|record| record.ttl));
+        let cached = cache.get(
+            "codecrafters.io",
+            Type::A,
+            start + Duration::from_millis(59_900),
+        );
+        assert_eq!(Some(1), cached.map(|record| record.ttl));
+        assert_eq!(None, cache.get("codecrafters.io", Type::MX, start));
+
+        // Expired, it's only kept to serve it stale
+        let expired = start + Duration::from_secs(60);
+        assert_eq!(None, cache.get("codecrafters.io", Type::A, expired));
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn test_reply() -> anyhow::Result<()> {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        let query = build_query("codecrafters.io", Type::A, 3)?;
+        assert_eq!(None, cache.reply(&query, start));
+
+        cache.insert(record("codecrafters.io", 60), start);
+        let reply = cache
+            .reply(&query, start + Duration::from_secs(5))
+            .expect("not cached");
+        let reply = DNSMessage::from_bytes(&reply)?;
+        assert_eq!(3, reply.id());
+        assert_eq!(1, reply.answers());
+        assert_eq!(55, reply.answer.unwrap()[0].ttl);
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive() -> anyhow::Result<()> {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        cache.insert(record("CodeCrafters.io", 60), start);
+        cache.insert(record("codecrafters.IO", 60), start);
+        assert_eq!(1, cache.len());
+
+        let query = build_query("CODECRAFTERS.io", Type::A, 4)?;
+        let reply = cache.reply(&query, start).expect("not cached");
+        let reply = DNSMessage::from_bytes(&reply)?;
+        // The answer echoes the name of the question
+        assert_eq!("CODECRAFTERS.io", reply.answer.unwrap()[0].name);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list() {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        cache.insert(record("codecrafters.io", 60), start);
+        cache.insert(record("another.io", 10), start);
+
+        let later = start + Duration::from_secs(15);
+        assert_eq!(
+            vec!["another.io A stale", "codecrafters.io A 45"],
+            cache.list(later)
+        );
+        cache.flush();
+        assert!(cache.list(later).is_empty());
+    }
+
+    #[test]
+    fn test_stale() {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        cache.insert(record("codecrafters.io", 60), start);
+
+        let (fresh, expired) = cache
+            .get_stale("codecrafters.io", Type::A, start + Duration::from_secs(20))
+            .unwrap();
+        assert_eq!((40, false), (fresh.ttl, expired));
+        let later = start + Duration::from_secs(120);
+        assert_eq!(None, cache.get("codecrafters.io", Type::A, later));
+        let (stale, expired) = cache.get_stale("codecrafters.io", Type::A, later).unwrap();
+        assert_eq!((STALE_TTL, true), (stale.ttl, expired));
+
+        // Evicted after the grace period
+        let evicted = start + Duration::from_secs(60) + STALE_GRACE;
+        assert_eq!(None, cache.get_stale("codecrafters.io", Type::A, evicted));
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use std::{fs, io};
+
+use anyhow::{anyhow, Result};
+
+use crate::Cache;
+
+// Listens for the text commands of the control socket at the path, in its own thread.
+// Each command is a line, answered with the lines of its output and OK, or ERR and the error.
+pub fn spawn_control(path: &Path, cache: Arc<Mutex<Cache>>) -> Result<JoinHandle<()>> {
+    // The socket of a previous run is left behind
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| serve(stream, &cache));
+            if let Err(e) = result {
+                eprintln!("Error in the control socket: {}", e);
+            }
+        }
+    });
+    Ok(handle)
+}
+
+fn serve(stream: UnixStream, cache: &Mutex<Cache>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        match run(&line?, cache) {
+            Ok(output) => {
+                for line in output {
+                    writeln!(writer, "{line}")?;
+                }
+                writeln!(writer, "OK")?;
+            }
+            Err(e) => writeln!(writer, "ERR {e}")?,
+        }
+    }
+    Ok(())
+}
+
+fn run(command: &str, cache: &Mutex<Cache>) -> Result<Vec<String>> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let mut cache = cache
+        .lock()
+        .map_err(|_| anyhow!("the cache is unavailable"))?;
+    match words.as_slice() {
+        ["CACHE", "LIST"] => Ok(cache.list(Instant::now())),
+        ["CACHE", "FLUSH"] => {
+            cache.flush();
+            Ok(vec![])
+        }
+        _ => Err(anyhow!("unknown command: {command}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{ResourceRecord, Type};
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_flush() -> Result<()> {
+        let mut cache = Cache::default();
+        let record = ResourceRecord::answer_by_type(Type::A, "codecrafters.io").unwrap();
+        cache.insert(record, Instant::now());
+        let cache = Arc::new(Mutex::new(cache));
+        let path = env::temp_dir().join(format!("dns_control_{}", process::id()));
+        spawn_control(&path, cache.clone())?;
+
+        let mut stream = UnixStream::connect(&path)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut read_line = || -> Result<String> {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            Ok(line.trim_end().to_string())
+        };
+        writeln!(stream, "CACHE LIST")?;
+        assert_eq!("codecrafters.io A 60", read_line()?);
+        assert_eq!("OK", read_line()?);
+        writeln!(stream, "CACHE FLUSH")?;
+        assert_eq!("OK", read_line()?);
+        writeln!(stream, "CACHE LIST")?;
+        assert_eq!("OK", read_line()?);
+        writeln!(stream, "FLUSH")?;
+        assert_eq!("ERR unknown command: FLUSH", read_line()?);
+
+        assert!(cache.lock().unwrap().is_empty());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::HashSet;
+
+// A set of domains that also matches their subdomains, ignoring the case and the trailing dot.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct Domains {
+    names: HashSet<String>,
+}
+
+impl Domains {
+    pub(crate) fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            names: names.into_iter().map(normalize).collect(),
+        }
+    }
+
+    // If the name or any of its parent domains is in the set.
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        let name = normalize(name);
+        let mut domain = name.as_str();
+        loop {
+            if self.names.contains(domain) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
@@ -0,0 +1,143 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+
+use crate::framing::exchange_framed;
+use crate::tls;
+
+const DOT_PORT: u16 = 853;
+
+// A DNS-over-TLS resolver (RFC 7858), the queries are framed like over TCP inside a TLS
+// connection. Written as <address>#<name>, like 1.1.1.1#one.one.one.one, the certificate has to
+// be valid for the name (or for the address without it).
+#[derive(Debug, Clone)]
+pub struct DotResolver {
+    address: SocketAddr,
+    name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+}
+
+// The resolvers parsed from the arguments share the same roots.
+impl PartialEq for DotResolver {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address && self.name == other.name
+    }
+}
+
+impl FromStr for DotResolver {
+    type Err = anyhow::Error;
+
+    fn from_str(resolver: &str) -> Result<Self> {
+        let (address, name) = match resolver.split_once('#') {
+            Some((address, name)) => (address, Some(name)),
+            None => (resolver, None),
+        };
+        // The port is optional, 853 by default
+        let address = match address.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, DOT_PORT),
+            Err(_) => address
+                .parse()
+                .map_err(|_| anyhow!("{resolver}: invalid address"))?,
+        };
+        let name = match name {
+            Some(name) => ServerName::try_from(name.to_string())
+                .map_err(|_| anyhow!("{resolver}: invalid name {name}"))?,
+            None => ServerName::IpAddress(address.ip().into()),
+        };
+        Ok(Self {
+            address,
+            name,
+            config: tls::default_config(),
+        })
+    }
+}
+
+impl DotResolver {
+    // Sends the query in a new connection and returns the reply.
+    pub fn exchange(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = tls::connect(self.address, self.name.clone(), self.config.clone())?;
+        exchange_framed(&mut stream, query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::{frame, read_framed};
+    use crate::tls::tests::self_signed;
+    use crate::{build_query, create_forwarder, parse_and_reply, DNSMessage, Type};
+    use rustls::{ServerConnection, StreamOwned};
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_from_str() -> Result<()> {
+        let resolver: DotResolver = "1.1.1.1#one.one.one.one".parse()?;
+        assert_eq!(SocketAddr::from(([1, 1, 1, 1], 853)), resolver.address);
+        assert_eq!(
+            ServerName::try_from("one.one.one.one")?.to_owned(),
+            resolver.name
+        );
+        let resolver: DotResolver = "127.0.0.1:8853".parse()?;
+        assert_eq!(SocketAddr::from(([127, 0, 0, 1], 8853)), resolver.address);
+        assert_eq!(ServerName::try_from("127.0.0.1")?.to_owned(), resolver.name);
+        let resolver: DotResolver = "[::1]:8853#dns.test".parse()?;
+        assert_eq!(8853, resolver.address.port());
+        assert!("dns.google".parse::<DotResolver>().is_err());
+        assert!("1.1.1.1#not a name".parse::<DotResolver>().is_err());
+        Ok(())
+    }
+
+    // Answers a single query over TLS with the reply of the local data
+    fn mock_server(name: &str) -> Result<(DotResolver, thread::JoinHandle<Result<()>>)> {
+        let (config, roots) = self_signed(name)?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let resolver = DotResolver {
+            config: tls::client_config(roots),
+            ..format!("{}#{name}", listener.local_addr()?).parse()?
+        };
+        let handle = thread::spawn(move || -> Result<()> {
+            let (stream, _) = listener.accept()?;
+            let mut stream = StreamOwned::new(ServerConnection::new(config)?, stream);
+            let query = read_framed(&mut stream)?;
+            stream.write_all(&frame(&parse_and_reply(&query)?)?)?;
+            stream.flush()?;
+            Ok(())
+        });
+        Ok((resolver, handle))
+    }
+
+    #[test]
+    fn test_forward() -> Result<()> {
+        let (resolver, handle) = mock_server("dns.test")?;
+        let query = build_query("codecrafters.io", Type::A, 42)?;
+        let destination = ([127, 0, 0, 1], 5353).into();
+        let mut forwarder = create_forwarder(&query, destination)?;
+        forwarder.forward_with(|query| resolver.exchange(query))?;
+
+        handle.join().expect("the server panicked")?;
+        let reply = DNSMessage::from_bytes(&forwarder.build_reply()?)?;
+        assert_eq!(42, reply.id());
+        assert_eq!(1, reply.answers());
+        Ok(())
+    }
+
+    #[test]
+    fn test_certificate_verified() -> Result<()> {
+        let (resolver, handle) = mock_server("dns.test")?;
+        // The certificate is not valid for another name
+        let resolver = DotResolver {
+            name: ServerName::try_from("other.test")?.to_owned(),
+            ..resolver
+        };
+        let query = build_query("codecrafters.io", Type::A, 43)?;
+        assert!(resolver.exchange(&query).is_err());
+        assert!(handle.join().expect("the server panicked").is_err());
+        Ok(())
+    }
+}
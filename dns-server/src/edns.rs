@@ -0,0 +1,48 @@
+use std::net::IpAddr;
+
+// The option code of the EDNS client subnet (RFC 7871)
+pub(crate) const CLIENT_SUBNET: u16 = 8;
+
+// The data of the client subnet option: the address family, the source prefix length, the
+// scope prefix length (always 0 in queries) and the network, only with the octets needed for
+// the prefix. The prefix is capped at the length of the address.
+pub(crate) fn client_subnet(ip: IpAddr, prefix: u8) -> Vec<u8> {
+    let (family, octets, max_prefix) = match ip {
+        IpAddr::V4(ip) => (1u16, ip.octets().to_vec(), 32),
+        IpAddr::V6(ip) => (2u16, ip.octets().to_vec(), 128),
+    };
+    let prefix = prefix.min(max_prefix);
+    let mut data = family.to_be_bytes().to_vec();
+    data.push(prefix);
+    data.push(0);
+    let len = (prefix as usize).div_ceil(8);
+    data.extend_from_slice(&octets[..len]);
+    // The bits after the prefix must be zero
+    let remainder = prefix % 8;
+    if remainder != 0 {
+        if let Some(last) = data.last_mut() {
+            *last &= 0xFF << (8 - remainder);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_subnet() {
+        let ip: IpAddr = [192, 168, 77, 9].into();
+        assert_eq!(vec![0, 1, 24, 0, 192, 168, 77], client_subnet(ip, 24));
+        assert_eq!(vec![0, 1, 20, 0, 192, 168, 64], client_subnet(ip, 20));
+        assert_eq!(vec![0, 1, 0, 0], client_subnet(ip, 0));
+        assert_eq!(vec![0, 1, 32, 0, 192, 168, 77, 9], client_subnet(ip, 40));
+
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(
+            vec![0, 2, 32, 0, 0x20, 0x01, 0x0d, 0xb8],
+            client_subnet(ip, 32)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Result};
+
+// The messages over a stream are prefixed by their length in 2 bytes, over TCP and TLS.
+pub fn frame(message: &[u8]) -> Result<Vec<u8>> {
+    let length: u16 = message
+        .len()
+        .try_into()
+        .map_err(|_| anyhow!("the message is longer than {} octets", u16::MAX))?;
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+    Ok(framed)
+}
+
+// Reads the next length-prefixed message of the stream.
+pub fn read_framed(stream: &mut impl Read) -> Result<Vec<u8>> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+// Sends the query over the stream and waits for the reply, whatever the transport is.
+pub fn exchange_framed<S: Read + Write>(stream: &mut S, query: &[u8]) -> Result<Vec<u8>> {
+    stream.write_all(&frame(query)?)?;
+    stream.flush()?;
+    read_framed(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_query, parse_and_reply, DNSMessage, Type};
+    use std::io::{self, Cursor};
+
+    // Records what is written, and reads a canned reply
+    struct MockStream {
+        written: Vec<u8>,
+        reply: Cursor<Vec<u8>>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reply.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_exchange_framed() -> Result<()> {
+        let query = build_query("codecrafters.io", Type::A, 9)?;
+        let reply = parse_and_reply(&query)?;
+        let mut stream = MockStream {
+            written: vec![],
+            reply: Cursor::new(frame(&reply)?),
+        };
+
+        let received = exchange_framed(&mut stream, &query)?;
+        assert_eq!(
+            query.len() as u16,
+            u16::from_be_bytes([stream.written[0], stream.written[1]])
+        );
+        assert_eq!(&query, &stream.written[2..]);
+        assert_eq!(9, DNSMessage::from_bytes(&received)?.id());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_framed_truncated() {
+        let mut stream = Cursor::new(vec![0, 5, 1, 2]);
+        assert!(read_framed(&mut stream).is_err());
+        assert!(frame(&vec![0; u16::MAX as usize + 1]).is_err());
+    }
+}
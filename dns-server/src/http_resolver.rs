@@ -0,0 +1,291 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+
+use crate::{tls, RESOLVE_TIMEOUT};
+
+const CONTENT_TYPE: &str = "application/dns-message";
+
+// A DNS-over-HTTPS resolver (RFC 8484), the queries are sent in their wire format as a POST.
+// An http:// URL sends them over plain HTTP, like to a local proxy to a DoH server.
+#[derive(Debug, Clone)]
+pub struct HttpResolver {
+    host: String,
+    port: u16,
+    path: String,
+    // Verifies the certificate of an https:// server, None over plain HTTP
+    tls: Option<Arc<ClientConfig>>,
+}
+
+// The resolvers parsed from the arguments share the same roots.
+impl PartialEq for HttpResolver {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.host, self.port, &self.path, self.tls.is_some())
+            == (&other.host, other.port, &other.path, other.tls.is_some())
+    }
+}
+
+impl FromStr for HttpResolver {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let (rest, tls, default_port) = match url.split_once("://") {
+            Some(("https", rest)) => (rest, Some(tls::default_config()), 443),
+            Some(("http", rest)) => (rest, None, 80),
+            _ => return Err(anyhow!("{url}: expecting an https:// or http:// URL")),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse()?),
+            None => (authority, default_port),
+        };
+        if host.is_empty() {
+            return Err(anyhow!("{url}: missing host"));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            tls,
+        })
+    }
+}
+
+impl HttpResolver {
+    // Sends the query and returns the DNS message in the body of the response.
+    pub fn exchange(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("{}: no address found", self.host))?;
+        let response = match &self.tls {
+            Some(config) => {
+                let name = ServerName::try_from(self.host.clone())?;
+                let stream = tls::connect(address, name, config.clone())?;
+                self.send(stream, query)?
+            }
+            None => {
+                let stream = TcpStream::connect_timeout(&address, RESOLVE_TIMEOUT)?;
+                stream.set_read_timeout(Some(RESOLVE_TIMEOUT))?;
+                self.send(stream, query)?
+            }
+        };
+        parse_response(&response)
+    }
+
+    // Sends the request and reads until the server closes the connection after the response.
+    // A TLS connection closed without close_notify is accepted like a plain one, the body is
+    // checked against its Content-Length anyway.
+    fn send(&self, mut stream: impl Read + Write, query: &[u8]) -> Result<Vec<u8>> {
+        stream.write_all(&self.request(query))?;
+        stream.flush()?;
+        let mut response = Vec::new();
+        match stream.read_to_end(&mut response) {
+            Err(e) if e.kind() != io::ErrorKind::UnexpectedEof || response.is_empty() => {
+                Err(e.into())
+            }
+            _ => Ok(response),
+        }
+    }
+
+    fn request(&self, query: &[u8]) -> Vec<u8> {
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {CONTENT_TYPE}\r\nAccept: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            query.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(query);
+        request
+    }
+}
+
+// The body of a successful HTTP response, a chunked one isn't supported.
+fn parse_response(response: &[u8]) -> Result<Vec<u8>> {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("incomplete HTTP response"))?;
+    let head = std::str::from_utf8(&response[..end])?;
+    let body = &response[end + 4..];
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => {}
+        _ => return Err(anyhow!("the HTTP resolver failed: {status}")),
+    }
+    let mut length = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = Some(value.parse::<usize>()?),
+            "transfer-encoding" if value.eq_ignore_ascii_case("chunked") => {
+                return Err(anyhow!("chunked HTTP responses are not supported"))
+            }
+            _ => {}
+        }
+    }
+    match length {
+        Some(length) => body
+            .get(..length)
+            .map(|body| body.to_vec())
+            .ok_or_else(|| anyhow!("the HTTP response body is shorter than {length}")),
+        None => Ok(body.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::tests::self_signed;
+    use crate::{build_query, create_forwarder, parse_and_reply, DNSMessage, Type};
+    use rustls::{ServerConnection, StreamOwned};
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_from_str() -> Result<()> {
+        let resolver: HttpResolver = "http://127.0.0.1:8053/dns-query".parse()?;
+        assert_eq!(
+            HttpResolver {
+                host: "127.0.0.1".to_string(),
+                port: 8053,
+                path: "/dns-query".to_string(),
+                tls: None,
+            },
+            resolver
+        );
+        let resolver: HttpResolver = "http://localhost".parse()?;
+        assert_eq!((80, "/"), (resolver.port, resolver.path.as_str()));
+        let resolver: HttpResolver = "https://dns.google/dns-query".parse()?;
+        assert_eq!((443, "dns.google"), (resolver.port, resolver.host.as_str()));
+        assert!(resolver.tls.is_some());
+        assert!("dns.google".parse::<HttpResolver>().is_err());
+        assert!("ftp://dns.google".parse::<HttpResolver>().is_err());
+        Ok(())
+    }
+
+    // Answers the POST with the reply of the local data, returning the head of the request
+    fn answer(mut stream: impl Read + Write) -> Result<String> {
+        let mut reader = BufReader::new(&mut stream);
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse()?;
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let mut query = vec![0; length];
+        reader.read_exact(&mut query)?;
+        let reply = parse_and_reply(&query)?;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\n\r\n",
+            reply.len()
+        )?;
+        stream.write_all(&reply)?;
+        stream.flush()?;
+        Ok(head)
+    }
+
+    // Answers a single POST over plain HTTP
+    fn mock_server() -> Result<(String, thread::JoinHandle<Result<String>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/dns-query", listener.local_addr()?);
+        let handle = thread::spawn(move || -> Result<String> {
+            let (stream, _) = listener.accept()?;
+            answer(stream)
+        });
+        Ok((url, handle))
+    }
+
+    #[test]
+    fn test_forward() -> Result<()> {
+        let (url, handle) = mock_server()?;
+        let resolver: HttpResolver = url.parse()?;
+        let query = build_query("codecrafters.io", Type::A, 42)?;
+        let destination = ([127, 0, 0, 1], 5353).into();
+        let mut forwarder = create_forwarder(&query, destination)?;
+        forwarder.forward_with(|query| resolver.exchange(query))?;
+
+        let head = handle.join().expect("the server panicked")?;
+        assert!(head.starts_with("POST /dns-query HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/dns-message\r\n"));
+        let reply = DNSMessage::from_bytes(&forwarder.build_reply()?)?;
+        assert_eq!(42, reply.id());
+        assert_eq!(1, reply.answers());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_https() -> Result<()> {
+        let (config, roots) = self_signed("127.0.0.1")?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!(
+            "https://127.0.0.1:{}/dns-query",
+            listener.local_addr()?.port()
+        );
+        // Closes without close_notify, like some servers do after the response
+        let handle = thread::spawn(move || -> Result<String> {
+            let (stream, _) = listener.accept()?;
+            answer(StreamOwned::new(ServerConnection::new(config)?, stream))
+        });
+        let resolver = HttpResolver {
+            tls: Some(tls::client_config(roots)),
+            ..url.parse()?
+        };
+        let query = build_query("codecrafters.io", Type::A, 43)?;
+        let destination = ([127, 0, 0, 1], 5353).into();
+        let mut forwarder = create_forwarder(&query, destination)?;
+        forwarder.forward_with(|query| resolver.exchange(query))?;
+
+        let head = handle.join().expect("the server panicked")?;
+        assert!(head.contains("Host: 127.0.0.1\r\n"));
+        let reply = DNSMessage::from_bytes(&forwarder.build_reply()?)?;
+        assert_eq!(43, reply.id());
+        assert_eq!(1, reply.answers());
+
+        // The certificate is verified with the default roots
+        let (config, _) = self_signed("127.0.0.1")?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!(
+            "https://127.0.0.1:{}/dns-query",
+            listener.local_addr()?.port()
+        );
+        let handle = thread::spawn(move || -> Result<String> {
+            let (stream, _) = listener.accept()?;
+            answer(StreamOwned::new(ServerConnection::new(config)?, stream))
+        });
+        let resolver: HttpResolver = url.parse()?;
+        assert!(resolver.exchange(&query).is_err());
+        assert!(handle.join().expect("the server panicked").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        assert!(parse_response(response).is_err());
+        let response = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nabc";
+        assert_eq!(b"ab".to_vec(), parse_response(response).unwrap());
+    }
+}
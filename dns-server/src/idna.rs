@@ -0,0 +1,206 @@
+// Internationalized domain names: the Unicode labels are written in the messages as
+// A-labels, "xn--" and the punycode (RFC 3492) of the label.
+
+const ACE_PREFIX: &str = "xn--";
+
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+// The name with its Unicode labels as A-labels, like münchen.de as xn--mnchen-3ya.de
+pub(crate) fn to_ascii(name: &str) -> String {
+    name.split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                return label.to_string();
+            }
+            match encode(&label.to_lowercase()) {
+                Some(encoded) => format!("{ACE_PREFIX}{encoded}"),
+                // Too long to be a valid label anyway
+                None => label.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// The name with its A-labels decoded to Unicode, to display it. The invalid ones are kept as
+// they are, like the ones that aren't encoded back to the same label.
+pub(crate) fn to_unicode(name: &str) -> String {
+    name.split('.')
+        .map(|label| {
+            let punycode = label.get(ACE_PREFIX.len()..).unwrap_or_default();
+            let decoded = is_a_label(label)
+                .then(|| decode(&punycode.to_ascii_lowercase()))
+                .flatten()
+                .filter(|decoded| {
+                    !decoded.is_ascii()
+                        && encode(decoded)
+                            .is_some_and(|encoded| encoded.eq_ignore_ascii_case(punycode))
+                });
+            decoded.unwrap_or_else(|| label.to_string())
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// If the label starts with the ACE prefix, in any case.
+pub(crate) fn is_a_label(label: &str) -> bool {
+    label
+        .get(..ACE_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(ACE_PREFIX))
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        TMIN
+    } else if k >= bias + TMAX {
+        TMAX
+    } else {
+        k - bias
+    }
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+fn digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    }
+}
+
+fn digit_value(c: char) -> Option<u32> {
+    match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+        _ => None,
+    }
+}
+
+// Punycode of the label, None if it overflows.
+fn encode(input: &str) -> Option<String> {
+    let chars: Vec<u32> = input.chars().map(|c| c as u32).collect();
+    let mut output: String = input.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    let mut handled = basic;
+    if basic > 0 {
+        output.push('-');
+    }
+    let (mut n, mut delta, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    while (handled as usize) < chars.len() {
+        // The next code point to insert
+        let m = chars.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &chars {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c != n {
+                continue;
+            }
+            let mut q = delta;
+            let mut k = BASE;
+            loop {
+                let t = threshold(k, bias);
+                if q < t {
+                    break;
+                }
+                output.push(digit(t + (q - t) % (BASE - t)));
+                q = (q - t) / (BASE - t);
+                k += BASE;
+            }
+            output.push(digit(q));
+            bias = adapt(delta, handled + 1, handled == basic);
+            delta = 0;
+            handled += 1;
+        }
+        delta = delta.checked_add(1)?;
+        n = n.checked_add(1)?;
+    }
+    Some(output)
+}
+
+// The label of the punycode, None if it's not valid punycode.
+fn decode(input: &str) -> Option<String> {
+    let (basic, extended) = match input.rfind('-') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+    let (mut n, mut i, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut digits = extended.chars();
+    while !digits.as_str().is_empty() {
+        let old_i = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+        loop {
+            let d = digit_value(digits.next()?)?;
+            i = i.checked_add(d.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if d < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punycode() {
+        for (label, punycode) in [
+            ("münchen", "mnchen-3ya"),
+            ("bücher", "bcher-kva"),
+            ("ü", "tda"),
+        ] {
+            assert_eq!(Some(punycode.to_string()), encode(label));
+            assert_eq!(Some(label.to_string()), decode(punycode));
+        }
+        // Invalid digits
+        assert_eq!(None, decode("mnchen-3y!"));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!("xn--mnchen-3ya.de", to_ascii("münchen.de"));
+        assert_eq!("xn--mnchen-3ya.de", to_ascii("MÜNCHEN.de"));
+        assert_eq!("codecrafters.io", to_ascii("codecrafters.io"));
+
+        assert_eq!("münchen.de", to_unicode("xn--mnchen-3ya.de"));
+        assert_eq!("münchen.de", to_unicode("XN--MNCHEN-3YA.de"));
+        assert_eq!("codecrafters.io", to_unicode("codecrafters.io"));
+        // Not valid punycode, kept as it is
+        assert_eq!("xn--!.io", to_unicode("xn--!.io"));
+        // Decoded to plain ASCII, encoding it again isn't the same label
+        assert_eq!("xn--abc-.io", to_unicode("xn--abc-.io"));
+    }
+}
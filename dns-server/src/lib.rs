@@ -1,16 +1,54 @@
-use std::net::SocketAddr;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use message::DNSMessage;
+use anyhow::{anyhow, Result};
 
+mod blocklist;
+mod cache;
+mod control;
+mod domains;
+mod dot_resolver;
+mod edns;
+mod framing;
+mod http_resolver;
+mod idna;
 mod message;
+mod query_log;
+mod rate_limit;
+mod tcp;
+mod tls;
+mod zones;
+
+pub use blocklist::Blocklist;
+pub use cache::Cache;
+pub use control::spawn_control;
+pub use dot_resolver::DotResolver;
+pub use framing::{exchange_framed, frame, read_framed};
+pub use http_resolver::HttpResolver;
+pub use message::{
+    DNSMessage, Header, MessageType, OpCode, ParseOptions, Question, ResourceRecord, ResponseCode,
+    Section, Type,
+};
+pub use query_log::{QueryLog, Served};
+pub use rate_limit::RateLimiter;
+pub use tcp::serve_tcp;
+pub use zones::Zones;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Forwarder {
     pub destination: SocketAddr,
     message: DNSMessage,
+    // Prefix of the client's address sent upstream in the EDNS client subnet option
+    client_subnet: Option<u8>,
+    // All the questions are answered, or the resolver had nothing to answer
+    complete: bool,
+    // Replies with only the answer section
+    minimal: bool,
 }
 
+// The size of the buffer for the replies of the resolver, announced in the OPT record
+const UDP_PAYLOAD_SIZE: u16 = 512;
+
 impl Forwarder {
     // Returns the bytes representing the DNS Message with the next question
     // If it is the last question to send, the forwarder marks is_complete as true
@@ -20,41 +58,230 @@ impl Forwarder {
         // it forwards one question at a time.
         // This is a codecrafters requirement.
         message.header.qd_count = 1;
+        // The server asks for recursion whatever the client asked, the reply keeps its RD bit
+        message.header.recursion_desired = true;
         if let Some(q) = &self.message.question {
             let question = q
                 .get(self.message.answers())
                 .expect("invalid questions lenght");
             message.question = Some(vec![question.clone()]);
         }
-        Ok(message.to_bytes())
+        message.additional = self.opt().map(|opt| vec![opt]);
+        message.to_bytes()
+    }
+
+    // Sends the client subnet of the given prefix length (like 24) upstream, unless the client
+    // already sent one.
+    pub fn with_client_subnet(mut self, prefix: Option<u8>) -> Self {
+        self.client_subnet = prefix;
+        self
+    }
+
+    // Drops the authority and additional records of the reply, to keep it small.
+    pub fn with_minimal(mut self, minimal: bool) -> Self {
+        self.minimal = minimal;
+        self
+    }
+
+    // The OPT record of the client passed through, with the client subnet added if enabled.
+    fn opt(&self) -> Option<ResourceRecord> {
+        let client_opt = self
+            .message
+            .additional
+            .iter()
+            .flatten()
+            .find(|rr| rr.atype == Type::Opt)
+            .cloned();
+        let mut opt = match (client_opt, self.client_subnet) {
+            (Some(opt), _) => opt,
+            (None, Some(_)) => ResourceRecord::opt(UDP_PAYLOAD_SIZE, vec![]),
+            (None, None) => return None,
+        };
+        // The reply has to fit in the buffer
+        opt.set_payload_size(UDP_PAYLOAD_SIZE);
+        let has_subnet = opt
+            .options()
+            .iter()
+            .any(|(code, _)| *code == edns::CLIENT_SUBNET);
+        if let (Some(prefix), false) = (self.client_subnet, has_subnet) {
+            let subnet = edns::client_subnet(self.destination.ip(), prefix);
+            opt.add_option(edns::CLIENT_SUBNET, &subnet);
+        }
+        Some(opt)
+    }
+
+    // Returns if the message is the reply to the question forwarded, with its id.
+    pub fn is_reply(&self, buf: &[u8]) -> bool {
+        buf.get(0..2) == Some(&self.message.id().to_be_bytes())
     }
 
     // Add the received answer from the resolver to the current response
     // If the answers now match the questions from the request, the forwarder is complete and returns true
     // Otherwise returns false indicating the need to keep forwarding
+    // A failure of the resolver is an error, to reply with Forwarder::fail
+    // A reply without answers (like NXDOMAIN or NODATA) passes its RCODE and authority records
+    // (the SOA) to the client.
     pub fn add_answer(&mut self, buf: &[u8]) -> Result<bool> {
         let reply = DNSMessage::from_bytes(buf)?;
-        match reply.answer {
+        if reply.answer.is_none() {
+            self.message.header.response_code = reply.header.response_code;
+            self.message.authority = reply.authority;
+        }
+        if let code @ (ResponseCode::ServerFailure | ResponseCode::Refused) =
+            reply.header.response_code
+        {
+            return Err(anyhow!("the resolver failed: {code:?}"));
+        }
+        self.complete = match reply.answer {
             Some(mut ans) => {
                 let answer = ans.remove(0);
                 self.message.add_answer(answer);
-                Ok(self.message.questions() == self.message.answers())
+                self.message.questions() == self.message.answers()
             }
             // Just finish the forwarder. (no questions no answers)
-            _ => Ok(true),
+            _ => true,
+        };
+        Ok(self.complete)
+    }
+
+    // The response assembled so far, with an answer for each question forwarded.
+    pub fn response(&self) -> &DNSMessage {
+        &self.message
+    }
+
+    // Returns if there is nothing left to forward, so the reply can be built.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    // Forwards all the questions through a transport that waits for the reply, like HTTP,
+    // until the forwarder is complete.
+    pub fn forward_with(
+        &mut self,
+        mut exchange: impl FnMut(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<()> {
+        loop {
+            let reply = exchange(&self.forward()?)?;
+            if self.add_answer(&reply)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Caches the answers received from the resolver
+    pub fn cache_answers(&self, cache: &mut Cache, now: Instant) {
+        for answer in self.message.answer.iter().flatten() {
+            cache.insert(answer.clone(), now);
         }
     }
 
-    pub fn build_reply(&mut self) -> Vec<u8> {
+    // The reply when the resolver fails or doesn't answer: the cached answers even if they
+    // expired (serve-stale), or a ServerFailure if they are not cached.
+    pub fn fail(&self, cache: &mut Cache, now: Instant) -> Result<Vec<u8>> {
+        let request = self.message.without_answers();
+        match cache.reply_message(request.clone(), now, true) {
+            Some(reply) => reply.to_bytes(),
+            None => request.build_error_reply(self.error_code()).to_bytes(),
+        }
+    }
+
+    // The error of the resolver to reply with, ServerFailure if it didn't send one.
+    fn error_code(&self) -> ResponseCode {
+        match self.message.header.response_code {
+            ResponseCode::NoError => ResponseCode::ServerFailure,
+            code => code,
+        }
+    }
+
+    pub fn build_reply(&mut self) -> Result<Vec<u8>> {
+        let code = self.message.header.response_code;
         self.message.header = self.message.header.build_reply();
+        // The RCODE of the resolver, like NameError, is kept
+        if code != ResponseCode::NoError {
+            self.message.header.response_code = code;
+        }
+        if self.minimal {
+            self.message.authority = None;
+            self.message.additional = None;
+        }
         self.message.to_bytes()
     }
 }
 
 // Parses the buffer as a DNS message, and the builds the reply with the local data.
 pub fn parse_and_reply(buf: &[u8]) -> Result<Vec<u8>> {
-    let message = DNSMessage::from_bytes(buf)?;
-    Ok(message.build_reply().to_bytes())
+    parse_and_reply_with(buf, &ParseOptions::default())
+}
+
+// Like parse_and_reply with the parsing options.
+// A malformed message, or one with names that can't be written back, is answered with a
+// FormatError. It only fails if the header can't be read.
+pub fn parse_and_reply_with(buf: &[u8], options: &ParseOptions) -> Result<Vec<u8>> {
+    let reply = DNSMessage::from_bytes_with(buf, options)
+        .and_then(|message| message.build_reply().to_bytes());
+    match reply {
+        Ok(reply) => Ok(reply),
+        Err(e) => match DNSMessage::format_error(buf) {
+            Some(reply) => reply.to_bytes(),
+            None => Err(e),
+        },
+    }
+}
+
+// Builds the bytes of a recursive query for the name, to send it to a DNS server.
+pub fn build_query(name: &str, qtype: Type, id: u16) -> Result<Vec<u8>> {
+    DNSMessage::query(name, qtype, id).to_bytes()
+}
+
+// How long resolve waits for the reply of the server
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Sends a query for the name to the server, and returns its parsed reply.
+pub fn resolve(name: &str, qtype: Type, server: SocketAddr) -> Result<DNSMessage> {
+    let query = build_query(name, qtype, rand::random())?;
+    DNSMessage::from_bytes(&exchange_udp(&query, server)?)
+}
+
+// Sends the query to the server over UDP, and returns the reply with the same id.
+// Datagrams from somewhere else or with another id (like a late reply) are skipped
+// until the timeout.
+pub fn exchange_udp(query: &[u8], server: SocketAddr) -> Result<Vec<u8>> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0; 16], 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.send_to(query, server)?;
+
+    let id = |message: &[u8]| {
+        message
+            .get(0..2)
+            .map(|id| u16::from_be_bytes([id[0], id[1]]))
+    };
+    let query_id = id(query).ok_or(anyhow!("invalid message: missing the id"))?;
+    let deadline = Instant::now() + RESOLVE_TIMEOUT;
+    let mut buf = [0; UDP_PAYLOAD_SIZE as usize];
+    loop {
+        // The skipped datagrams don't extend the timeout
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(anyhow!("no reply from {server}"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (size, source) = socket.recv_from(&mut buf)?;
+        if source == server && id(&buf[..size]) == Some(query_id) {
+            return Ok(buf[..size].to_vec());
+        }
+    }
+}
+
+// The Refused reply for the query, None if it can't be parsed.
+pub fn refuse(buf: &[u8]) -> Option<Vec<u8>> {
+    let message = DNSMessage::from_bytes(buf).ok()?;
+    message
+        .build_error_reply(ResponseCode::Refused)
+        .to_bytes()
+        .ok()
 }
 
 pub fn create_forwarder(buf: &[u8], destination: SocketAddr) -> Result<Forwarder> {
@@ -62,5 +289,282 @@ pub fn create_forwarder(buf: &[u8], destination: SocketAddr) -> Result<Forwarder
     Ok(Forwarder {
         destination,
         message: request,
+        client_subnet: None,
+        complete: false,
+        minimal: false,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_query() -> Result<()> {
+        let query = build_query("codecrafters.io", Type::MX, 1234)?;
+        // Recursion desired
+        assert_eq!(0b0000_0001, query[2]);
+
+        let message = DNSMessage::from_bytes(&query)?;
+        assert_eq!(1, message.questions());
+        assert_eq!(0, message.answers());
+        assert_eq!(
+            DNSMessage::query("codecrafters.io", Type::MX, 1234),
+            message
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        // Serves a single query with the local data
+        let server = UdpSocket::bind("127.0.0.1:0")?;
+        let address = server.local_addr()?;
+        let handle = std::thread::spawn(move || -> Result<()> {
+            let mut buf = [0; 512];
+            let (_, source) = server.recv_from(&mut buf)?;
+            server.send_to(&parse_and_reply(&buf)?, source)?;
+            Ok(())
+        });
+
+        let reply = resolve("codecrafters.io", Type::A, address)?;
+        handle.join().expect("the server panicked")?;
+        assert_eq!(1, reply.questions());
+        assert_eq!(1, reply.answers());
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_reserved_bits() -> Result<()> {
+        let mut query = build_query("codecrafters.io", Type::A, 1234)?;
+        query[3] |= 0b0001_0000;
+
+        let strict = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(DNSMessage::from_bytes_with(&query, &strict).is_err());
+        let reply = parse_and_reply_with(&query, &strict)?;
+        assert_eq!(12, reply.len());
+        assert_eq!(&1234u16.to_be_bytes(), &reply[0..2]);
+        // A response with RCODE FormatError
+        assert_eq!(0b1000_0000, reply[2] & 0b1000_0000);
+        assert_eq!(1, reply[3] & 0b0000_1111);
+
+        // Accepted by default
+        let reply = DNSMessage::from_bytes(&parse_and_reply(&query)?)?;
+        assert_eq!(1, reply.answers());
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_question() -> Result<()> {
+        let query = build_query("codecrafters.io", Type::A, 4321)?;
+        // The name is cut in the middle
+        let reply = parse_and_reply(&query[..17])?;
+        assert_eq!(12, reply.len());
+        assert_eq!(&4321u16.to_be_bytes(), &reply[0..2]);
+        assert_eq!(1, reply[3] & 0b0000_1111);
+
+        // Without a complete header there is nothing to reply to
+        assert!(parse_and_reply(&query[..8]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reply_long_label() -> Result<()> {
+        // A label of 64 octets can be parsed, but it can't be written back
+        let mut query = build_query("codecrafters.io", Type::A, 3)?;
+        query.splice(12..25, [[64].as_slice(), &[b'a'; 64]].concat());
+        let reply = DNSMessage::from_bytes(&parse_and_reply(&query)?)?;
+        assert_eq!(ResponseCode::FormatError, reply.header.response_code);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reply_unknown_op_code() -> Result<()> {
+        let mut query = build_query("codecrafters.io", Type::A, 6)?;
+        query[2] |= 6 << 3;
+        let reply = DNSMessage::from_bytes(&parse_and_reply(&query)?)?;
+        assert_eq!(ResponseCode::NotImplemented, reply.header.response_code);
+        assert_eq!(1, reply.questions());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_recursion_desired() -> Result<()> {
+        let mut query = build_query("codecrafters.io", Type::A, 11)?;
+        query[2] &= !0b0000_0001;
+        let destination = SocketAddr::from(([127, 0, 0, 1], 5353));
+        let mut forwarder = create_forwarder(&query, destination)?;
+        let forwarded = forwarder.forward()?;
+        assert_eq!(0b0000_0001, forwarded[2] & 0b0000_0001);
+
+        let answer = DNSMessage::from_bytes(&forwarded)?
+            .build_reply()
+            .to_bytes()?;
+        assert!(forwarder.add_answer(&answer)?);
+        let reply = forwarder.build_reply()?;
+        assert_eq!(0, reply[2] & 0b0000_0001);
+
+        assert!(forwarder.is_reply(&answer));
+        assert!(!forwarder.is_reply(&build_query("codecrafters.io", Type::A, 12)?));
+        assert!(!forwarder.is_reply(&[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_client_subnet() -> Result<()> {
+        let query = build_query("codecrafters.io", Type::A, 12)?;
+        let destination = SocketAddr::from(([192, 168, 77, 9], 5353));
+        let mut forwarder = create_forwarder(&query, destination)?.with_client_subnet(Some(24));
+        let forwarded = forwarder.forward()?;
+        // A single additional record, the OPT with the client subnet option
+        assert_eq!(1, forwarded[11]);
+        let ecs = [0, 8, 0, 7, 0, 1, 24, 0, 192, 168, 77];
+        let opt = [&[0, 0, 41, 0x02, 0x00, 0, 0, 0, 0, 0, 11], ecs.as_slice()].concat();
+        assert!(forwarded.ends_with(&opt));
+
+        // Without it the query is forwarded as it came
+        let mut forwarder = create_forwarder(&query, destination)?;
+        assert_eq!(query, forwarder.forward()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_two_questions() -> Result<()> {
+        let mut request = DNSMessage::query("codecrafters.io", Type::A, 21);
+        let another = DNSMessage::query("another.codecrafters.io", Type::A, 21);
+        if let (Some(questions), Some(more)) = (&mut request.question, another.question) {
+            questions.extend(more);
+        }
+        let destination = SocketAddr::from(([127, 0, 0, 1], 5353));
+        let mut forwarder = create_forwarder(&request.to_bytes()?, destination)?;
+        assert!(!forwarder.is_complete());
+
+        // The fake resolver answers each question with the local data
+        for complete in [false, true] {
+            let answer = parse_and_reply(&forwarder.forward()?)?;
+            assert_eq!(complete, forwarder.add_answer(&answer)?);
+            assert_eq!(complete, forwarder.is_complete());
+        }
+        let response = forwarder.response();
+        assert_eq!(2, response.answers());
+        let names: Vec<&str> = response
+            .answer
+            .iter()
+            .flatten()
+            .map(|rr| rr.name.as_str())
+            .collect();
+        assert_eq!(vec!["codecrafters.io", "another.codecrafters.io"], names);
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_empty_answer() -> Result<()> {
+        let query = build_query("missing.codecrafters.io", Type::A, 31)?;
+        let destination = SocketAddr::from(([127, 0, 0, 1], 5353));
+        let mut cache = Cache::default();
+
+        // The fake resolver fails without answers
+        let mut forwarder = create_forwarder(&query, destination)?;
+        let failure = DNSMessage::from_bytes(&query)?
+            .build_error_reply(ResponseCode::Refused)
+            .to_bytes()?;
+        assert!(forwarder.add_answer(&failure).is_err());
+        let reply = DNSMessage::from_bytes(&forwarder.fail(&mut cache, Instant::now())?)?;
+        assert_eq!(ResponseCode::Refused, reply.header.response_code);
+
+        let mut forwarder = create_forwarder(&query, destination)?;
+        let failure = DNSMessage::from_bytes(&query)?
+            .build_error_reply(ResponseCode::ServerFailure)
+            .to_bytes()?;
+        assert!(forwarder.add_answer(&failure).is_err());
+        let reply = DNSMessage::from_bytes(&forwarder.fail(&mut cache, Instant::now())?)?;
+        assert_eq!(ResponseCode::ServerFailure, reply.header.response_code);
+        assert_eq!(0, reply.answers());
+
+        // A name that doesn't exist, with a record in the authority section
+        let mut forwarder = create_forwarder(&query, destination)?;
+        let mut missing =
+            DNSMessage::from_bytes(&query)?.build_error_reply(ResponseCode::NameError);
+        missing.authority =
+            ResourceRecord::answer_by_type(Type::A, "codecrafters.io").map(|rr| vec![rr]);
+        assert!(forwarder.add_answer(&missing.to_bytes()?)?);
+        assert!(forwarder.is_complete());
+        let reply = DNSMessage::from_bytes(&forwarder.build_reply()?)?;
+        assert_eq!(ResponseCode::NameError, reply.header.response_code);
+        assert_eq!(0, reply.answers());
+        assert_eq!(missing.authority, reply.authority);
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_minimal() -> Result<()> {
+        // The client sends an OPT record, kept in the additional section of the reply
+        let mut query = DNSMessage::query("missing.codecrafters.io", Type::A, 32);
+        query.additional = Some(vec![ResourceRecord::opt(1232, vec![])]);
+        let query = query.to_bytes()?;
+        let mut missing =
+            DNSMessage::from_bytes(&query)?.build_error_reply(ResponseCode::NameError);
+        missing.authority =
+            ResourceRecord::answer_by_type(Type::A, "codecrafters.io").map(|rr| vec![rr]);
+        let destination = SocketAddr::from(([127, 0, 0, 1], 5353));
+
+        let mut forwarder = create_forwarder(&query, destination)?;
+        forwarder.add_answer(&missing.to_bytes()?)?;
+        let reply = DNSMessage::from_bytes(&forwarder.build_reply()?)?;
+        assert!(reply.authority.is_some());
+        assert!(reply.additional.is_some());
+
+        let mut forwarder = create_forwarder(&query, destination)?.with_minimal(true);
+        forwarder.add_answer(&missing.to_bytes()?)?;
+        let reply = DNSMessage::from_bytes(&forwarder.build_reply()?)?;
+        assert_eq!(ResponseCode::NameError, reply.header.response_code);
+        assert_eq!(None, reply.authority);
+        assert_eq!(None, reply.additional);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse() -> Result<()> {
+        let query = build_query("codecrafters.io", Type::A, 99)?;
+        let reply = DNSMessage::from_bytes(&refuse(&query).expect("not refused"))?;
+        assert_eq!(ResponseCode::Refused, reply.header.response_code);
+        assert_eq!(1, reply.questions());
+        assert_eq!(0, reply.answers());
+        assert_eq!(None, refuse(&query[..5]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_stale() -> Result<()> {
+        let query = build_query("codecrafters.io", Type::A, 77)?;
+        let destination = SocketAddr::from(([127, 0, 0, 1], 5353));
+        let mut forwarder = create_forwarder(&query, destination)?;
+        forwarder.forward()?;
+        // The fake resolver fails
+        let failure = DNSMessage::from_bytes(&query)?
+            .build_error_reply(ResponseCode::ServerFailure)
+            .to_bytes()?;
+        assert!(forwarder.add_answer(&failure).is_err());
+
+        let start = Instant::now();
+        let expired = start + Duration::from_secs(120);
+        // Nothing cached
+        let mut cache = Cache::default();
+        let reply = DNSMessage::from_bytes(&forwarder.fail(&mut cache, expired)?)?;
+        assert_eq!(ResponseCode::ServerFailure, reply.header.response_code);
+        assert_eq!(0, reply.answers());
+
+        let mut cached = create_forwarder(&query, destination)?;
+        cached.add_answer(&parse_and_reply(&query)?)?;
+        cached.cache_answers(&mut cache, start);
+        let reply = DNSMessage::from_bytes(&forwarder.fail(&mut cache, expired)?)?;
+        assert_eq!(77, reply.id());
+        assert_eq!(ResponseCode::NoError, reply.header.response_code);
+        assert_eq!(1, reply.answers());
+        assert_eq!(30, reply.answer.unwrap()[0].ttl);
+        Ok(())
+    }
+}
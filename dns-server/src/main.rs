@@ -1,61 +1,314 @@
 use anyhow::{anyhow, Result};
-use dns_starter_rust::{create_forwarder, parse_and_reply, Forwarder};
-use std::net::SocketAddr;
+use dns_starter_rust::{
+    create_forwarder, exchange_udp, parse_and_reply_with, refuse, serve_tcp, spawn_control,
+    Blocklist, Cache, DNSMessage, DotResolver, Forwarder, HttpResolver, ParseOptions, QueryLog,
+    RateLimiter, Served, Zones,
+};
+use std::ffi::c_int;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::{env, net::UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, net::UdpSocket, thread};
+
+const USAGE: &str = "usage: [--resolver <address> | --resolver-http <url> \
+    | --resolver-dot <address>#<name>] [--blocklist <file>] [--client-subnet <prefix>] \
+    [--control <socket>] [--query-log <file>] [--zone <name>]... [--rate-limit <queries>] \
+    [--tcp-only] [--minimal] [--verbose] [--strict]";
+
+// Served both over UDP and TCP
+const ADDRESS: &str = "127.0.0.1:2053";
+
+// How long to wait for the resolver before replying from the stale cache
+const RESOLVER_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How often the server checks if it has to shut down while there are no queries
+const SHUTDOWN_POLL: Duration = Duration::from_millis(500);
+
+// Set by SIGINT and SIGTERM, the server stops and flushes the query log
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+
+// Options of the server given as arguments.
+#[derive(Debug, PartialEq, Clone, Default)]
+struct Config {
+    resolver: Option<SocketAddr>,
+    // Forwards the queries over HTTPS instead (DNS-over-HTTPS), or over plain HTTP to a proxy
+    resolver_http: Option<HttpResolver>,
+    // Forwards the queries over TLS instead (DNS-over-TLS)
+    resolver_dot: Option<DotResolver>,
+    // Prints the messages received
+    verbose: bool,
+    // Replies FormatError to the messages with a non-zero Z
+    strict: bool,
+    // File with the names answered with NXDOMAIN
+    blocklist: Option<PathBuf>,
+    // Queries per second allowed for each client, the rest are refused
+    rate_limit: Option<usize>,
+    // Unix socket to list and flush the cache
+    control: Option<PathBuf>,
+    // Prefix length of the client's address sent to the resolver (EDNS client subnet)
+    client_subnet: Option<u8>,
+    // File where a line is appended for each query answered
+    query_log: Option<PathBuf>,
+    // Zones the server is authoritative for without a resolver, the rest of names are refused
+    zones: Vec<String>,
+    // Only serves over TCP, without binding the UDP socket
+    tcp_only: bool,
+    // Only the answer section of the forwarded replies, without authority and additional records
+    minimal: bool,
+}
+
+impl Config {
+    fn binds_udp(&self) -> bool {
+        !self.tcp_only
+    }
+
+    // The resolvers over a connection are waited for before reading the next query
+    fn waits_for_resolver(&self) -> bool {
+        self.resolver_http.is_some() || self.resolver_dot.is_some()
+    }
+}
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let mut resolver = None;
-    if args.len() > 1 {
-        if args.len() != 3 {
-            return Err(anyhow!("invalid number of arguments, the only valid use of arguments is --resolver <address>"));
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = parse_args(&args)?;
+    start_server(&config)
+}
+
+fn parse_args(args: &[String]) -> Result<Config> {
+    let mut config = Config::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--resolver" => {
+                let address = args
+                    .next()
+                    .ok_or(anyhow!("missing address for --resolver, {USAGE}"))?;
+                config.resolver = Some(SocketAddr::from_str(address)?);
+            }
+            "--resolver-http" => {
+                let url = args
+                    .next()
+                    .ok_or(anyhow!("missing URL for --resolver-http, {USAGE}"))?;
+                config.resolver_http = Some(url.parse()?);
+            }
+            "--resolver-dot" => {
+                let resolver = args
+                    .next()
+                    .ok_or(anyhow!("missing address for --resolver-dot, {USAGE}"))?;
+                config.resolver_dot = Some(resolver.parse()?);
+            }
+            "--blocklist" => {
+                let file = args
+                    .next()
+                    .ok_or(anyhow!("missing file for --blocklist, {USAGE}"))?;
+                config.blocklist = Some(PathBuf::from(file));
+            }
+            "--rate-limit" => {
+                let limit = args
+                    .next()
+                    .ok_or(anyhow!("missing number for --rate-limit, {USAGE}"))?;
+                config.rate_limit = Some(limit.parse()?);
+            }
+            "--control" => {
+                let socket = args
+                    .next()
+                    .ok_or(anyhow!("missing socket for --control, {USAGE}"))?;
+                config.control = Some(PathBuf::from(socket));
+            }
+            "--client-subnet" => {
+                let prefix = args.next().ok_or(anyhow!(
+                    "missing prefix length for --client-subnet, {USAGE}"
+                ))?;
+                config.client_subnet = Some(prefix.parse()?);
+            }
+            "--query-log" => {
+                let file = args
+                    .next()
+                    .ok_or(anyhow!("missing file for --query-log, {USAGE}"))?;
+                config.query_log = Some(PathBuf::from(file));
+            }
+            "--zone" => {
+                let zone = args
+                    .next()
+                    .ok_or(anyhow!("missing name for --zone, {USAGE}"))?;
+                config.zones.push(zone.to_string());
+            }
+            "--tcp-only" => config.tcp_only = true,
+            "--minimal" => config.minimal = true,
+            "--verbose" => config.verbose = true,
+            "--strict" => config.strict = true,
+            _ => return Err(anyhow!("invalid argument {arg}, {USAGE}")),
         }
-        if &args[1] != "--resolver" {
-            return Err(anyhow!(
-                "invalid argument, the only valid use of arguments is --resolver <address>"
-            ));
+    }
+    let resolvers = [
+        config.resolver.is_some(),
+        config.resolver_http.is_some(),
+        config.resolver_dot.is_some(),
+    ];
+    if resolvers.into_iter().filter(|&set| set).count() > 1 {
+        return Err(anyhow!("only one resolver can be used, {USAGE}"));
+    }
+    Ok(config)
+}
+
+fn start_server(config: &Config) -> Result<()> {
+    let server = Arc::new(Server::new(config)?);
+    handle_shutdown_signals();
+    let listener = TcpListener::bind(ADDRESS)?;
+    let tcp_server = server.clone();
+    // The connections are dropped when the server exits
+    thread::spawn(move || serve_tcp_with(listener, tcp_server));
+    if config.binds_udp() {
+        serve_udp(&server, UdpSocket::bind(ADDRESS)?, &SHUTDOWN)?;
+    } else {
+        while !SHUTDOWN.load(Ordering::Relaxed) {
+            thread::sleep(SHUTDOWN_POLL);
         }
-        resolver = Some(SocketAddr::from_str(&args[2])?);
     }
+    server.flush_log();
+    Ok(())
+}
 
-    start_server(resolver)
+extern "C" fn request_shutdown(_signal: c_int) {
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+fn handle_shutdown_signals() {
+    extern "C" {
+        fn signal(signal: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+    // Safety: the handler only stores to an atomic, that is async-signal-safe
+    unsafe {
+        signal(SIGINT, request_shutdown);
+        signal(SIGTERM, request_shutdown);
+    }
 }
 
-fn start_server(resolver: Option<SocketAddr>) -> Result<()> {
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053")?;
+// Serves the queries received by the socket until the shutdown flag is set.
+fn serve_udp(server: &Server, udp_socket: UdpSocket, shutdown: &AtomicBool) -> Result<()> {
+    let config = &server.config;
     let mut buf = [0; 512];
     let mut forwarder: Option<Forwarder> = None;
-    loop {
-        match (udp_socket.recv_from(&mut buf), resolver) {
-            (Ok((size, source)), Some(addr_resolver)) => {
-                println!("Received {} bytes from {} with resolver", size, source);
-                match &mut forwarder {
-                    Some(fw) => match fw.add_answer(&buf)? {
-                        true => {
-                            let reply = fw.build_reply();
-                            udp_socket.send_to(&reply, fw.destination)?;
-                            forwarder = None
-                        }
-                        false => {
+    let mut rate_limiter = config.rate_limit.map(RateLimiter::per_second);
+    let cache = &server.cache;
+    // The flag is checked at least this often, even without queries
+    let timeout = match config.resolver {
+        Some(_) => RESOLVER_TIMEOUT,
+        None => SHUTDOWN_POLL,
+    };
+    udp_socket.set_read_timeout(Some(timeout))?;
+    while !shutdown.load(Ordering::Relaxed) {
+        let received = udp_socket.recv_from(&mut buf);
+        if let (Ok((size, source)), true) = (&received, config.verbose) {
+            println!("Received {} bytes from {}", size, source);
+            match DNSMessage::from_bytes(&buf[..*size]) {
+                Ok(message) => println!("{:#?}", message),
+                Err(e) => println!("Invalid message: {}", e),
+            }
+        }
+        // Only the queries of the clients, not the answers of the resolver
+        let from_client = matches!(&received, Ok((_, source)) if Some(*source) != config.resolver);
+        if let (Ok((size, source)), true) = (&received, from_client) {
+            let query = &buf[..*size];
+            let allowed = match &mut rate_limiter {
+                Some(limiter) => limiter.allow(source.ip(), Instant::now()),
+                None => true,
+            };
+            let reply = match allowed {
+                false => refuse(query).map(|reply| (reply, Served::Refused)),
+                true => server.sinkhole(query).map(|reply| (reply, Served::Blocked)),
+            };
+            if let Some((reply, served)) = reply {
+                send_reply(&udp_socket, server, Ok(reply), *source, served)?;
+                continue;
+            }
+            // Over the limit, without anything to reply to
+            if !allowed {
+                continue;
+            }
+        }
+        match (received, config.resolver, config.waits_for_resolver()) {
+            (Ok((size, source)), _, true) => {
+                let (reply, served) = server.reply(&buf[..size], source);
+                send_reply(&udp_socket, server, reply, source, served)?;
+            }
+            (Ok((size, source)), Some(addr_resolver), false) => match &mut forwarder {
+                // A late reply to a query already answered, or anything else from the resolver
+                Some(fw) if source == addr_resolver && !fw.is_reply(&buf[..size]) => {}
+                None if source == addr_resolver => {}
+                // Another client while waiting for the resolver, it's answered right away
+                Some(_) if source != addr_resolver => {
+                    let (reply, served) = server.reply(&buf[..size], source);
+                    send_reply(&udp_socket, server, reply, source, served)?;
+                }
+                Some(fw) => match fw.add_answer(&buf[..size]) {
+                    Ok(true) => {
+                        fw.cache_answers(&mut cache.lock().unwrap(), Instant::now());
+                        let reply = fw.build_reply();
+                        let served = Served::Forwarded;
+                        send_reply(&udp_socket, server, reply, fw.destination, served)?;
+                        forwarder = None
+                    }
+                    Ok(false) => {
+                        let req = fw.forward()?;
+                        udp_socket.send_to(&req, addr_resolver)?;
+                    }
+                    Err(e) => {
+                        eprintln!("Error from the resolver: {}", e);
+                        let reply = fw.fail(&mut cache.lock().unwrap(), Instant::now());
+                        let served = Served::Forwarded;
+                        send_reply(&udp_socket, server, reply, fw.destination, served)?;
+                        forwarder = None
+                    }
+                },
+                None => match cache.lock().unwrap().reply(&buf[..size], Instant::now()) {
+                    Some(reply) => {
+                        let served = Served::Cached;
+                        send_reply(&udp_socket, server, Ok(reply), source, served)?;
+                    }
+                    None => match create_forwarder(&buf[..size], source)
+                        .map(|fw| fw.with_client_subnet(config.client_subnet))
+                        .map(|fw| fw.with_minimal(config.minimal))
+                    {
+                        Ok(mut fw) => {
                             let req = fw.forward()?;
                             udp_socket.send_to(&req, addr_resolver)?;
+                            forwarder = Some(fw);
                         }
+                        Err(e) => match DNSMessage::format_error(&buf[..size]) {
+                            Some(reply) => {
+                                let reply = reply.to_bytes();
+                                let served = Served::Local;
+                                send_reply(&udp_socket, server, reply, source, served)?;
+                            }
+                            None => eprintln!("Invalid message from {}: {}", source, e),
+                        },
                     },
-                    None => {
-                        let mut fw = create_forwarder(&buf, source)?;
-                        let req = fw.forward()?;
-                        udp_socket.send_to(&req, addr_resolver)?;
-                        forwarder = Some(fw);
-                    }
+                },
+            },
+            (Ok((size, source)), None, false) => match server.local_reply(&buf[..size]) {
+                (Ok(response), served) => {
+                    send_reply(&udp_socket, server, Ok(response), source, served)?;
+                }
+                // Not even a header to reply to
+                (Err(e), _) => eprintln!("Invalid message from {}: {}", source, e),
+            },
+            // The resolver didn't answer in time
+            (Err(e), _, _) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if let Some(fw) = forwarder.take() {
+                    let reply = fw.fail(&mut cache.lock().unwrap(), Instant::now());
+                    let served = Served::Forwarded;
+                    send_reply(&udp_socket, server, reply, fw.destination, served)?;
                 }
             }
-            (Ok((size, source)), None) => {
-                println!("Received {} bytes from {}", size, source);
-                let response = parse_and_reply(&buf)?;
-                udp_socket.send_to(&response, source)?;
-            }
-            (Err(e), _) => {
+            (Err(e), _, _) => {
                 eprintln!("Error receiving data: {}", e);
                 break;
             }
@@ -63,3 +316,323 @@ fn start_server(resolver: Option<SocketAddr>) -> Result<()> {
     }
     Ok(())
 }
+
+// Each query over TCP is answered before the next one is read, the clients can't spoof their
+// address so they are not rate limited.
+fn serve_tcp_with(listener: TcpListener, server: Arc<Server>) {
+    serve_tcp(listener, move |query, source| {
+        let (reply, served) = match server.sinkhole(query) {
+            Some(reply) => (Ok(reply), Served::Blocked),
+            None => server.reply(query, source),
+        };
+        if let Ok(reply) = &reply {
+            server.log(reply, source.ip(), served);
+        }
+        reply
+    })
+}
+
+// What the replies are built with, shared by the UDP loop and the TCP connections.
+struct Server {
+    config: Config,
+    options: ParseOptions,
+    blocklist: Option<Blocklist>,
+    zones: Option<Zones>,
+    // Also shared with the control socket
+    cache: Arc<Mutex<Cache>>,
+    query_log: Option<Mutex<QueryLog>>,
+}
+
+impl Server {
+    fn new(config: &Config) -> Result<Self> {
+        let blocklist = match &config.blocklist {
+            Some(file) => Some(Blocklist::load(file)?),
+            None => None,
+        };
+        let cache = Arc::new(Mutex::new(Cache::default()));
+        if let Some(path) = &config.control {
+            spawn_control(path, cache.clone())?;
+        }
+        let query_log = match &config.query_log {
+            Some(file) => Some(Mutex::new(QueryLog::open(file)?)),
+            None => None,
+        };
+        Ok(Self {
+            config: config.clone(),
+            options: ParseOptions {
+                strict: config.strict,
+                ..Default::default()
+            },
+            blocklist,
+            zones: (!config.zones.is_empty())
+                .then(|| Zones::from_names(config.zones.iter().map(String::as_str))),
+            cache,
+            query_log,
+        })
+    }
+
+    // The NameError reply if the query asks for a blocked name.
+    fn sinkhole(&self, query: &[u8]) -> Option<Vec<u8>> {
+        self.blocklist.as_ref()?.sinkhole(query)
+    }
+
+    // The reply when the query is answered before reading the next one: from the cache or the
+    // resolver, waiting for it, or with the local data.
+    fn reply(&self, query: &[u8], source: SocketAddr) -> (Result<Vec<u8>>, Served) {
+        let config = &self.config;
+        match (&config.resolver_http, &config.resolver_dot, config.resolver) {
+            (Some(http), _, _) => self.forward(query, source, |query| http.exchange(query)),
+            (None, Some(dot), _) => self.forward(query, source, |query| dot.exchange(query)),
+            (None, None, Some(resolver)) => {
+                self.forward(query, source, |query| exchange_udp(query, resolver))
+            }
+            (None, None, None) => self.local_reply(query),
+        }
+    }
+
+    // The reply with the local data, or Refused for the names outside of the zones if the
+    // server is authoritative for some.
+    fn local_reply(&self, query: &[u8]) -> (Result<Vec<u8>>, Served) {
+        let refused = self
+            .zones
+            .as_ref()
+            .and_then(|zones| zones.refuse_outside(query));
+        match refused {
+            Some(reply) => (Ok(reply), Served::Refused),
+            None => (parse_and_reply_with(query, &self.options), Served::Local),
+        }
+    }
+
+    fn forward(
+        &self,
+        query: &[u8],
+        source: SocketAddr,
+        exchange: impl FnMut(&[u8]) -> Result<Vec<u8>>,
+    ) -> (Result<Vec<u8>>, Served) {
+        if let Some(reply) = self.cache.lock().unwrap().reply(query, Instant::now()) {
+            return (Ok(reply), Served::Cached);
+        }
+        let mut fw = match create_forwarder(query, source) {
+            Ok(fw) => fw
+                .with_client_subnet(self.config.client_subnet)
+                .with_minimal(self.config.minimal),
+            Err(e) => {
+                let reply = DNSMessage::format_error(query).ok_or(e);
+                return (reply.and_then(|reply| reply.to_bytes()), Served::Local);
+            }
+        };
+        let reply = match fw.forward_with(exchange) {
+            Ok(()) => {
+                fw.cache_answers(&mut self.cache.lock().unwrap(), Instant::now());
+                fw.build_reply()
+            }
+            Err(e) => {
+                eprintln!("Error from the resolver: {}", e);
+                fw.fail(&mut self.cache.lock().unwrap(), Instant::now())
+            }
+        };
+        (reply, Served::Forwarded)
+    }
+
+    fn flush_log(&self) {
+        if let Some(log) = &self.query_log {
+            if let Err(e) = log.lock().unwrap().flush() {
+                eprintln!("Error writing the query log: {}", e);
+            }
+        }
+    }
+
+    fn log(&self, reply: &[u8], client: IpAddr, served: Served) {
+        if let Some(log) = &self.query_log {
+            if let Err(e) = log.lock().unwrap().log(reply, client, served) {
+                eprintln!("Error writing the query log: {}", e);
+            }
+        }
+    }
+}
+
+// Sends the reply to the client and logs it, one that couldn't be encoded is only reported.
+fn send_reply(
+    socket: &UdpSocket,
+    server: &Server,
+    reply: Result<Vec<u8>>,
+    destination: SocketAddr,
+    served: Served,
+) -> Result<()> {
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!("Invalid reply for {}: {}", destination, e);
+            return Ok(());
+        }
+    };
+    socket.send_to(&reply, destination)?;
+    server.log(&reply, destination.ip(), served);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns_starter_rust::{build_query, resolve, MessageType, Type};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() -> Result<()> {
+        assert_eq!(Config::default(), parse_args(&[])?);
+
+        let config = parse_args(&args(&["--verbose"]))?;
+        assert!(config.verbose);
+        assert_eq!(None, config.resolver);
+
+        let config = parse_args(&args(&["--resolver", "127.0.0.1:5353", "--verbose"]))?;
+        assert!(config.verbose);
+        assert_eq!(
+            Some(SocketAddr::from(([127, 0, 0, 1], 5353))),
+            config.resolver
+        );
+        let config = parse_args(&args(&["--resolver", "127.0.0.1:5353"]))?;
+        assert!(!config.verbose);
+
+        assert!(parse_args(&args(&["--resolver"])).is_err());
+        assert!(parse_args(&args(&["--resolver", "nope"])).is_err());
+        assert!(parse_args(&args(&["--strict"]))?.strict);
+        let config = parse_args(&args(&["--blocklist", "blocked.txt"]))?;
+        assert_eq!(Some(PathBuf::from("blocked.txt")), config.blocklist);
+        assert!(parse_args(&args(&["--blocklist"])).is_err());
+        assert_eq!(
+            Some(10),
+            parse_args(&args(&["--rate-limit", "10"]))?.rate_limit
+        );
+        assert!(parse_args(&args(&["--rate-limit", "many"])).is_err());
+        let config = parse_args(&args(&[
+            "--resolver-http",
+            "http://127.0.0.1:8053/dns-query",
+        ]))?;
+        assert!(config.resolver_http.is_some());
+        let config = parse_args(&args(&["--resolver-http", "https://dns.google/dns-query"]))?;
+        assert!(config.resolver_http.is_some());
+        assert!(parse_args(&args(&["--resolver-http", "dns.google"])).is_err());
+        let both = [
+            "--resolver",
+            "127.0.0.1:5353",
+            "--resolver-http",
+            "http://127.0.0.1",
+        ];
+        assert!(parse_args(&args(&both)).is_err());
+        let config = parse_args(&args(&["--resolver-dot", "1.1.1.1#one.one.one.one"]))?;
+        assert!(config.resolver_dot.is_some() && config.waits_for_resolver());
+        assert!(parse_args(&args(&["--resolver-dot", "one.one.one.one"])).is_err());
+        let both = [
+            "--resolver-http",
+            "http://127.0.0.1",
+            "--resolver-dot",
+            "1.1.1.1",
+        ];
+        assert!(parse_args(&args(&both)).is_err());
+        assert_eq!(
+            Some(24),
+            parse_args(&args(&["--client-subnet", "24"]))?.client_subnet
+        );
+        assert!(parse_args(&args(&["--client-subnet", "300"])).is_err());
+        let config = parse_args(&args(&[
+            "--zone",
+            "codecrafters.io",
+            "--zone",
+            "example.com",
+        ]))?;
+        assert_eq!(vec!["codecrafters.io", "example.com"], config.zones);
+        assert!(parse_args(&[])?.binds_udp());
+        let config = parse_args(&args(&["--tcp-only", "--resolver", "127.0.0.1:5353"]))?;
+        assert!(!config.binds_udp());
+        assert!(config.resolver.is_some());
+        assert!(!parse_args(&[])?.minimal);
+        assert!(parse_args(&args(&["--minimal"]))?.minimal);
+        let config = parse_args(&args(&["--query-log", "queries.log"]))?;
+        assert_eq!(Some(PathBuf::from("queries.log")), config.query_log);
+        let config = parse_args(&args(&["--control", "/tmp/dns.sock"]))?;
+        assert_eq!(Some(PathBuf::from("/tmp/dns.sock")), config.control);
+        assert!(parse_args(&args(&["--quiet"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_authoritative_zones() -> Result<()> {
+        let config = parse_args(&args(&["--zone", "codecrafters.io"]))?;
+        let server = Server::new(&config)?;
+        let query = build_query("example.com", Type::A, 1)?;
+        let (reply, served) = server.local_reply(&query);
+        assert_eq!(Served::Refused, served);
+        assert_eq!(0, DNSMessage::from_bytes(&reply?)?.answers());
+
+        let query = build_query("codecrafters.io", Type::A, 2)?;
+        let (reply, served) = server.local_reply(&query);
+        assert_eq!(Served::Local, served);
+        assert_eq!(1, DNSMessage::from_bytes(&reply?)?.answers());
+        Ok(())
+    }
+    #[test]
+    fn test_shutdown() -> Result<()> {
+        let server = Server::new(&Config::default())?;
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let address = socket.local_addr()?;
+        let shutdown = AtomicBool::new(false);
+        thread::scope(|scope| -> Result<()> {
+            let serving = scope.spawn(|| serve_udp(&server, socket, &shutdown));
+            // Still answering before the flag is set
+            let reply = resolve("codecrafters.io", Type::A, address)?;
+            assert_eq!(1, reply.answers());
+
+            shutdown.store(true, Ordering::Relaxed);
+            serving.join().expect("the server panicked")?;
+            Ok(())
+        })
+    }
+    #[test]
+    fn test_resolver_replies() -> Result<()> {
+        let resolver = UdpSocket::bind("127.0.0.1:0")?;
+        resolver.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let config = Config {
+            resolver: Some(resolver.local_addr()?),
+            ..Default::default()
+        };
+        let server = Server::new(&config)?;
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let address = socket.local_addr()?;
+        let client = UdpSocket::bind("127.0.0.1:0")?;
+        client.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let shutdown = AtomicBool::new(false);
+        thread::scope(|scope| -> Result<()> {
+            let serving = scope.spawn(|| serve_udp(&server, socket, &shutdown));
+            // A late reply without a query waiting is dropped, not forwarded back
+            let late =
+                parse_and_reply_with(&build_query("late.io", Type::A, 7)?, &Default::default())?;
+            resolver.send_to(&late, address)?;
+
+            client.send_to(&build_query("codecrafters.io", Type::A, 9)?, address)?;
+            let mut buf = [0; 512];
+            let (size, _) = resolver.recv_from(&mut buf)?;
+            let forwarded = DNSMessage::from_bytes(&buf[..size])?;
+            assert_eq!(9, forwarded.id());
+            assert_eq!(MessageType::Query, forwarded.header().message_type());
+
+            // Only the reply with the id of the query is taken
+            resolver.send_to(&late, address)?;
+            resolver.send_to(
+                &parse_and_reply_with(&buf[..size], &Default::default())?,
+                address,
+            )?;
+            let (size, _) = client.recv_from(&mut buf)?;
+            let reply = DNSMessage::from_bytes(&buf[..size])?;
+            assert_eq!(9, reply.id());
+            assert_eq!(1, reply.answers());
+
+            shutdown.store(true, Ordering::Relaxed);
+            serving.join().expect("the server panicked")?;
+            Ok(())
+        })
+    }
+}
@@ -2,14 +2,17 @@ mod answer;
 mod header;
 mod question;
 
+use std::collections::HashMap;
 use std::ops::Range;
 
-use answer::ResourceRecord;
-use header::Header;
-use question::Question;
+pub use answer::ResourceRecord;
+pub use header::{Header, MessageType, OpCode, ResponseCode};
+pub use question::Question;
 
 use anyhow::{anyhow, Result};
 
+use crate::idna;
+
 // Small macro to impl try from in enums repr
 // Is it worth it to make it a proc macro to just derive it in each enum?
 #[macro_export]
@@ -30,10 +33,31 @@ macro_rules! impl_try_from {
     };
 }
 
+// Pointer jumps allowed while parsing a name, more are treated as a malformed (or malicious) message
+const DEFAULT_MAX_JUMPS: usize = 5;
+
+// How strict the parsing of a message is.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ParseOptions {
+    pub max_jumps: usize,
+    // Rejects the messages with a non-zero Z, the default is to accept them for compatibility
+    pub strict: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_jumps: DEFAULT_MAX_JUMPS,
+            strict: false,
+        }
+    }
+}
+
 // Small wrapper to keep track of the current position while parsing.
 struct RawMessage<'a> {
     buffer: &'a [u8],
     current_pos: usize,
+    max_jumps: usize,
 }
 
 impl<'a> RawMessage<'a> {
@@ -41,18 +65,26 @@ impl<'a> RawMessage<'a> {
         Self {
             buffer,
             current_pos: 0,
+            max_jumps: DEFAULT_MAX_JUMPS,
         }
     }
+
+    fn with_options(mut self, options: &ParseOptions) -> Self {
+        self.max_jumps = options.max_jumps;
+        self
+    }
     fn get(&self, n: usize) -> Result<u8> {
         self.buffer
             .get(n)
             .copied()
-            .ok_or(anyhow!("invalid index: {n}"))
+            .ok_or_else(|| anyhow!("invalid index: {n}"))
     }
 
     // get the range without updating the current pointer
     fn get_range(&self, range: Range<usize>) -> Result<&[u8]> {
-        self.buffer.get(range).ok_or(anyhow!("invalid range"))
+        self.buffer
+            .get(range)
+            .ok_or_else(|| anyhow!("invalid range"))
     }
 
     // updates the current pointer
@@ -63,7 +95,7 @@ impl<'a> RawMessage<'a> {
         let next = self
             .buffer
             .get(self.current_pos..self.current_pos + n)
-            .ok_or(anyhow!("invalid range"));
+            .ok_or_else(|| anyhow!("invalid range"));
         self.current_pos += n;
         next
     }
@@ -85,9 +117,9 @@ impl_try_from!(Class, u16, {
     HS = 4,
 });
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 #[repr(u16)]
-enum Type {
+pub enum Type {
     A = 1,      // host address
     NS = 2,     // NS: authorative name server
     MD = 3,     // MD: mail destination (obsolete)
@@ -104,6 +136,7 @@ enum Type {
     MInfo = 14, // MINFO:  mailbox or mail list information
     MX = 15,    // MX: mail exchange
     Txt = 16,   // TXT: text strings
+    Opt = 41,   // OPT: EDNS pseudo record, only in the additional section
 }
 
 impl_try_from!(Type, u16, {
@@ -123,13 +156,24 @@ impl_try_from!(Type, u16, {
     MInfo = 14,
     MX = 15,
     Txt = 16,
+    Opt = 41,
 });
 
+// The sections of a message with resource records.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
 #[derive(Debug, PartialEq, Clone)]
-pub(crate) struct DNSMessage {
+pub struct DNSMessage {
     pub(crate) header: Header,
     pub(crate) question: Option<Vec<Question>>,
     pub(crate) answer: Option<Vec<ResourceRecord>>,
+    pub(crate) authority: Option<Vec<ResourceRecord>>,
+    pub(crate) additional: Option<Vec<ResourceRecord>>,
 }
 
 impl Default for DNSMessage {
@@ -139,11 +183,34 @@ impl Default for DNSMessage {
             header,
             question: None,
             answer: None,
+            authority: None,
+            additional: None,
         }
     }
 }
 
 impl DNSMessage {
+    // A recursive query with a single question for the name.
+    pub(crate) fn query(name: &str, qtype: Type, id: u16) -> Self {
+        let mut header = Header::query(id);
+        header.qd_count = 1;
+        Self {
+            header,
+            question: Some(vec![Question {
+                name: name.to_string(),
+                qtype,
+                class: Class::IN,
+            }]),
+            ..Default::default()
+        }
+    }
+
+    pub fn id(&self) -> u16 {
+        self.header.id
+    }
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
     pub fn answers(&self) -> usize {
         self.header.an_count as usize
     }
@@ -151,6 +218,20 @@ impl DNSMessage {
         self.header.qd_count as usize
     }
     pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        Self::from_bytes_with_len(buf).map(|(message, _)| message)
+    }
+
+    // The message at the start of the buffer and the octets it takes, to move past it when the
+    // buffer has more data after it.
+    pub fn from_bytes_with_len(buf: &[u8]) -> Result<(Self, usize)> {
+        Self::parse(buf, &ParseOptions::default())
+    }
+
+    pub fn from_bytes_with(buf: &[u8], options: &ParseOptions) -> Result<Self> {
+        Self::parse(buf, options).map(|(message, _)| message)
+    }
+
+    fn parse(buf: &[u8], options: &ParseOptions) -> Result<(Self, usize)> {
         if buf.len() < 12 {
             return Err(anyhow!(
                 "invalid message: expecting at least 12 octets for the header."
@@ -158,54 +239,95 @@ impl DNSMessage {
         }
         let header_bytes = buf[0..12].try_into()?;
         let header = Header::from_bytes(header_bytes)?;
-        println!("message id: {:?}", header.id);
+        if options.strict && header.reserved_bits_set() {
+            return Err(anyhow!("invalid message: the reserved Z bits are not zero"));
+        }
 
-        let mut raw = RawMessage::new(buf);
+        let mut raw = RawMessage::new(buf).with_options(options);
         // The 12 bytes of the header are already parsed
         raw.current_pos = 12;
 
         let question = if header.qd_count != 0 {
             let mut questions = Vec::with_capacity(header.qd_count as usize);
-            for i in 0..header.qd_count {
-                println!("parsing question: {}", i + 1);
+            for _ in 0..header.qd_count {
                 questions.push(Question::from_bytes(&mut raw)?)
             }
             Some(questions)
         } else {
             None
         };
-        let answer = if header.an_count != 0 {
-            let mut answers = Vec::with_capacity(header.an_count as usize);
-            for i in 0..header.an_count {
-                println!("parsing answer: {}", i + 1);
-                answers.push(ResourceRecord::from_bytes(&mut raw)?)
-            }
-            Some(answers)
-        } else {
-            None
-        };
+        let answer = parse_records(&mut raw, header.an_count)?;
+        let authority = parse_records(&mut raw, header.ns_count)?;
+        let additional = parse_records(&mut raw, header.ar_count)?;
 
-        Ok(Self {
+        let message = Self {
             header,
             question,
             answer,
+            authority,
+            additional,
+        };
+        message.check_counts()?;
+        Ok((message, raw.current_pos))
+    }
+
+    // The records parsed in each section have to be the ones declared by the header.
+    fn check_counts(&self) -> Result<()> {
+        let sections = [
+            (Section::Answer, self.header.an_count, &self.answer),
+            (Section::Authority, self.header.ns_count, &self.authority),
+            (Section::Additional, self.header.ar_count, &self.additional),
+        ];
+        for (section, declared, records) in sections {
+            let parsed = count(records);
+            if parsed != declared {
+                return Err(anyhow!(
+                    "invalid message: the {section:?} section declares {declared} records but has {parsed}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // The FormatError reply for the malformed message, None if not even its header is readable.
+    pub fn format_error(buf: &[u8]) -> Option<Self> {
+        let header = Header::from_bytes(buf.get(0..12)?.try_into().ok()?).ok()?;
+        Some(Self {
+            header: header.format_error(),
+            ..Default::default()
         })
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    // The counts of the header are the ones of the sections written.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut header = self.header;
+        header.qd_count = count(&self.question);
+        header.an_count = count(&self.answer);
+        header.ns_count = count(&self.authority);
+        header.ar_count = count(&self.additional);
         let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(&header.to_bytes());
+        // The offset of the first question with each name
+        let mut names = HashMap::new();
         if let Some(questions) = &self.question {
             for q in questions {
-                bytes.extend(q.to_bytes());
+                names.entry(q.name.as_str()).or_insert(bytes.len());
+                bytes.extend(q.to_bytes()?);
             }
         }
-        if let Some(answer) = &self.answer {
-            for rr in answer {
-                bytes.extend(rr.to_bytes());
+        for (_, rr) in self.records() {
+            {
+                match names.get(rr.name.as_str()) {
+                    // The pointer only has 14 bits for the offset
+                    Some(&offset) if offset <= MAX_POINTER_OFFSET => {
+                        let pointer = (offset as u16 | 0xC000).to_be_bytes();
+                        bytes.extend(rr.to_bytes_with_name(pointer.to_vec())?)
+                    }
+                    _ => bytes.extend(rr.to_bytes()?),
+                }
             }
         }
-        bytes
+        Ok(bytes)
     }
 
     pub fn build_reply(self) -> Self {
@@ -216,14 +338,72 @@ impl DNSMessage {
 
         if let Some(questions) = &self.question {
             for q in questions {
-                let rr = ResourceRecord::answer_by_type(q.qtype, &q.name);
-                reply.add_answer(rr)
+                match ResourceRecord::answer_by_type(q.qtype, &q.name) {
+                    Some(rr) => reply.add_answer(rr),
+                    // The other questions are still answered
+                    None => reply.header.response_code = ResponseCode::NotImplemented,
+                }
             }
         }
         reply.question = self.question;
         reply
     }
 
+    // The reply with answers already known, like the cached ones.
+    pub(crate) fn build_reply_with(self, answers: Vec<ResourceRecord>) -> Self {
+        let mut reply = Self {
+            header: self.header.build_reply(),
+            ..Default::default()
+        };
+        for rr in answers {
+            reply.add_answer(rr);
+        }
+        reply.question = self.question;
+        reply
+    }
+
+    // The message with only its header and questions.
+    pub(crate) fn without_answers(&self) -> Self {
+        let mut message = Self {
+            header: self.header,
+            question: self.question.clone(),
+            ..Default::default()
+        };
+        message.header.an_count = 0;
+        message
+    }
+
+    // The reply with only the questions and the response code.
+    pub(crate) fn build_error_reply(self, code: ResponseCode) -> Self {
+        let mut reply = Self {
+            header: self.header.build_reply(),
+            ..Default::default()
+        };
+        reply.header.response_code = code;
+        reply.header.qd_count = self.header.qd_count;
+        reply.question = self.question;
+        reply
+    }
+
+    pub fn questions_iter(&self) -> impl Iterator<Item = &Question> {
+        self.question.iter().flatten()
+    }
+
+    pub(crate) fn question_names(&self) -> impl Iterator<Item = &str> {
+        self.questions_iter().map(|q| q.name.as_str())
+    }
+
+    // The records of the answer, authority and additional sections, in that order.
+    pub fn records(&self) -> impl Iterator<Item = (Section, &ResourceRecord)> {
+        [
+            (Section::Answer, &self.answer),
+            (Section::Authority, &self.authority),
+            (Section::Additional, &self.additional),
+        ]
+        .into_iter()
+        .flat_map(|(section, records)| records.iter().flatten().map(move |rr| (section, rr)))
+    }
+
     pub(crate) fn add_answer(&mut self, rr: ResourceRecord) {
         self.header.an_count += 1;
         match &mut self.answer {
@@ -233,6 +413,52 @@ impl DNSMessage {
     }
 }
 
+fn parse_records(raw: &mut RawMessage, count: u16) -> Result<Option<Vec<ResourceRecord>>> {
+    if count == 0 {
+        return Ok(None);
+    }
+    let mut records = Vec::with_capacity(count as usize);
+    // A message ending before all the records is caught by the check of the counts
+    while records.len() < count as usize && raw.current_pos < raw.buffer.len() {
+        records.push(ResourceRecord::from_bytes(raw)?)
+    }
+    Ok(Some(records))
+}
+
+fn count<T>(section: &Option<Vec<T>>) -> u16 {
+    section.as_ref().map_or(0, |section| section.len() as u16)
+}
+
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
+// The length of a label only has 6 bits, the other 2 mark a pointer
+const MAX_LABEL_LEN: usize = 63;
+// Of the whole encoded name, with the lengths and the null termination
+const MAX_NAME_LEN: usize = 255;
+
+// Encodes the name as its labels, each one prefixed by its length, and the null termination.
+// The Unicode labels are encoded as A-labels (punycode).
+fn encode_name(name: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    // The root name (like the one of an OPT record) is just the null termination
+    for label in idna::to_ascii(name).split('.').filter(|l| !l.is_empty()) {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(anyhow!(
+                "the label {label} is longer than {MAX_LABEL_LEN} octets"
+            ));
+        }
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    // Add null termination
+    bytes.push(0);
+    if bytes.len() > MAX_NAME_LEN {
+        return Err(anyhow!(
+            "the name {name} is longer than {MAX_NAME_LEN} octets"
+        ));
+    }
+    Ok(bytes)
+}
+
 fn parse_labels(bytes: &mut RawMessage) -> Result<String> {
     let mut labels = vec![];
     let mut current = bytes.current_pos;
@@ -250,8 +476,8 @@ fn parse_labels(bytes: &mut RawMessage) -> Result<String> {
                 next_pointer = Some(current + 2);
             }
             jumps += 1;
-            if jumps > 5 {
-                return Err(anyhow!("too many pointers jumps, max: 5"));
+            if jumps > bytes.max_jumps {
+                return Err(anyhow!("too many pointers jumps, max: {}", bytes.max_jumps));
             }
             current = offset as usize;
             // Goes back to read the label from the offset
@@ -264,6 +490,7 @@ fn parse_labels(bytes: &mut RawMessage) -> Result<String> {
         current += len;
     }
 
+    // The name is kept as it came, A-labels are only decoded to display it
     let name = labels.join(".");
     // Why is rust okay for the mut borrow after another unrelated instruction but not just after the loop?
     // If the next_pointer is set, jumps to that value, otherwise continues with the current index
@@ -274,7 +501,8 @@ fn parse_labels(bytes: &mut RawMessage) -> Result<String> {
 // returns the index to start reading the label from if it is a pointer
 // otherwise, returns None
 fn pointer(byte: u8, next: u8) -> Option<u16> {
-    if byte != 0b11000000 {
+    // The two most significant bits set, the rest of the bits are part of the offset
+    if byte & 0b11000000 != 0b11000000 {
         return None;
     }
     let pointer = ((byte as u16) << 8) | (next as u16);
@@ -304,6 +532,35 @@ mod tests {
 
         let p = pointer(b1, b2);
         assert!(p.is_none());
+
+        // Offsets over 255 use the low bits of the first byte
+        assert_eq!(Some(0x10C), pointer(0b11000001, 0b00001100));
+    }
+
+    #[test]
+    fn test_max_jumps() -> Result<()> {
+        // The name "a", followed by pointers to the previous pointer
+        let mut bytes = vec![1, b'a', 0, 0b11000000, 0];
+        for offset in [3, 5, 7] {
+            bytes.extend_from_slice(&[0b11000000, offset]);
+        }
+
+        let options = ParseOptions {
+            max_jumps: 2,
+            ..Default::default()
+        };
+        let mut raw = RawMessage::new(&bytes).with_options(&options);
+        raw.current_pos = 5;
+        assert_eq!("a", parse_labels(&mut raw)?);
+        // Continues after the pointer
+        assert_eq!(7, raw.current_pos);
+        let error = parse_labels(&mut raw).unwrap_err();
+        assert_eq!("too many pointers jumps, max: 2", error.to_string());
+
+        let mut raw = RawMessage::new(&bytes);
+        raw.current_pos = 9;
+        assert_eq!("a", parse_labels(&mut raw)?);
+        Ok(())
     }
 
     #[test]
@@ -333,6 +590,10 @@ mod tests {
         let question = message.question.unwrap();
         assert_eq!(1, question.len());
         assert_eq!("codecrafters.io", question[0].name);
+
+        // The header, the name with its lengths and null termination, the type and the class
+        let (_, len) = DNSMessage::from_bytes_with_len(&request)?;
+        assert_eq!(12 + 17 + 4, len);
         Ok(())
     }
 
@@ -399,4 +660,147 @@ mod tests {
         assert_eq!(Data::IP(Ipv4Addr::new(76, 76, 21, 21)), answer[0].data);
         Ok(())
     }
+
+    #[test]
+    fn test_inflated_answer_count() -> Result<()> {
+        let mut reply = DNSMessage::query("codecrafters.io", Type::A, 8)
+            .build_reply()
+            .to_bytes()?;
+        // Three answers declared, only one present
+        reply[7] = 3;
+        let error = DNSMessage::from_bytes(&reply).unwrap_err();
+        assert_eq!(
+            "invalid message: the Answer section declares 3 records but has 1",
+            error.to_string()
+        );
+
+        // An authority record also declared after the answer
+        reply[7] = 1;
+        reply[9] = 1;
+        let error = DNSMessage::from_bytes(&reply).unwrap_err();
+        assert!(error.to_string().contains("Authority section declares 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_reply_unknown_type() {
+        let mut message = DNSMessage::default();
+        message.header.qd_count = 2;
+        message.question = Some(vec![
+            Question {
+                name: "codecrafters.io".to_string(),
+                qtype: Type::MX,
+                class: Class::IN,
+            },
+            Question {
+                name: "codecrafters.io".to_string(),
+                ..Default::default()
+            },
+        ]);
+
+        let reply = message.build_reply();
+        assert_eq!(ResponseCode::NotImplemented, reply.header.response_code);
+        assert_eq!(1, reply.answers());
+        assert_eq!(Type::A, reply.answer.unwrap()[0].atype);
+
+        let mut message = DNSMessage::default();
+        message.header.qd_count = 1;
+        message.question = Some(vec![Question {
+            name: "codecrafters.io".to_string(),
+            qtype: Type::MX,
+            class: Class::IN,
+        }]);
+        let reply = message.build_reply();
+        assert_eq!(ResponseCode::NotImplemented, reply.header.response_code);
+        assert_eq!(0, reply.answers());
+        assert!(reply.answer.is_none());
+    }
+
+    #[test]
+    fn test_to_bytes_compressed_answer() -> Result<()> {
+        let mut message = DNSMessage::default();
+        message.header.qd_count = 1;
+        message.question = Some(vec![Question {
+            name: "codecrafters.io".to_string(),
+            ..Default::default()
+        }]);
+        let reply = message.build_reply();
+
+        let bytes = reply.to_bytes()?;
+        // Header, question name (17 bytes), type and class
+        let answer_start = 12 + 17 + 4;
+        assert_eq!(&[0xC0, 0x0C], &bytes[answer_start..answer_start + 2]);
+        // Type, class, ttl, length and the ip after the pointer
+        assert_eq!(answer_start + 2 + 14, bytes.len());
+
+        let parsed = DNSMessage::from_bytes(&bytes)?;
+        assert_eq!(reply, parsed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode_name_round_trip() -> Result<()> {
+        let message = DNSMessage::query("münchen.de", Type::A, 1);
+        let bytes = message.to_bytes()?;
+        let ace = b"\x0exn--mnchen-3ya\x02de\x00";
+        assert_eq!(ace, &bytes[12..12 + ace.len()]);
+
+        let parsed = DNSMessage::from_bytes(&bytes)?;
+        assert_eq!(Some("xn--mnchen-3ya.de"), parsed.question_names().next());
+        let question = parsed.questions_iter().next().unwrap();
+        assert_eq!("münchen.de", question.display_name());
+
+        // Written back exactly as they came, even the A-labels that don't round-trip
+        for name in ["XN--MNCHEN-3YA.de", "xn--abc-.de"] {
+            let bytes = DNSMessage::query(name, Type::A, 1).to_bytes()?;
+            let parsed = DNSMessage::from_bytes(&bytes)?;
+            assert_eq!(Some(name), parsed.question_names().next());
+            assert_eq!(bytes, parsed.to_bytes()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_bytes_long_label() -> Result<()> {
+        let label = "a".repeat(MAX_LABEL_LEN);
+        let message = DNSMessage::query(&format!("{label}.io"), Type::A, 1);
+        assert!(message.to_bytes().is_ok());
+
+        let message = DNSMessage::query(&format!("{label}a.io"), Type::A, 1);
+        assert!(message.to_bytes().is_err());
+        // The whole name is too long even if each label is valid
+        let name = [label.as_str(); 4].join(".");
+        let message = DNSMessage::query(&name, Type::A, 1);
+        assert!(message.to_bytes().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_records() -> Result<()> {
+        let mut message = DNSMessage::query("codecrafters.io", Type::A, 5).build_reply();
+        let authority = ResourceRecord::answer_by_type(Type::A, "another.codecrafters.io");
+        message.authority = Some(vec![authority.unwrap()]);
+        let mut bytes = message.to_bytes()?;
+        // An EDNS OPT record: root name, type, payload size as the class, ttl and no options
+        bytes.extend_from_slice(&[0, 0, 41, 0x04, 0xD0, 0, 0, 0, 0, 0, 0]);
+        bytes[11] = 1;
+
+        let parsed = DNSMessage::from_bytes(&bytes)?;
+        let records: Vec<(Section, &str)> = parsed
+            .records()
+            .map(|(section, rr)| (section, rr.name.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Section::Answer, "codecrafters.io"),
+                (Section::Authority, "another.codecrafters.io"),
+                (Section::Additional, ""),
+            ],
+            records
+        );
+        assert_eq!(1, parsed.questions_iter().count());
+        // Written back as it was
+        assert_eq!(bytes, parsed.to_bytes()?);
+        Ok(())
+    }
 }
@@ -1,24 +1,33 @@
 use core::str;
 use std::collections::HashMap;
+use std::fmt;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
 use std::sync::OnceLock;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
-use super::{parse_labels, Class, RawMessage, Type};
+use super::{encode_name, parse_labels, Class, RawMessage, Type};
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone)]
 pub(super) enum Data {
     None,
     IP(Ipv4Addr),
+    // The EDNS pseudo record, its class is the UDP payload size of the sender
+    Opt { payload_size: u16, options: Vec<u8> },
+    // Host information, two character-strings
+    HInfo { cpu: String, os: String },
+    // The character-strings of a TXT record, kept as bytes since they can be anything
+    Text(Vec<Vec<u8>>),
+    // The opaque data of NULL and the types not implemented, kept to forward them intact
+    Raw(Vec<u8>),
 }
 #[derive(Debug, PartialEq, Clone)]
-pub(crate) struct ResourceRecord {
-    pub(super) name: String,
-    pub(super) atype: Type,
+pub struct ResourceRecord {
+    pub(crate) name: String,
+    pub(crate) atype: Type,
     pub(super) class: Class,
-    pub(super) ttl: u32, // The specification asks for a signed int, using a signed one for now.
+    pub(crate) ttl: u32, // The specification asks for a signed int, using a signed one for now.
     pub(super) length: u16,
     pub(super) data: Data, // RDATA
 }
@@ -50,7 +59,22 @@ fn domains() -> &'static HashMap<&'static str, &'static str> {
 }
 
 impl ResourceRecord {
-    pub(super) fn answer_by_type(qtype: Type, name: &str) -> Self {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn atype(&self) -> Type {
+        self.atype
+    }
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+    // The data in the presentation format of dig, like 8.8.8.8 for an A record.
+    pub fn rdata(&self) -> String {
+        self.data.to_string()
+    }
+
+    // Returns None for the types the server can't answer.
+    pub(crate) fn answer_by_type(qtype: Type, name: &str) -> Option<Self> {
         match qtype {
             Type::A => {
                 let ip = match domains().get(name) {
@@ -58,16 +82,16 @@ impl ResourceRecord {
                     None => Ipv4Addr::new(8, 8, 8, 8),
                 };
                 // I think that if a dns server doesn't have a domain it should not return it.
-                Self {
+                Some(Self {
                     name: name.to_string(),
                     atype: qtype,
                     class: Class::IN,
                     ttl: 60,
                     length: 4,
                     data: Data::IP(ip),
-                }
+                })
             }
-            _ => unimplemented!("not implemented"),
+            _ => None,
         }
     }
 
@@ -75,17 +99,39 @@ impl ResourceRecord {
         let name = parse_labels(bytes)?;
         let atype =
             u16::from_be_bytes(bytes.current_and_advance_range(2)?.try_into()?).try_into()?;
-        let class =
-            u16::from_be_bytes(bytes.current_and_advance_range(2)?.try_into()?).try_into()?;
+        let class_value = u16::from_be_bytes(bytes.current_and_advance_range(2)?.try_into()?);
+        let class = match atype {
+            Type::Opt => Class::IN,
+            _ => class_value.try_into()?,
+        };
         let ttl = u32::from_be_bytes(bytes.current_and_advance_range(4)?.try_into()?);
         let length = u16::from_be_bytes(bytes.current_and_advance_range(2)?.try_into()?);
         let data = bytes.current_and_advance_range(length as usize)?;
         let data = match atype {
-            // Only mapping length 4
-            // But in theory, it should be fine for a type A
-            Type::A => Data::IP(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
-            // Unimplemented
-            _ => Data::None,
+            Type::A => match data {
+                [a, b, c, d] => Data::IP(Ipv4Addr::new(*a, *b, *c, *d)),
+                _ => return Err(anyhow!("invalid length for an A record: {length}")),
+            },
+            Type::Opt => Data::Opt {
+                payload_size: class_value,
+                options: data.to_vec(),
+            },
+            Type::HInfo => {
+                let (cpu, rest) = parse_character_string(data)?;
+                let (os, _) = parse_character_string(rest)?;
+                Data::HInfo { cpu, os }
+            }
+            Type::Txt => {
+                let mut strings = vec![];
+                let mut rest = data;
+                while !rest.is_empty() {
+                    let (string, next) = split_character_string(rest)?;
+                    strings.push(string.to_vec());
+                    rest = next;
+                }
+                Data::Text(strings)
+            }
+            _ => Data::Raw(data.to_vec()),
         };
 
         Ok(Self {
@@ -98,32 +144,166 @@ impl ResourceRecord {
         })
     }
 
-    pub(super) fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.name.split('.').fold(Vec::new(), |mut bytes, label| {
-            let len = label.len() as u8;
-            bytes.push(len);
-            bytes.extend_from_slice(label.as_bytes());
-            bytes
-        });
-        // Add null termination
-        bytes.push(0);
+    // The EDNS pseudo record of the root name, with the options already encoded.
+    pub(crate) fn opt(payload_size: u16, options: Vec<u8>) -> Self {
+        Self {
+            atype: Type::Opt,
+            length: options.len() as u16,
+            data: Data::Opt {
+                payload_size,
+                options,
+            },
+            ..Default::default()
+        }
+    }
+
+    // The code and data of each option of an OPT record, none for the other records.
+    pub(crate) fn options(&self) -> Vec<(u16, &[u8])> {
+        let Data::Opt { options, .. } = &self.data else {
+            return vec![];
+        };
+        let mut parsed = vec![];
+        let mut rest = options.as_slice();
+        while let [c0, c1, l0, l1, data @ ..] = rest {
+            let len = (u16::from_be_bytes([*l0, *l1]) as usize).min(data.len());
+            parsed.push((u16::from_be_bytes([*c0, *c1]), &data[..len]));
+            rest = &data[len..];
+        }
+        parsed
+    }
 
+    // Appends the option to an OPT record, the other records are left as they are.
+    pub(crate) fn add_option(&mut self, code: u16, data: &[u8]) {
+        if let Data::Opt { options, .. } = &mut self.data {
+            options.extend_from_slice(&code.to_be_bytes());
+            options.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            options.extend_from_slice(data);
+            self.length = options.len() as u16;
+        }
+    }
+
+    // The UDP payload size of an OPT record.
+    pub(crate) fn set_payload_size(&mut self, size: u16) {
+        if let Data::Opt { payload_size, .. } = &mut self.data {
+            *payload_size = size;
+        }
+    }
+
+    pub(super) fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_bytes_with_name(encode_name(&self.name)?)
+    }
+
+    // The bytes of the record after its already encoded name, that can be a compression pointer.
+    // The length is the one of the data written.
+    pub(super) fn to_bytes_with_name(&self, name: Vec<u8>) -> Result<Vec<u8>> {
+        let mut bytes = name;
         let qtype = self.atype as u16;
         bytes.extend_from_slice(&qtype.to_be_bytes());
-        let class = self.class as u16;
+        let class = match &self.data {
+            Data::Opt { payload_size, .. } => *payload_size,
+            _ => self.class as u16,
+        };
         bytes.extend_from_slice(&class.to_be_bytes());
         bytes.extend_from_slice(&self.ttl.to_be_bytes());
-        bytes.extend_from_slice(&self.length.to_be_bytes());
-        match self.data {
+        let mut data = Vec::new();
+        match &self.data {
             Data::None => {}
             Data::IP(ip) => {
-                bytes.extend_from_slice(&ip.octets());
+                data.extend_from_slice(&ip.octets());
+            }
+            Data::Opt { options, .. } => data.extend_from_slice(options),
+            Data::HInfo { cpu, os } => {
+                encode_character_string(cpu, &mut data)?;
+                encode_character_string(os, &mut data)?;
+            }
+            Data::Text(strings) => {
+                for string in strings {
+                    encode_character_bytes(string, &mut data)?;
+                }
+            }
+            Data::Raw(raw) => data.extend_from_slice(raw),
+        }
+        bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&data);
+        Ok(bytes)
+    }
+}
+
+// The rdata in the presentation format of dig, IPs as text and strings quoted.
+impl fmt::Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Data::None => Ok(()),
+            Data::IP(ip) => write!(f, "{ip}"),
+            Data::Opt { payload_size, .. } => write!(f, "udp: {payload_size}"),
+            Data::HInfo { cpu, os } => {
+                write!(
+                    f,
+                    "\"{}\" \"{}\"",
+                    escaped(cpu.as_bytes()),
+                    escaped(os.as_bytes())
+                )
+            }
+            Data::Text(strings) => {
+                let quoted: Vec<String> = strings
+                    .iter()
+                    .map(|string| format!("\"{}\"", escaped(string)))
+                    .collect();
+                f.write_str(&quoted.join(" "))
             }
+            Data::Raw(raw) => f.write_str(&escaped(raw)),
         }
-        bytes
     }
 }
 
+// Escapes the bytes that aren't printable ASCII as \DDD in decimal, and " and \ with a
+// backslash, so the control bytes of the data don't reach the terminal.
+fn escaped(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{byte:03}")),
+        }
+    }
+    escaped
+}
+
+// A character-string is prefixed by its length in a single octet, returns it and the rest.
+fn parse_character_string(data: &[u8]) -> Result<(String, &[u8])> {
+    let (string, rest) = split_character_string(data)?;
+    Ok((str::from_utf8(string)?.to_string(), rest))
+}
+
+fn split_character_string(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let (&len, rest) = data
+        .split_first()
+        .ok_or_else(|| anyhow!("missing character-string"))?;
+    if rest.len() < len as usize {
+        return Err(anyhow!("the character-string is shorter than {len}"));
+    }
+    Ok(rest.split_at(len as usize))
+}
+
+fn encode_character_string(string: &str, bytes: &mut Vec<u8>) -> Result<()> {
+    encode_character_bytes(string.as_bytes(), bytes)
+        .map_err(|_| anyhow!("the character-string {string} is longer than 255 octets"))
+}
+
+fn encode_character_bytes(string: &[u8], bytes: &mut Vec<u8>) -> Result<()> {
+    let len: u8 = string
+        .len()
+        .try_into()
+        .map_err(|_| anyhow!("the character-string is longer than 255 octets"))?;
+    bytes.push(len);
+    bytes.extend_from_slice(string);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,7 +320,11 @@ mod tests {
             data: Data::IP(Ipv4Addr::from_bits(0x08080808)),
         };
         let answer = ResourceRecord::answer_by_type(Type::A, "codecrafters.io");
-        assert_eq!(expected_answer, answer);
+        assert_eq!(Some(expected_answer), answer);
+        assert_eq!(
+            None,
+            ResourceRecord::answer_by_type(Type::MX, "codecrafters.io")
+        );
     }
 
     #[test]
@@ -154,7 +338,7 @@ mod tests {
             data: Data::IP(Ipv4Addr::from_bits(0x08080808)),
         };
 
-        let bytes = answer.to_bytes();
+        let bytes = answer.to_bytes()?;
         let len = bytes[0];
         assert_eq!(12, len);
         let len_hex = format!("{:#02x}", len);
@@ -218,8 +402,8 @@ mod tests {
         assert_eq!(Class::IN, rr.class);
         assert_eq!(ttl, rr.ttl);
         match rr.data {
-            Data::None => panic!("data was not mapped"),
             Data::IP(ip) => assert_eq!(data, ip.octets()),
+            _ => panic!("data was not mapped"),
         }
         Ok(())
     }
@@ -265,9 +449,77 @@ mod tests {
         assert_eq!(Class::IN, rr.class);
         assert_eq!(ttl, rr.ttl);
         match rr.data {
-            Data::None => panic!("data was not mapped"),
             Data::IP(ip) => assert_eq!(data, ip.octets()),
+            _ => panic!("data was not mapped"),
         }
         Ok(())
     }
+
+    #[test]
+    fn test_hinfo_round_trip() -> Result<()> {
+        let record = ResourceRecord {
+            name: "codecrafters.io".to_string(),
+            atype: Type::HInfo,
+            ttl: 60,
+            data: Data::HInfo {
+                cpu: "INTEL-386".to_string(),
+                os: "UNIX".to_string(),
+            },
+            ..Default::default()
+        };
+        let bytes = record.to_bytes()?;
+        let mut raw = RawMessage::new(&bytes);
+        let parsed = ResourceRecord::from_bytes(&mut raw)?;
+        // Both strings with their length octet
+        assert_eq!(1 + 9 + 1 + 4, parsed.length);
+        assert_eq!(record.data, parsed.data);
+        assert_eq!(bytes, parsed.to_bytes()?);
+
+        let mut long = record.clone();
+        long.data = Data::HInfo {
+            cpu: "x".repeat(256),
+            os: "UNIX".to_string(),
+        };
+        assert!(long.to_bytes().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_txt_display_escapes() -> Result<()> {
+        let record = ResourceRecord {
+            name: "codecrafters.io".to_string(),
+            atype: Type::Txt,
+            data: Data::Text(vec![b"bell\x07\"quoted\"".to_vec(), vec![0xc3, 0xa9]]),
+            ..Default::default()
+        };
+        let bytes = record.to_bytes()?;
+        let mut raw = RawMessage::new(&bytes);
+        let parsed = ResourceRecord::from_bytes(&mut raw)?;
+        assert_eq!(record.data, parsed.data);
+        assert_eq!(
+            "\"bell\\007\\\"quoted\\\"\" \"\\195\\169\"",
+            parsed.data.to_string()
+        );
+        assert_eq!("a\\000\\255", Data::Raw(vec![b'a', 0, 255]).to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_round_trip() -> Result<()> {
+        let payload: Vec<u8> = (0..40).map(|_| rand::random()).collect();
+        let record = ResourceRecord {
+            name: "codecrafters.io".to_string(),
+            atype: Type::Null,
+            length: payload.len() as u16,
+            data: Data::Raw(payload.clone()),
+            ..Default::default()
+        };
+        let bytes = record.to_bytes()?;
+        assert!(bytes.ends_with(&payload));
+        let mut raw = RawMessage::new(&bytes);
+        let parsed = ResourceRecord::from_bytes(&mut raw)?;
+        assert_eq!(record, parsed);
+        assert_eq!(bytes, parsed.to_bytes()?);
+        Ok(())
+    }
 }
@@ -4,7 +4,7 @@ use crate::impl_try_from;
 
 #[repr(u8)]
 #[derive(Debug, PartialEq, Copy, Clone)]
-enum MessageType {
+pub enum MessageType {
     Query,
     Response,
 }
@@ -14,27 +14,45 @@ impl_try_from!(MessageType, u8, {
     Response = 1,
 });
 
-#[repr(u8)]
 #[derive(Debug, PartialEq, Copy, Clone)]
-enum OpCode {
+pub enum OpCode {
     Query,
     IQuery,
     Status,
     // The spec has reserved these values for future use, cc sends a 3 as a test.
     Reserved,
+    // The rest of the 4 bits, like NOTIFY (4) and UPDATE (5), answered with NotImplemented
+    Unknown(u8),
 }
 
-impl_try_from!(OpCode, u8, {
-    Query = 0,
-    IQuery = 1,
-    Status = 2,
-    Reserved = 3,
+// Every value of the 4 bits is an opcode, so the header of any query can be parsed.
+impl From<u8> for OpCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Query,
+            1 => Self::IQuery,
+            2 => Self::Status,
+            3 => Self::Reserved,
+            value => Self::Unknown(value & 0b1111),
+        }
+    }
+}
 
-});
+impl From<OpCode> for u8 {
+    fn from(op_code: OpCode) -> Self {
+        match op_code {
+            OpCode::Query => 0,
+            OpCode::IQuery => 1,
+            OpCode::Status => 2,
+            OpCode::Reserved => 3,
+            OpCode::Unknown(value) => value,
+        }
+    }
+}
 
 #[repr(u8)]
 #[derive(Debug, PartialEq, Copy, Clone)]
-enum ResponseCode {
+pub enum ResponseCode {
     NoError,
     FormatError,
     ServerFailure,
@@ -53,20 +71,20 @@ impl_try_from!(ResponseCode, u8, {
 });
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-pub(crate) struct Header {
-    pub(super) id: u16,          // ID: 16 bits big endian
-    message_type: MessageType,   // QR: 1 bit
-    op_code: OpCode,             // OPCODE: 4 bits
-    auth_answer: bool,           // AA (The response server owns the domain): 1 bit
-    truncation: bool,            // TC: 1 bit
-    recursion_desired: bool,     // RD: 1 bit
-    recursion_available: bool,   // RA: 1 bit
-    z: u8,                       // reserverd: 3 bits
-    response_code: ResponseCode, // RCODE: 4 bits
-    pub(crate) qd_count: u16,    // QDCOUNT: 16 bits big endian
-    pub(crate) an_count: u16,    // ANCOUNT: 16 bits big endian
-    ns_count: u16,               // NSCOUNT: 16 bits big endian
-    ar_count: u16,               // ARCOUNT : 16 bits big endian
+pub struct Header {
+    pub(super) id: u16,                     // ID: 16 bits big endian
+    message_type: MessageType,              // QR: 1 bit
+    op_code: OpCode,                        // OPCODE: 4 bits
+    auth_answer: bool,                      // AA (The response server owns the domain): 1 bit
+    truncation: bool,                       // TC: 1 bit
+    pub(crate) recursion_desired: bool,     // RD: 1 bit
+    recursion_available: bool,              // RA: 1 bit
+    z: u8,                                  // reserverd: 3 bits
+    pub(crate) response_code: ResponseCode, // RCODE: 4 bits
+    pub(crate) qd_count: u16,               // QDCOUNT: 16 bits big endian
+    pub(crate) an_count: u16,               // ANCOUNT: 16 bits big endian
+    pub(crate) ns_count: u16,               // NSCOUNT: 16 bits big endian
+    pub(crate) ar_count: u16,               // ARCOUNT : 16 bits big endian
 }
 
 impl Default for Header {
@@ -90,6 +108,40 @@ impl Default for Header {
 }
 
 impl Header {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+    pub fn op_code(&self) -> OpCode {
+        self.op_code
+    }
+    pub fn auth_answer(&self) -> bool {
+        self.auth_answer
+    }
+    pub fn truncation(&self) -> bool {
+        self.truncation
+    }
+    pub fn recursion_desired(&self) -> bool {
+        self.recursion_desired
+    }
+    pub fn recursion_available(&self) -> bool {
+        self.recursion_available
+    }
+    pub fn response_code(&self) -> ResponseCode {
+        self.response_code
+    }
+
+    // The header of a query asking for recursion.
+    pub(super) fn query(id: u16) -> Self {
+        Self {
+            id,
+            recursion_desired: true,
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn build_reply(&self) -> Self {
         let mut reply = *self;
         reply.message_type = MessageType::Response;
@@ -100,16 +152,35 @@ impl Header {
         reply
     }
 
+    // The reply to a malformed message, without any section.
+    pub(crate) fn format_error(&self) -> Self {
+        Self {
+            z: 0,
+            response_code: ResponseCode::FormatError,
+            qd_count: 0,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+            ..self.build_reply()
+        }
+    }
+
+    // The reserved bits must be zero
+    pub(super) fn reserved_bits_set(&self) -> bool {
+        self.z != 0
+    }
+
     // Safety: Using directly the indices of the array as we expect a known size
     pub(super) fn from_bytes(buf: [u8; 12]) -> Result<Self> {
-        let mut header = Self::default();
-        let id: [u8; 2] = buf[0..2].try_into()?;
-        header.id = u16::from_be_bytes(id);
+        let mut header = Self {
+            id: u16::from_be_bytes([buf[0], buf[1]]),
+            ..Default::default()
+        };
 
         let bit_qr = (buf[2] & 0b10000000) >> 7;
         header.message_type = bit_qr.try_into()?;
         let bits_op = (buf[2] & 0b01111000) >> 3;
-        header.op_code = bits_op.try_into()?;
+        header.op_code = bits_op.into();
         header.auth_answer = (buf[2] & 0b00000100) >> 2 != 0;
         header.truncation = (buf[2] & 0b00000010) >> 1 != 0;
         header.recursion_desired = (buf[2] & 0b00000001) != 0;
@@ -119,10 +190,11 @@ impl Header {
         let bits_rc = buf[3] & 0b00001111;
         header.response_code = bits_rc.try_into()?;
 
-        header.qd_count = u16::from_be_bytes(buf[4..6].try_into()?);
-        header.an_count = u16::from_be_bytes(buf[6..8].try_into()?);
-        header.ns_count = u16::from_be_bytes(buf[8..10].try_into()?);
-        header.ar_count = u16::from_be_bytes(buf[10..12].try_into()?);
+        // The buffer has a fixed size, the counts don't need a fallible conversion
+        header.qd_count = u16::from_be_bytes([buf[4], buf[5]]);
+        header.an_count = u16::from_be_bytes([buf[6], buf[7]]);
+        header.ns_count = u16::from_be_bytes([buf[8], buf[9]]);
+        header.ar_count = u16::from_be_bytes([buf[10], buf[11]]);
         Ok(header)
     }
 
@@ -131,7 +203,7 @@ impl Header {
         buf[0..2].copy_from_slice(&self.id.to_be_bytes());
 
         let bit_qr = self.message_type as u8;
-        let bits_op = self.op_code as u8;
+        let bits_op = u8::from(self.op_code);
         let bit_aa = self.auth_answer as u8;
         let bit_tr = self.truncation as u8;
         let bit_rd = self.recursion_desired as u8;
@@ -205,6 +277,17 @@ mod tests {
         assert_eq!(12, h.ar_count);
         Ok(())
     }
+
+    #[test]
+    fn test_unknown_op_code() -> Result<()> {
+        let mut buf: [u8; 12] = [0; 12];
+        buf[2] = 6 << 3;
+        let h = Header::from_bytes(buf)?;
+        assert_eq!(OpCode::Unknown(6), h.op_code);
+        assert_eq!(buf, h.to_bytes());
+        assert_eq!(ResponseCode::NotImplemented, h.build_reply().response_code);
+        Ok(())
+    }
 }
 
 #[test]
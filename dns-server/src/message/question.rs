@@ -1,11 +1,12 @@
 use anyhow::Result;
 
-use super::{parse_labels, Class, RawMessage, Type};
+use super::{encode_name, parse_labels, Class, RawMessage, Type};
+use crate::idna;
 
 #[derive(Debug, PartialEq, Clone)]
-pub(crate) struct Question {
-    pub(super) name: String, // domain name
-    pub(super) qtype: Type,  // 2 bytes
+pub struct Question {
+    pub(crate) name: String, // domain name
+    pub(crate) qtype: Type,  // 2 bytes
     pub(super) class: Class, // 2 bytes
 }
 
@@ -20,6 +21,13 @@ impl Default for Question {
 }
 
 impl Question {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn qtype(&self) -> Type {
+        self.qtype
+    }
+
     pub(super) fn from_bytes(bytes: &mut RawMessage) -> Result<Self> {
         let name = parse_labels(bytes)?;
         let qtype =
@@ -30,21 +38,18 @@ impl Question {
         Ok(Self { name, qtype, class })
     }
 
-    pub(super) fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.name.split('.').fold(Vec::new(), |mut bytes, label| {
-            let len = label.len() as u8;
-            bytes.push(len);
-            bytes.extend_from_slice(label.as_bytes());
-            bytes
-        });
-        // Add null termination
-        bytes.push(0);
+    // The name with its A-labels decoded to Unicode, like münchen.de
+    pub(crate) fn display_name(&self) -> String {
+        idna::to_unicode(&self.name)
+    }
 
+    pub(super) fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = encode_name(&self.name)?;
         let qtype = self.qtype as u16;
         bytes.extend_from_slice(&qtype.to_be_bytes());
         let class = self.class as u16;
         bytes.extend_from_slice(&class.to_be_bytes());
-        bytes
+        Ok(bytes)
     }
 }
 
@@ -116,7 +121,7 @@ mod tests {
             ..Default::default()
         };
 
-        let bytes = question.to_bytes();
+        let bytes = question.to_bytes()?;
         let len = bytes[0];
         assert_eq!(12, len);
         let len_hex = format!("{:#02x}", len);
@@ -0,0 +1,110 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::DNSMessage;
+
+// How often the buffered lines are written to the file at most
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// How the reply to a query was built.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Served {
+    Local,
+    Cached,
+    Forwarded,
+    Refused,
+    Blocked,
+}
+
+impl fmt::Display for Served {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let served = match self {
+            Served::Local => "local",
+            Served::Cached => "cached",
+            Served::Forwarded => "forwarded",
+            Served::Refused => "refused",
+            Served::Blocked => "blocked",
+        };
+        f.write_str(served)
+    }
+}
+
+// Appends a tab-separated line for each question answered: the unix time in milliseconds,
+// the client, the name, the type, the response code and how it was served.
+pub struct QueryLog {
+    writer: BufWriter<File>,
+    last_flush: Instant,
+}
+
+impl QueryLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            last_flush: Instant::now(),
+        })
+    }
+
+    // Logs the questions of the reply sent to the client, a reply that can't be parsed is skipped.
+    pub fn log(&mut self, reply: &[u8], client: IpAddr, served: Served) -> Result<()> {
+        let Ok(reply) = DNSMessage::from_bytes(reply) else {
+            return Ok(());
+        };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let code = reply.header.response_code;
+        for q in reply.questions_iter() {
+            writeln!(
+                self.writer,
+                "{timestamp}\t{client}\t{}\t{:?}\t{code:?}\t{served}",
+                q.display_name(),
+                q.qtype
+            )?;
+        }
+        // Buffered, but not for too long
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_query, parse_and_reply, Type};
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_log() -> Result<()> {
+        let path = env::temp_dir().join(format!("dns_query_log_{}", process::id()));
+        let mut log = QueryLog::open(&path)?;
+        let reply = parse_and_reply(&build_query("codecrafters.io", Type::A, 1)?)?;
+        log.log(&reply, [127, 0, 0, 1].into(), Served::Local)?;
+        log.log(b"not a message", [127, 0, 0, 1].into(), Served::Refused)?;
+        log.flush()?;
+
+        let contents = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(1, lines.len());
+        let fields: Vec<&str> = lines[0].split('\t').collect();
+        assert!(fields[0].parse::<u128>().is_ok());
+        assert_eq!(
+            vec!["127.0.0.1", "codecrafters.io", "A", "NoError", "local"],
+            fields[1..]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Limits the queries of each client to a number per window of time (a sliding window).
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    // The time of the recent queries of each client
+    clients: HashMap<IpAddr, VecDeque<Instant>>,
+    last_eviction: Option<Instant>,
+}
+
+impl RateLimiter {
+    // A limit of queries per second.
+    pub fn per_second(limit: usize) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            clients: HashMap::new(),
+            last_eviction: None,
+        }
+    }
+
+    // Records a query of the client, false if it's over the limit and has to be refused.
+    // The refused queries don't count.
+    pub fn allow(&mut self, client: IpAddr, now: Instant) -> bool {
+        self.evict_idle(now);
+        let queries = self.clients.entry(client).or_default();
+        while queries
+            .front()
+            .is_some_and(|&time| now.duration_since(time) >= self.window)
+        {
+            queries.pop_front();
+        }
+        if queries.len() >= self.limit {
+            return false;
+        }
+        queries.push_back(now);
+        true
+    }
+
+    // Forgets the clients without queries in the last window, at most once per window.
+    fn evict_idle(&mut self, now: Instant) {
+        if self
+            .last_eviction
+            .is_some_and(|last| now.duration_since(last) < self.window)
+        {
+            return;
+        }
+        self.last_eviction = Some(now);
+        let window = self.window;
+        self.clients.retain(|_, queries| {
+            queries
+                .back()
+                .is_some_and(|&time| now.duration_since(time) < window)
+        });
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow() {
+        let mut limiter = RateLimiter::per_second(2);
+        let (a, b): (IpAddr, IpAddr) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        let start = Instant::now();
+
+        assert!(limiter.allow(a, start));
+        assert!(limiter.allow(a, start + Duration::from_millis(100)));
+        // The third one in the same second
+        assert!(!limiter.allow(a, start + Duration::from_millis(200)));
+        // Another client is under the limit
+        assert!(limiter.allow(b, start + Duration::from_millis(200)));
+
+        // The first query left the window
+        assert!(limiter.allow(a, start + Duration::from_millis(1000)));
+        assert!(!limiter.allow(a, start + Duration::from_millis(1050)));
+    }
+
+    #[test]
+    fn test_evict_idle() {
+        let mut limiter = RateLimiter::per_second(1);
+        let start = Instant::now();
+        limiter.allow([10, 0, 0, 1].into(), start);
+        limiter.allow([10, 0, 0, 2].into(), start + Duration::from_millis(500));
+        assert_eq!(2, limiter.clients());
+
+        // Only the first client has been idle for a whole window
+        limiter.allow([10, 0, 0, 3].into(), start + Duration::from_millis(1200));
+        assert_eq!(2, limiter.clients());
+        limiter.allow([10, 0, 0, 3].into(), start + Duration::from_millis(5000));
+        assert_eq!(1, limiter.clients());
+    }
+}
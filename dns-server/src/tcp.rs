@@ -0,0 +1,86 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::framing::{frame, read_framed};
+
+// Connections without a new query for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Serves the connections of the listener, each one in its own thread. Every length-prefixed
+// query is answered by the handler with the client address, until the client closes it.
+pub fn serve_tcp<H>(listener: TcpListener, handler: H)
+where
+    H: Fn(&[u8], SocketAddr) -> Result<Vec<u8>> + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Error accepting a TCP connection: {}", e);
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, handler.as_ref()) {
+                eprintln!("Error in a TCP connection: {}", e);
+            }
+        });
+    }
+}
+
+fn serve_connection(
+    mut stream: TcpStream,
+    handler: &impl Fn(&[u8], SocketAddr) -> Result<Vec<u8>>,
+) -> Result<()> {
+    let source = stream.peer_addr()?;
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    loop {
+        let query = match read_framed(&mut stream) {
+            Ok(query) => query,
+            // Closed by the client, or idle
+            Err(e) if is_closed(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let reply = handler(&query, source)?;
+        io::Write::write_all(&mut stream, &frame(&reply)?)?;
+    }
+}
+
+fn is_closed(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_query, exchange_framed, parse_and_reply, DNSMessage, Type};
+
+    #[test]
+    fn test_serve_tcp() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        thread::spawn(move || serve_tcp(listener, |query, _| parse_and_reply(query)));
+
+        // Several queries over the same connection
+        let mut stream = TcpStream::connect(address)?;
+        for id in [1, 2] {
+            let query = build_query("codecrafters.io", Type::A, id)?;
+            let reply = DNSMessage::from_bytes(&exchange_framed(&mut stream, &query)?)?;
+            assert_eq!(id, reply.id());
+            assert_eq!(1, reply.answers());
+        }
+        Ok(())
+    }
+}
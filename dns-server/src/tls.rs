@@ -0,0 +1,61 @@
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+
+use anyhow::Result;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::RESOLVE_TIMEOUT;
+
+// A TCP connection encrypted with TLS, read and written like the plain one.
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// The client config verifying the certificates with the Mozilla roots.
+pub fn default_config() -> Arc<ClientConfig> {
+    client_config(RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    })
+}
+
+pub fn client_config(roots: RootCertStore) -> Arc<ClientConfig> {
+    Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+}
+
+// Connects to the server, the handshake happens with the first read or write and fails if the
+// certificate is not valid for the name.
+pub fn connect(
+    address: SocketAddr,
+    name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+) -> Result<TlsStream> {
+    let stream = TcpStream::connect_timeout(&address, RESOLVE_TIMEOUT)?;
+    stream.set_read_timeout(Some(RESOLVE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESOLVE_TIMEOUT))?;
+    let connection = ClientConnection::new(config, name)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::ServerConfig;
+
+    // A self-signed certificate for the name, as the server config using it and the roots
+    // trusting it.
+    pub(crate) fn self_signed(name: &str) -> Result<(Arc<ServerConfig>, RootCertStore)> {
+        let certified = rcgen::generate_simple_self_signed(vec![name.to_string()])?;
+        let key =
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+        let server = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key)?;
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone())?;
+        Ok((Arc::new(server), roots))
+    }
+}
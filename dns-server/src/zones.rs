@@ -0,0 +1,57 @@
+use crate::domains::Domains;
+use crate::message::{DNSMessage, ResponseCode};
+
+// The zones the server is authoritative for, when it answers without a resolver.
+// A zone includes its subdomains.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Zones {
+    names: Domains,
+}
+
+impl Zones {
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            names: Domains::from_names(names),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    // The Refused reply if the message asks for a name outside of the zones, None if it has
+    // to be answered.
+    pub fn refuse_outside(&self, buf: &[u8]) -> Option<Vec<u8>> {
+        let message = DNSMessage::from_bytes(buf).ok()?;
+        let outside = message.question_names().any(|name| !self.contains(name));
+        if !outside {
+            return None;
+        }
+        message
+            .build_error_reply(ResponseCode::Refused)
+            .to_bytes()
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_query, Type};
+    use anyhow::Result;
+
+    #[test]
+    fn test_refuse_outside() -> Result<()> {
+        let zones = Zones::from_names(["codecrafters.io"]);
+        let query = build_query("another.CodeCrafters.io", Type::A, 1)?;
+        assert_eq!(None, zones.refuse_outside(&query));
+
+        let query = build_query("example.com", Type::A, 2)?;
+        let reply = zones.refuse_outside(&query).expect("not refused");
+        let message = DNSMessage::from_bytes(&reply)?;
+        assert_eq!(ResponseCode::Refused, message.header.response_code);
+        assert_eq!(1, message.questions());
+        assert_eq!(0, message.answers());
+        Ok(())
+    }
+}
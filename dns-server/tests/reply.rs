@@ -0,0 +1,81 @@
+use std::net::UdpSocket;
+use std::thread;
+
+use dns_starter_rust::{
+    build_query, exchange_udp, parse_and_reply, DNSMessage, MessageType, OpCode, ResponseCode,
+    Section, Type,
+};
+
+#[test]
+fn test_reply_response_code() -> anyhow::Result<()> {
+    let reply = DNSMessage::from_bytes(&parse_and_reply(&build_query(
+        "codecrafters.io",
+        Type::A,
+        42,
+    )?)?)?;
+    let header = reply.header();
+    assert_eq!(42, header.id());
+    assert_eq!(MessageType::Response, header.message_type());
+    assert_eq!(OpCode::Query, header.op_code());
+    assert_eq!(ResponseCode::NoError, header.response_code());
+
+    // The server only answers A records
+    let reply = DNSMessage::from_bytes(&parse_and_reply(&build_query(
+        "codecrafters.io",
+        Type::MX,
+        43,
+    )?)?)?;
+    assert_eq!(ResponseCode::NotImplemented, reply.header().response_code());
+    Ok(())
+}
+
+#[test]
+fn test_reply_records() -> anyhow::Result<()> {
+    let reply = DNSMessage::from_bytes(&parse_and_reply(&build_query(
+        "codecrafters.io",
+        Type::A,
+        44,
+    )?)?)?;
+    let questions: Vec<(&str, Type)> = reply
+        .questions_iter()
+        .map(|q| (q.name(), q.qtype()))
+        .collect();
+    assert_eq!(vec![("codecrafters.io", Type::A)], questions);
+    let records: Vec<_> = reply
+        .records()
+        .map(|(section, rr)| (section, rr.name(), rr.atype(), rr.ttl(), rr.rdata()))
+        .collect();
+    assert_eq!(
+        vec![(
+            Section::Answer,
+            "codecrafters.io",
+            Type::A,
+            60,
+            "8.8.8.8".to_string()
+        )],
+        records
+    );
+    Ok(())
+}
+
+#[test]
+fn test_exchange_udp_skips_other_ids() -> anyhow::Result<()> {
+    let server = UdpSocket::bind("127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    let resolver = thread::spawn(move || -> anyhow::Result<()> {
+        let mut buf = [0; 512];
+        let (size, client) = server.recv_from(&mut buf)?;
+        let reply = parse_and_reply(&buf[..size])?;
+        // A late reply to another query comes first
+        let mut other = reply.clone();
+        other[0..2].copy_from_slice(&7u16.to_be_bytes());
+        server.send_to(&other, client)?;
+        server.send_to(&reply, client)?;
+        Ok(())
+    });
+
+    let reply = exchange_udp(&build_query("codecrafters.io", Type::A, 45)?, address)?;
+    assert_eq!(45, DNSMessage::from_bytes(&reply)?.header().id());
+    resolver.join().unwrap()?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+
+use crate::tokenizer::{tokenize, Token, Word};
+
+// Aliases by name, sorted to list them.
+pub(crate) type Aliases = BTreeMap<String, String>;
+
+// Parses a definition like name=value, the quotes around the value were already removed
+// by the tokenizer.
+pub(crate) fn parse_definition(definition: &str) -> Option<(String, String)> {
+    let (name, value) = definition.split_once('=')?;
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+// Formats the alias like bash does, so it can be reused as input.
+pub(crate) fn format(name: &str, value: &str) -> String {
+    format!("alias {name}='{value}'")
+}
+
+// Replaces the first word of each command while it's an unquoted alias, with the tokens of its value.
+// An alias is expanded only once, so aliases referring to themselves (or each other) don't loop.
+pub(crate) fn expand(aliases: &Aliases, tokens: Vec<Token>) -> Result<Vec<Token>> {
+    expand_tokens(aliases, tokens, &HashSet::new())
+}
+
+fn expand_tokens(
+    aliases: &Aliases,
+    tokens: Vec<Token>,
+    seen: &HashSet<String>,
+) -> Result<Vec<Token>> {
+    let mut expanded = Vec::with_capacity(tokens.len());
+    // The first word of the line or after an operator is a command
+    let mut command_start = true;
+    for token in tokens {
+        let is_operator = matches!(token, Token::Operator(_));
+        match token {
+            Token::Word(word) if command_start => match alias(aliases, &word, seen) {
+                Some((name, value)) => {
+                    let mut seen = seen.clone();
+                    seen.insert(name);
+                    expanded.extend(expand_tokens(aliases, tokenize(value)?, &seen)?);
+                }
+                None => expanded.push(Token::Word(word)),
+            },
+            token => expanded.push(token),
+        }
+        command_start = is_operator;
+    }
+    Ok(expanded)
+}
+
+// The alias named by the unquoted word, unless it was already expanded.
+fn alias<'a>(
+    aliases: &'a Aliases,
+    word: &Word,
+    seen: &HashSet<String>,
+) -> Option<(String, &'a str)> {
+    let name = word.unquoted().filter(|name| !seen.contains(name))?;
+    let value = aliases.get(&name)?;
+    Some((name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_definition() {
+        assert_eq!(
+            Some(("ll".to_string(), "ls -l".to_string())),
+            parse_definition("ll=ls -l")
+        );
+        assert_eq!(
+            Some(("e".to_string(), "echo".to_string())),
+            parse_definition("e=echo")
+        );
+        assert_eq!(None, parse_definition("ll"));
+        assert_eq!(None, parse_definition("=ls"));
+    }
+
+    #[test]
+    fn test_format() {
+        let mut aliases = Aliases::new();
+        aliases.insert("ll".to_string(), "ls -l".to_string());
+        aliases.insert("e".to_string(), "echo".to_string());
+        let listed: Vec<String> = aliases.iter().map(|(n, v)| format(n, v)).collect();
+        assert_eq!(vec!["alias e='echo'", "alias ll='ls -l'"], listed);
+    }
+
+    // Expands the aliases of the input, showing the words and operators as they are written
+    fn expand_line(aliases: &Aliases, input: &str) -> String {
+        expand(aliases, tokenize(input).unwrap())
+            .unwrap()
+            .iter()
+            .map(|token| match token {
+                Token::Word(word) => word.literal(),
+                _ => ";".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut aliases = Aliases::new();
+        aliases.insert("ll".to_string(), "ls -l".to_string());
+        aliases.insert("ls".to_string(), "ls --color".to_string());
+        aliases.insert("a".to_string(), "b x".to_string());
+        aliases.insert("b".to_string(), "a y".to_string());
+        aliases.insert("both".to_string(), "ll; ll".to_string());
+
+        assert_eq!("echo ll", expand_line(&aliases, "echo ll"));
+        assert_eq!("ls --color -l /tmp", expand_line(&aliases, "ll /tmp"));
+        // Aliases referring to themselves are expanded once
+        assert_eq!("ls --color", expand_line(&aliases, "ls"));
+        assert_eq!("a y x", expand_line(&aliases, "a"));
+        // Every command of the line is expanded, quoted words are not
+        assert_eq!("ls --color -l ; ll", expand_line(&aliases, "ll; 'll'"));
+        assert_eq!(
+            "ls --color -l ; ls --color -l",
+            expand_line(&aliases, "both")
+        );
+    }
+}
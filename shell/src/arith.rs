@@ -0,0 +1,206 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use anyhow::{anyhow, Result};
+
+use crate::tokenizer::{Quoting, Word};
+use crate::variables::{self, Variables};
+
+// Replaces each $(( expression )) of the unquoted and double quoted parts of the word
+// with its value.
+pub(crate) fn expand_word(word: &Word, variables: &Variables, last_status: i32) -> Result<Word> {
+    let mut expanded = word.clone();
+    for part in &mut expanded.parts {
+        if part.quoting != Quoting::Single && part.text.contains("$((") {
+            part.text = expand(&part.text, variables, last_status)?;
+        }
+    }
+    Ok(expanded)
+}
+
+fn expand(text: &str, variables: &Variables, last_status: i32) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("$((") {
+        expanded.push_str(&rest[..start]);
+        let inner = &rest[start + 3..];
+        let end = closing(inner).ok_or_else(|| anyhow!("{}: missing `))'", &rest[start..]))?;
+        // The nested expansions and the $ variables are replaced before evaluating it
+        let expression = expand(&inner[..end], variables, last_status)?;
+        let expression = variables::expand(&expression, variables, last_status);
+        expanded.push_str(&evaluate(&expression, variables)?.to_string());
+        rest = &inner[end + 2..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// Position of the )) closing the expression, skipping the parentheses inside it.
+fn closing(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return text[i..].starts_with("))").then_some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Evaluates the integer expression with + - * / % and parentheses, with the usual precedence.
+// A name is the value of the variable, 0 if it's unset or empty.
+pub(crate) fn evaluate(expression: &str, variables: &Variables) -> Result<i64> {
+    let mut parser = Parser {
+        chars: expression.chars().peekable(),
+        variables,
+    };
+    let value = parser.sum();
+    let value = match parser.next_char() {
+        None => value,
+        Some(c) => value.and(Err(anyhow!("syntax error: operand expected near `{c}'"))),
+    };
+    value.map_err(|e| anyhow!("{}: {e}", expression.trim()))
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    variables: &'a Variables,
+}
+
+impl Parser<'_> {
+    // The next character that is not whitespace, without taking it.
+    fn next_char(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    // Terms joined by + and -
+    fn sum(&mut self) -> Result<i64> {
+        let mut value = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.next_char() {
+            self.chars.next();
+            let operand = self.product()?;
+            value = match op {
+                '+' => value.wrapping_add(operand),
+                _ => value.wrapping_sub(operand),
+            };
+        }
+        Ok(value)
+    }
+
+    // Factors joined by *, / and %
+    fn product(&mut self) -> Result<i64> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.next_char() {
+            self.chars.next();
+            let operand = self.factor()?;
+            value = match op {
+                '*' => value.wrapping_mul(operand),
+                _ if operand == 0 => return Err(anyhow!("division by 0")),
+                '/' => value.wrapping_div(operand),
+                _ => value.wrapping_rem(operand),
+            };
+        }
+        Ok(value)
+    }
+
+    // A number, a variable, a sign before a factor or a parenthesized expression
+    fn factor(&mut self) -> Result<i64> {
+        match self.next_char() {
+            Some('-') => {
+                self.chars.next();
+                Ok(self.factor()?.wrapping_neg())
+            }
+            Some('+') => {
+                self.chars.next();
+                self.factor()
+            }
+            Some('(') => {
+                self.chars.next();
+                let value = self.sum()?;
+                match self.next_char() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(value)
+                    }
+                    _ => Err(anyhow!("missing `)'")),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let number = self.take_while(|c| c.is_ascii_alphanumeric());
+                number
+                    .parse()
+                    .map_err(|_| anyhow!("{number}: value too great for base"))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                let value = self.variables.get(&name).unwrap_or_default();
+                match value.trim() {
+                    "" => Ok(0),
+                    value => value
+                        .parse()
+                        .map_err(|_| anyhow!("{name}: invalid number `{value}'")),
+                }
+            }
+            Some(c) => Err(anyhow!("syntax error: operand expected near `{c}'")),
+            None => Err(anyhow!("syntax error: operand expected")),
+        }
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.chars.next_if(|c| accept(*c)) {
+            taken.push(c);
+        }
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() -> Result<()> {
+        let variables = Variables::default();
+        assert_eq!(14, evaluate("2 + 3 * 4", &variables)?);
+        assert_eq!(20, evaluate("(2 + 3) * 4", &variables)?);
+        assert_eq!(1, evaluate("7 % 3", &variables)?);
+        assert_eq!(-3, evaluate("7 / -2", &variables)?);
+        assert_eq!(3, evaluate("1 - -2", &variables)?);
+        assert_eq!(2, evaluate("  10-4-4  ", &variables)?);
+        assert!(evaluate("2 +", &variables).is_err());
+        assert!(evaluate("2 3", &variables).is_err());
+        assert!(evaluate("(2", &variables).is_err());
+        assert!(evaluate("", &variables).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let variables = Variables::default();
+        let error = evaluate("1 / (2 - 2)", &variables).unwrap_err();
+        assert_eq!("1 / (2 - 2): division by 0", error.to_string());
+        assert!(evaluate("5 % 0", &variables).is_err());
+    }
+
+    #[test]
+    fn test_variables() -> Result<()> {
+        let mut variables = Variables::default();
+        variables.set("x", "6");
+        variables.set("word", "abc");
+        assert_eq!(13, evaluate("x * 2 + 1", &variables)?);
+        assert_eq!(0, evaluate("unset_arith_var", &variables)?);
+        assert!(evaluate("word + 1", &variables).is_err());
+
+        assert_eq!("a14b", expand("a$((2 + 3 * 4))b", &variables, 0)?);
+        assert_eq!(
+            "12 7",
+            expand("$(($x * 2)) $((1 + $((x))))", &variables, 0)?
+        );
+        assert!(expand("$((1 + 2)", &variables, 0).is_err());
+        Ok(())
+    }
+}
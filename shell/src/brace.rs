@@ -0,0 +1,165 @@
+use crate::tokenizer::{Quoting, Word};
+
+// A character of a word with the quoting it had.
+type QuotedChar = (char, Quoting);
+
+// Expands the braces of the word into the words they generate, like a{b,c}d into abd acd
+// or {1..3} into 1 2 3. Nested braces expand recursively. Only unquoted braces expand,
+// and a brace without a comma or a range stays literal, like {a}.
+pub(crate) fn expand(word: &Word) -> Vec<Word> {
+    let chars: Vec<QuotedChar> = word
+        .parts
+        .iter()
+        .flat_map(|part| part.text.chars().map(move |c| (c, part.quoting)))
+        .collect();
+    if chars.is_empty() {
+        return vec![word.clone()];
+    }
+    expand_chars(&chars)
+        .into_iter()
+        // Like bash, an alternative that expands to nothing is not a word
+        .filter(|chars| !chars.is_empty())
+        .map(|chars| {
+            let mut word = Word::default();
+            for (c, quoting) in chars {
+                word.push(c, quoting);
+            }
+            word
+        })
+        .collect()
+}
+
+fn expand_chars(chars: &[QuotedChar]) -> Vec<Vec<QuotedChar>> {
+    for open in 0..chars.len() {
+        if chars[open] != ('{', Quoting::Unquoted) {
+            continue;
+        }
+        let Some((close, commas)) = closing_brace(chars, open) else {
+            continue;
+        };
+        let alternatives = if commas.is_empty() {
+            match range(&chars[open + 1..close]) {
+                Some(range) => range,
+                None => continue,
+            }
+        } else {
+            let mut alternatives = vec![];
+            let mut start = open + 1;
+            for end in commas.into_iter().chain([close]) {
+                alternatives.push(chars[start..end].to_vec());
+                start = end + 1;
+            }
+            alternatives
+        };
+        // The expanded words can still have braces, nested or after this one
+        return alternatives
+            .into_iter()
+            .flat_map(|alternative| {
+                let mut expanded = chars[..open].to_vec();
+                expanded.extend(alternative);
+                expanded.extend_from_slice(&chars[close + 1..]);
+                expand_chars(&expanded)
+            })
+            .collect();
+    }
+    vec![chars.to_vec()]
+}
+
+// The position of the } closing the brace, and of the commas separating its alternatives.
+fn closing_brace(chars: &[QuotedChar], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    for (i, &(c, quoting)) in chars.iter().enumerate().skip(open) {
+        if quoting != Quoting::Unquoted {
+            continue;
+        }
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((i, commas));
+                }
+            }
+            ',' if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// The numbers of a range like 1..3, counting down if the end is lower than the start.
+fn range(chars: &[QuotedChar]) -> Option<Vec<Vec<QuotedChar>>> {
+    if chars
+        .iter()
+        .any(|&(_, quoting)| quoting != Quoting::Unquoted)
+    {
+        return None;
+    }
+    let text: String = chars.iter().map(|&(c, _)| c).collect();
+    let (start, end) = text.split_once("..")?;
+    let (start, end): (i64, i64) = (start.parse().ok()?, end.parse().ok()?);
+    let numbers: Vec<i64> = match start <= end {
+        true => (start..=end).collect(),
+        false => (end..=start).rev().collect(),
+    };
+    Some(
+        numbers
+            .into_iter()
+            .map(|n| {
+                n.to_string()
+                    .chars()
+                    .map(|c| (c, Quoting::Unquoted))
+                    .collect()
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{tokenize, Token};
+
+    // The literal words the braces of the word expand to
+    fn expand_line(input: &str) -> Vec<String> {
+        let tokens = tokenize(input).unwrap();
+        let Some(Token::Word(word)) = tokens.first() else {
+            panic!("not a word: {input}");
+        };
+        expand(word).iter().map(Word::literal).collect()
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(vec!["a.txt", "b.txt", "c.txt"], expand_line("{a,b,c}.txt"));
+        assert_eq!(vec!["xay", "xby"], expand_line("x{a,b}y"));
+        assert_eq!(vec!["a1", "a2", "b1", "b2"], expand_line("{a,b}{1,2}"));
+        assert_eq!(vec!["a", "ab"], expand_line("a{,b}"));
+    }
+
+    #[test]
+    fn test_nested() {
+        assert_eq!(vec!["xay", "xby", "xcy"], expand_line("x{a,{b,c}}y"));
+        assert_eq!(vec!["a1", "a2", "b"], expand_line("{a{1,2},b}"));
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(vec!["1", "2", "3"], expand_line("{1..3}"));
+        assert_eq!(vec!["f3", "f2", "f1"], expand_line("f{3..1}"));
+        assert_eq!(vec!["-1", "0", "1"], expand_line("{-1..1}"));
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(vec!["{a,b}"], expand_line("'{a,b}'"));
+        assert_eq!(vec!["{a,b}"], expand_line("\"{a,b}\""));
+        assert_eq!(vec!["{a,b}"], expand_line("\\{a,b}"));
+        assert_eq!(vec!["{a}"], expand_line("{a}"));
+        assert_eq!(vec!["{a,b"], expand_line("{a,b"));
+        assert_eq!(vec!["{1..x}"], expand_line("{1..x}"));
+        // Only the quoted comma is literal
+        assert_eq!(vec!["a,b", "c"], expand_line("{a',b',c}"));
+    }
+}
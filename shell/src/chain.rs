@@ -0,0 +1,509 @@
+use anyhow::{anyhow, Result};
+
+use crate::tokenizer::{Operator, RedirectOp, Token, Word};
+
+// Operator joining a command with the previous one in the line.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Connector {
+    // &&: runs only if the previous command succeeded
+    And,
+    // ||: runs only if the previous command failed
+    Or,
+    // ;: always runs after the previous command
+    Seq,
+}
+
+// Redirection of a file descriptor of the command, to or from the target file.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Redirect {
+    pub(crate) fd: u32,
+    pub(crate) op: RedirectOp,
+    pub(crate) target: Word,
+}
+
+// A command with its words and redirections, before any expansion.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct SimpleCommand {
+    pub(crate) words: Vec<Word>,
+    pub(crate) redirects: Vec<Redirect>,
+    // Ended with &
+    pub(crate) background: bool,
+}
+
+impl SimpleCommand {
+    fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.redirects.is_empty()
+    }
+}
+
+// Commands grouped with ( ), they run in a subshell.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Subshell {
+    pub(crate) commands: Chain,
+    pub(crate) redirects: Vec<Redirect>,
+    // Ended with &
+    pub(crate) background: bool,
+}
+
+// Commands joined with |, the output of each one is the input of the next one.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Pipeline {
+    // Simple commands or subshells, at least two
+    pub(crate) stages: Vec<Command>,
+    // Ended with &
+    pub(crate) background: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Command {
+    Simple(SimpleCommand),
+    Subshell(Subshell),
+    Pipeline(Pipeline),
+}
+
+impl Command {
+    fn set_background(&mut self) {
+        match self {
+            Command::Simple(command) => command.background = true,
+            Command::Subshell(subshell) => subshell.background = true,
+            Command::Pipeline(pipeline) => pipeline.background = true,
+        }
+    }
+}
+
+// The commands joined by operators, the first command has no connector.
+pub(crate) type Chain = Vec<(Option<Connector>, Command)>;
+
+fn unexpected(token: &str) -> anyhow::Error {
+    anyhow!("syntax error near unexpected token `{token}'")
+}
+
+// Splits the tokens of the line in the commands joined by operators.
+// The background operator ends its command like ;
+pub(crate) fn split_chain(tokens: Vec<Token>) -> Result<Chain> {
+    parse_chain(&mut tokens.into_iter(), false)
+}
+
+// Parses the commands until the end of the tokens, or until the ) closing the subshell if nested.
+fn parse_chain(tokens: &mut impl Iterator<Item = Token>, nested: bool) -> Result<Chain> {
+    let mut commands = vec![];
+    let mut connector = None;
+    let mut command = SimpleCommand::default();
+    // The subshell just closed, only redirections and operators can follow it
+    let mut subshell = None;
+    // The commands before the | of the pipeline being parsed
+    let mut stages = vec![];
+    let mut closed = false;
+    while let Some(token) = tokens.next() {
+        let (next, operator) = match token {
+            Token::Word(word) if subshell.is_some() => return Err(unexpected(&word.literal())),
+            Token::Word(word) => {
+                command.words.push(word);
+                continue;
+            }
+            Token::Redirect { fd, op } => {
+                let Some(Token::Word(target)) = tokens.next() else {
+                    return Err(unexpected("newline"));
+                };
+                let fd = fd.unwrap_or(match op {
+                    RedirectOp::Input | RedirectOp::HereDoc { .. } | RedirectOp::DupInput => 0,
+                    RedirectOp::Output | RedirectOp::Append | RedirectOp::DupOutput => 1,
+                });
+                let redirect = Redirect { fd, op, target };
+                match &mut subshell {
+                    Some(Subshell { redirects, .. }) => redirects.push(redirect),
+                    None => command.redirects.push(redirect),
+                }
+                continue;
+            }
+            Token::Operator(Operator::OpenParen) => {
+                if !command.is_empty() || subshell.is_some() {
+                    return Err(unexpected("("));
+                }
+                subshell = Some(Subshell {
+                    commands: parse_chain(tokens, true)?,
+                    redirects: vec![],
+                    background: false,
+                });
+                continue;
+            }
+            Token::Operator(Operator::CloseParen) if nested => {
+                closed = true;
+                break;
+            }
+            Token::Operator(Operator::CloseParen) => return Err(unexpected(")")),
+            Token::Operator(Operator::And) => (Connector::And, "&&"),
+            Token::Operator(Operator::Or) => (Connector::Or, "||"),
+            Token::Operator(Operator::Semicolon) => (Connector::Seq, ";"),
+            Token::Operator(Operator::Background) => (Connector::Seq, "&"),
+            Token::Operator(Operator::Pipe) => {
+                let Some(stage) = finish(&mut command, &mut subshell, &mut vec![]) else {
+                    return Err(unexpected("|"));
+                };
+                stages.push(stage);
+                continue;
+            }
+        };
+        let Some(mut finished) = finish(&mut command, &mut subshell, &mut stages) else {
+            return Err(unexpected(operator));
+        };
+        if operator == "&" {
+            finished.set_background();
+        }
+        commands.push((connector, finished));
+        connector = Some(next);
+    }
+    if nested && !closed {
+        return Err(anyhow!("syntax error: unexpected end of line"));
+    }
+    match finish(&mut command, &mut subshell, &mut stages) {
+        Some(finished) => commands.push((connector, finished)),
+        // A trailing ; is valid, there's just no command after it
+        None if stages.is_empty() && matches!(connector, None | Some(Connector::Seq)) => {}
+        None => return Err(anyhow!("syntax error: unexpected end of line")),
+    }
+    // Empty ( )
+    if nested && commands.is_empty() {
+        return Err(unexpected(")"));
+    }
+    Ok(commands)
+}
+
+// Takes the command being parsed, if there is one, as the last stage of the pipeline
+// when there are stages before it.
+fn finish(
+    command: &mut SimpleCommand,
+    subshell: &mut Option<Subshell>,
+    stages: &mut Vec<Command>,
+) -> Option<Command> {
+    let finished = match subshell.take() {
+        Some(subshell) => Command::Subshell(subshell),
+        None if command.is_empty() => return None,
+        None => Command::Simple(std::mem::take(command)),
+    };
+    if stages.is_empty() {
+        return Some(finished);
+    }
+    stages.push(finished);
+    Some(Command::Pipeline(Pipeline {
+        stages: std::mem::take(stages),
+        background: false,
+    }))
+}
+
+// Runs the commands of the line left to right, skipping the ones whose connector
+// doesn't match the status of the previous command. The command is run knowing if its
+// status is tested by a && or || after it.
+// Returns the status of the last command executed.
+pub(crate) fn run_chain<T>(
+    commands: &[(Option<Connector>, T)],
+    mut run: impl FnMut(&T, bool) -> Result<i32>,
+) -> Result<i32> {
+    let mut status = 0;
+    for (i, (connector, command)) in commands.iter().enumerate() {
+        let skip = match connector {
+            Some(Connector::And) => status != 0,
+            Some(Connector::Or) => status == 0,
+            Some(Connector::Seq) | None => false,
+        };
+        let tested = commands
+            .get(i + 1)
+            .is_some_and(|(next, _)| matches!(next, Some(Connector::And | Connector::Or)));
+        if !skip {
+            status = run(command, tested)?;
+        }
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    // The command as its literal words, with a trailing & if it runs in the background
+    fn describe(command: &Command) -> String {
+        let (mut words, background): (Vec<String>, bool) = match command {
+            Command::Simple(command) => (
+                command.words.iter().map(Word::literal).collect(),
+                command.background,
+            ),
+            Command::Subshell(subshell) => {
+                let inner: Vec<String> = subshell
+                    .commands
+                    .iter()
+                    .map(|(_, command)| describe(command))
+                    .collect();
+                (
+                    vec![format!("({})", inner.join(" ; "))],
+                    subshell.background,
+                )
+            }
+            Command::Pipeline(pipeline) => {
+                let stages: Vec<String> = pipeline.stages.iter().map(describe).collect();
+                (vec![stages.join(" | ")], pipeline.background)
+            }
+        };
+        if background {
+            words.push("&".to_string());
+        }
+        words.join(" ")
+    }
+
+    fn split(input: &str) -> Result<Vec<(Option<Connector>, String)>> {
+        let commands = split_chain(tokenize(input)?)?;
+        Ok(commands
+            .iter()
+            .map(|(connector, command)| (*connector, describe(command)))
+            .collect())
+    }
+
+    // Runs the line recording the commands executed, `false` is the only failing command.
+    fn run_line(input: &str) -> Result<(i32, Vec<String>)> {
+        let mut executed = vec![];
+        let commands = split(input)?;
+        let status = run_chain(&commands, |command, _| {
+            executed.push(command.clone());
+            Ok(if command == "false" { 1 } else { 0 })
+        })?;
+        Ok((status, executed))
+    }
+
+    fn commands(pairs: &[(Option<Connector>, &str)]) -> Vec<(Option<Connector>, String)> {
+        pairs
+            .iter()
+            .map(|(connector, command)| (*connector, command.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_chain() -> Result<()> {
+        assert_eq!(commands(&[(None, "echo hello")]), split(" echo hello ")?);
+        assert!(split("")?.is_empty());
+        assert_eq!(
+            commands(&[(None, "true"), (Some(Connector::And), "echo yes")]),
+            split("true && echo yes")?
+        );
+        assert_eq!(
+            commands(&[
+                (None, "a"),
+                (Some(Connector::And), "b"),
+                (Some(Connector::And), "c")
+            ]),
+            split("a&&b &&c")?
+        );
+        // Quoted operators are not split
+        assert_eq!(commands(&[(None, "echo a && b")]), split("echo 'a && b'")?);
+        assert_eq!(
+            commands(&[(None, "echo a && b")]),
+            split("echo \"a && b\"")?
+        );
+        assert_eq!(
+            commands(&[(None, "echo a && b")]),
+            split("echo a \\&\\& b")?
+        );
+
+        assert!(split("&& echo").is_err());
+        assert!(split("echo &&").is_err());
+        assert!(split("echo && && echo").is_err());
+        assert!(split("|| echo").is_err());
+
+        assert_eq!(
+            commands(&[
+                (None, "false"),
+                (Some(Connector::Or), "echo rescued"),
+                (Some(Connector::And), "echo again")
+            ]),
+            split("false || echo rescued && echo again")?
+        );
+        assert_eq!(commands(&[(None, "echo a || b")]), split("echo 'a || b'")?);
+
+        assert_eq!(
+            commands(&[(None, "echo a"), (Some(Connector::Seq), "echo b")]),
+            split("echo a ; echo b")?
+        );
+        assert_eq!(commands(&[(None, "echo a")]), split("echo a;")?);
+        // Quoted semicolons are literal
+        assert_eq!(commands(&[(None, "echo a;b")]), split("echo 'a;b'")?);
+        assert_eq!(commands(&[(None, "echo a ; b")]), split("echo \"a ; b\"")?);
+        assert!(split("; echo").is_err());
+
+        assert_eq!(commands(&[(None, "sleep 1 &")]), split("sleep 1 &")?);
+        assert_eq!(
+            commands(&[(None, "sleep 1 &"), (Some(Connector::Seq), "echo a")]),
+            split("sleep 1& echo a")?
+        );
+        assert_eq!(commands(&[(None, "echo a&")]), split("echo 'a&'")?);
+        assert_eq!(commands(&[(None, "echo a&")]), split("echo a\\&")?);
+        assert!(split("& echo").is_err());
+        assert!(split("echo a ;; echo b").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_pipelines() -> Result<()> {
+        assert_eq!(commands(&[(None, "ls -l | wc -l")]), split("ls -l|wc -l")?);
+        assert_eq!(
+            commands(&[(None, "a | b | c"), (Some(Connector::And), "d")]),
+            split("a | b | c && d")?
+        );
+        assert_eq!(
+            commands(&[(None, "(a ; b) | c &"), (Some(Connector::Seq), "d")]),
+            split("(a; b) | c & d")?
+        );
+        assert_eq!(commands(&[(None, "a | (b)")]), split("a | (b)")?);
+        // Each stage keeps its redirections
+        let commands = split_chain(tokenize("a 2> err | b > out")?)?;
+        let Command::Pipeline(pipeline) = &commands[0].1 else {
+            panic!("not a pipeline");
+        };
+        assert_eq!(2, pipeline.stages.len());
+
+        assert!(split("| a").is_err());
+        assert!(split("a |").is_err());
+        assert!(split("a | | b").is_err());
+        assert!(split("a | && b").is_err());
+        assert!(split("a | ;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_redirects() -> Result<()> {
+        let commands = split_chain(tokenize("echo a > out 2>> err b < in")?)?;
+        assert_eq!(1, commands.len());
+        let Command::Simple(command) = &commands[0].1 else {
+            panic!("not a simple command");
+        };
+        let words: Vec<String> = command.words.iter().map(Word::literal).collect();
+        assert_eq!(vec!["echo", "a", "b"], words);
+        let redirects: Vec<(u32, RedirectOp, String)> = command
+            .redirects
+            .iter()
+            .map(|redirect| (redirect.fd, redirect.op, redirect.target.literal()))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, RedirectOp::Output, "out".to_string()),
+                (2, RedirectOp::Append, "err".to_string()),
+                (0, RedirectOp::Input, "in".to_string())
+            ],
+            redirects
+        );
+
+        // Only redirections is still a command
+        assert_eq!(1, split_chain(tokenize("> file")?)?.len());
+        assert!(split_chain(tokenize("echo >")?).is_err());
+        assert!(split_chain(tokenize("echo > ;")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_subshells() -> Result<()> {
+        assert_eq!(
+            commands(&[(None, "(cd /tmp ; ls)")]),
+            split("(cd /tmp; ls)")?
+        );
+        assert_eq!(
+            commands(&[(None, "(a ; (b))"), (Some(Connector::And), "c")]),
+            split("( a; (b) ) && c")?
+        );
+        assert_eq!(commands(&[(None, "(sleep 1) &")]), split("(sleep 1) &")?);
+        let commands = split_chain(tokenize("(echo a) > out")?)?;
+        let Command::Subshell(subshell) = &commands[0].1 else {
+            panic!("not a subshell");
+        };
+        assert_eq!(1, subshell.redirects.len());
+
+        assert!(split("(echo a").is_err());
+        assert!(split("echo a)").is_err());
+        assert!(split("()").is_err());
+        assert!(split("echo (a)").is_err());
+        assert!(split("(a) b").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_and() -> Result<()> {
+        assert_eq!(
+            (0, vec!["true".into(), "echo yes".into()]),
+            run_line("true && echo yes")?
+        );
+        assert_eq!((1, vec!["false".into()]), run_line("false && echo no")?);
+        assert_eq!(
+            (1, vec!["true".into(), "false".into()]),
+            run_line("true && false && echo no")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_or() -> Result<()> {
+        assert_eq!((0, vec!["true".into()]), run_line("true || echo no")?);
+        assert_eq!(
+            (0, vec!["false".into(), "echo yes".into()]),
+            run_line("false || echo yes")?
+        );
+        // Left to right evaluation, the skipped commands keep the previous status
+        assert_eq!(
+            (
+                0,
+                vec!["false".into(), "echo rescued".into(), "echo again".into()]
+            ),
+            run_line("false || echo rescued && echo again")?
+        );
+        assert_eq!(
+            (0, vec!["true".into(), "echo again".into()]),
+            run_line("true || echo skipped && echo again")?
+        );
+        assert_eq!(
+            (1, vec!["false".into(), "false".into()]),
+            run_line("false && echo skipped || false")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tested() -> Result<()> {
+        let commands = split("a && b || c ; d ; e || f")?;
+        let mut tested = vec![];
+        run_chain(&commands, |command, is_tested| {
+            tested.push((command.clone(), is_tested));
+            Ok(if command == "b" { 1 } else { 0 })
+        })?;
+        let expected = [
+            ("a", true),
+            ("b", true),
+            ("c", false),
+            ("d", false),
+            ("e", true),
+        ];
+        let expected: Vec<(String, bool)> = expected
+            .iter()
+            .map(|(command, is_tested)| (command.to_string(), *is_tested))
+            .collect();
+        assert_eq!(expected, tested);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seq() -> Result<()> {
+        assert_eq!(
+            (0, vec!["echo a".into(), "echo b".into()]),
+            run_line("echo a ; echo b")?
+        );
+        assert_eq!(
+            (0, vec!["false".into(), "echo b".into()]),
+            run_line("false; echo b")?
+        );
+        assert_eq!(
+            (1, vec!["echo a".into(), "false".into()]),
+            run_line("echo a ; false")?
+        );
+        // ; has lower precedence than && and ||
+        assert_eq!(
+            (0, vec!["false".into(), "echo c".into()]),
+            run_line("false && echo b ; echo c")?
+        );
+        Ok(())
+    }
+}
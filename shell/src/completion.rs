@@ -0,0 +1,112 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use crate::builtin_names;
+use crate::path::executable_entries;
+
+// Rustyline helper providing the tab completion of the repl.
+#[derive(Default)]
+pub(crate) struct ShellHelper {
+    // PATH of the shell the commands are completed from, set by the repl before reading
+    pub(crate) path: String,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let word = &line[..pos];
+        // Only the first word (the command) is completed
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+        Ok((0, complete(word, &self.path)))
+    }
+}
+
+// Returns the replacements for the command prefix, from the builtins and the executables in the PATH.
+// A single match is completed with a trailing space, with multiple matches rustyline
+// completes the common prefix (and lists them on the second tab).
+pub(crate) fn complete(prefix: &str, path: &str) -> Vec<String> {
+    let builtins = builtin_names().into_iter().map(String::from);
+    let executables =
+        executable_entries(path).filter_map(|entry| entry.file_name().into_string().ok());
+    let mut candidates: Vec<String> = builtins
+        .chain(executables)
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    if let [single] = candidates.as_mut_slice() {
+        single.push(' ');
+    }
+    candidates
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(vec!["echo "], complete("ec", ""));
+        assert_eq!(vec!["exit "], complete("exi", ""));
+        assert_eq!(vec!["echo", "exec", "exit", "export"], complete("e", ""));
+        assert_eq!(vec!["type "], complete("type", ""));
+        assert!(complete("xyz", "").is_empty());
+    }
+
+    #[test]
+    fn test_complete_path() -> anyhow::Result<()> {
+        let dir = env::temp_dir().join(format!("shell_complete_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("custom_dir"))?;
+        // Same name as a builtin
+        for name in ["custom_cmd", "custom_other", "echo"] {
+            fs::write(dir.join(name), "")?;
+            fs::set_permissions(dir.join(name), fs::Permissions::from_mode(0o755))?;
+        }
+        // Not executable
+        fs::write(dir.join("custom_data"), "")?;
+        fs::set_permissions(dir.join("custom_data"), fs::Permissions::from_mode(0o644))?;
+        let path = format!("/does/not/exist:{}", dir.display());
+
+        assert_eq!(vec!["custom_cmd "], complete("custom_c", &path));
+        assert_eq!(vec!["custom_cmd", "custom_other"], complete("cus", &path));
+        assert_eq!(vec!["echo "], complete("ech", &path));
+        // Neither the directory nor the file that can't be executed
+        assert!(complete("custom_d", &path).is_empty());
+
+        // The file that can't be executed comes first in PATH, the executable one still completes
+        let later = dir.join("later");
+        fs::create_dir_all(&later)?;
+        fs::write(later.join("custom_data"), "")?;
+        fs::set_permissions(later.join("custom_data"), fs::Permissions::from_mode(0o755))?;
+        let path = format!("{}:{}", dir.display(), later.display());
+        assert_eq!(vec!["custom_data "], complete("custom_d", &path));
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+// Evaluates the expression of the test builtin.
+// Supports the file tests -e -f -d, the string tests -n -z = !=, the integer
+// comparisons -eq -ne -lt -le -gt -ge and negation with !.
+pub(crate) fn evaluate(args: &[&str]) -> Result<bool> {
+    match args {
+        [] => Ok(false),
+        ["!", rest @ ..] => evaluate(rest).map(|result| !result),
+        [string] => Ok(!string.is_empty()),
+        [op, operand] => unary(op, operand),
+        [left, op, right] => binary(left, op, right),
+        _ => Err(anyhow!("too many arguments")),
+    }
+}
+
+fn unary(op: &str, operand: &str) -> Result<bool> {
+    let path = Path::new(operand);
+    match op {
+        "-e" => Ok(path.exists()),
+        "-f" => Ok(path.is_file()),
+        "-d" => Ok(path.is_dir()),
+        "-n" => Ok(!operand.is_empty()),
+        "-z" => Ok(operand.is_empty()),
+        _ => Err(anyhow!("{op}: unary operator expected")),
+    }
+}
+
+fn binary(left: &str, op: &str, right: &str) -> Result<bool> {
+    match op {
+        "=" | "==" => return Ok(left == right),
+        "!=" => return Ok(left != right),
+        _ => {}
+    }
+    let integer = |operand: &str| {
+        operand
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| anyhow!("{operand}: integer expression expected"))
+    };
+    let (left, right) = (integer(left)?, integer(right)?);
+    match op {
+        "-eq" => Ok(left == right),
+        "-ne" => Ok(left != right),
+        "-lt" => Ok(left < right),
+        "-le" => Ok(left <= right),
+        "-gt" => Ok(left > right),
+        "-ge" => Ok(left >= right),
+        _ => Err(anyhow!("{op}: binary operator expected")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_files() -> Result<()> {
+        let dir = env::temp_dir();
+        let file = dir.join(format!("shell_condition_test_{}", std::process::id()));
+        std::fs::write(&file, "")?;
+        let file = file.display().to_string();
+        let dir = dir.display().to_string();
+
+        assert!(evaluate(&["-e", &file])?);
+        assert!(evaluate(&["-f", &file])?);
+        assert!(!evaluate(&["-d", &file])?);
+        assert!(evaluate(&["-d", &dir])?);
+        assert!(!evaluate(&["-f", &dir])?);
+        std::fs::remove_file(&file)?;
+        assert!(!evaluate(&["-e", &file])?);
+        assert!(evaluate(&["!", "-e", &file])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strings() -> Result<()> {
+        assert!(evaluate(&["a", "=", "a"])?);
+        assert!(!evaluate(&["a", "=", "b"])?);
+        assert!(evaluate(&["a", "!=", "b"])?);
+        assert!(evaluate(&["-z", ""])?);
+        assert!(evaluate(&["-n", "a"])?);
+        assert!(evaluate(&["a"])?);
+        assert!(!evaluate(&[""])?);
+        assert!(!evaluate(&[])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_integers() -> Result<()> {
+        assert!(evaluate(&["2", "-lt", "10"])?);
+        assert!(!evaluate(&["2", "-gt", "10"])?);
+        assert!(evaluate(&["-3", "-eq", "-3"])?);
+        assert!(evaluate(&["3", "-ge", "3"])?);
+        assert!(evaluate(&["a", "-eq", "1"]).is_err());
+        assert!(evaluate(&["1", "-foo", "1"]).is_err());
+        assert!(evaluate(&["-x", "a"]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::tokenizer::{Quoting, Word};
+
+// Returns if the word has an unquoted *, ? or [, so its fields are expanded as patterns.
+// The quoted wildcards of the same word are then patterns too.
+pub(crate) fn has_pattern(word: &Word) -> bool {
+    word.parts
+        .iter()
+        .any(|part| part.quoting == Quoting::Unquoted && part.text.contains(['*', '?', '[']))
+}
+
+// Expands the pattern into the sorted paths matching it. Without any match the pattern
+// is kept as it is, like sh.
+pub(crate) fn expand(pattern: &str) -> Vec<String> {
+    let paths = matching_paths(pattern, list_dir);
+    if paths.is_empty() {
+        return vec![pattern.to_string()];
+    }
+    paths
+}
+
+// The names of the entries of the directory.
+fn list_dir(dir: &str) -> io::Result<Vec<String>> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    Ok(fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect())
+}
+
+// Matches the pattern one path component at a time, listing the directories with the list.
+// The matches are collected best-effort: a directory that can't be listed (like one without
+// the read permission) has no matches, instead of failing the whole expansion.
+fn matching_paths(pattern: &str, list: impl Fn(&str) -> io::Result<Vec<String>>) -> Vec<String> {
+    // Each path is a prefix of a match, ending with / unless it's the current directory
+    let (mut paths, pattern) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = pattern.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        // Repeated and trailing slashes are already in the prefixes
+        if component.is_empty() {
+            continue;
+        }
+        let last = i == components.len() - 1;
+        let mut next = vec![];
+        for prefix in &paths {
+            let names = match is_pattern(component) {
+                true => match list(prefix) {
+                    Ok(names) => names
+                        .into_iter()
+                        .filter(|name| matches(component, name))
+                        .collect(),
+                    Err(_) => continue,
+                },
+                false => vec![unescape(component)],
+            };
+            for name in names {
+                let path = format!("{prefix}{name}");
+                if last {
+                    if Path::new(&path).symlink_metadata().is_ok() {
+                        next.push(path);
+                    }
+                } else if Path::new(&path).is_dir() {
+                    next.push(path + "/");
+                }
+            }
+        }
+        paths = next;
+    }
+    paths.sort();
+    paths
+}
+
+fn is_pattern(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+// Removes the backslashes escaping the wildcards.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// Returns if the name matches the pattern: * matches any text, ? any character and [...] one
+// of the characters in the brackets, with ranges like a-z and negated with ! or ^.
+// A leading . is only matched explicitly, so hidden files are skipped by *.
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_chars(&pattern, &name)
+}
+
+fn matches_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        // Any number of characters, trying the shortest first
+        Some('*') => (0..=name.len()).any(|skip| matches_chars(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches_chars(&pattern[1..], &name[1..]),
+        Some('[') => match (bracket(&pattern[1..]), name.first()) {
+            (Some((matched, len)), Some(c)) => {
+                matched(*c) && matches_chars(&pattern[len + 1..], &name[1..])
+            }
+            (Some(_), None) => false,
+            // Without the closing bracket the [ is literal
+            (None, _) => name.first() == Some(&'[') && matches_chars(&pattern[1..], &name[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && matches_chars(&pattern[2..], &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && matches_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+// Parses the bracket expression after the [, returning what it matches and its length
+// including the closing ]. A ] right after the [ (or the negation) is a literal one.
+fn bracket(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, usize)> {
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    let start = usize::from(negated);
+    let close = start + 1 + pattern.get(start + 1..)?.iter().position(|c| *c == ']')?;
+    let set = &pattern[start..close];
+    let matched = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == '-' {
+                found |= (set[i]..=set[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                found |= set[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matched, close + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+        assert!(matches("[abc]x", "bx"));
+        assert!(matches("[a-c]x", "cx"));
+        assert!(!matches("[!a-c]x", "cx"));
+        assert!(matches("[^a-c]x", "dx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[x", "[x"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        // Hidden files only match an explicit .
+        assert!(!matches("*", ".hidden"));
+        assert!(matches(".*", ".hidden"));
+    }
+
+    #[test]
+    fn test_unreadable_directories() -> anyhow::Result<()> {
+        let root = env::temp_dir().join(format!("shell_glob_test_{}", std::process::id()));
+        for dir in ["readable", "unreadable", "empty"] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        fs::write(root.join("readable/x"), "")?;
+        fs::write(root.join("unreadable/x"), "")?;
+        fs::write(root.join("file"), "")?;
+        let root = root.display().to_string();
+        // Listing fails like a directory without the read permission (root could still read it)
+        let unreadable = format!("{root}/unreadable/");
+        let list = |dir: &str| match dir == unreadable {
+            true => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+            false => list_dir(dir),
+        };
+
+        assert_eq!(
+            vec![format!("{root}/readable/x")],
+            matching_paths(&format!("{root}/*/[x]"), list)
+        );
+        assert_eq!(
+            vec![
+                format!("{root}/empty/"),
+                format!("{root}/readable/"),
+                format!("{root}/unreadable/")
+            ],
+            matching_paths(&format!("{root}/*/"), list)
+        );
+        assert!(matching_paths(&format!("{root}/unreadable/*"), list).is_empty());
+        assert_eq!(
+            vec![format!("{root}/file")],
+            matching_paths(&format!("{root}/f?l[a-z]"), list)
+        );
+
+        // Without matches the pattern is kept
+        let pattern = format!("{root}/*/missing*");
+        assert_eq!(vec![pattern.clone()], expand(&pattern));
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
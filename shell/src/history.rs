@@ -0,0 +1,263 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+pub(crate) const DEFAULT_HISTORY_SIZE: usize = 500;
+const DEFAULT_HISTORY_FILE: &str = ".shell_history";
+
+// The file to persist the history: $HISTFILE, or ~/.shell_history by default.
+pub(crate) fn history_file() -> Option<PathBuf> {
+    match env::var_os("HISTFILE") {
+        Some(file) => Some(PathBuf::from(file)),
+        None => env::var_os("HOME").map(|home| Path::new(&home).join(DEFAULT_HISTORY_FILE)),
+    }
+}
+
+// In memory record of the entered commands.
+// When the max size is reached, the oldest entries are dropped.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct History {
+    entries: Vec<String>,
+    max_size: usize,
+    // Entries pushed in this session, not yet written to the history file
+    unsaved: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+impl History {
+    pub(crate) fn new(max_size: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            max_size,
+            unsaved: 0,
+        }
+    }
+
+    // Stores the line, empty (or only whitespace) lines are ignored.
+    pub(crate) fn push(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.max_size == 0 {
+            return;
+        }
+        if self.entries.len() == self.max_size {
+            self.entries.remove(0);
+        }
+        self.entries.push(line.to_string());
+        self.unsaved += 1;
+    }
+
+    // Loads the entries of a previous session, a missing file is just an empty history.
+    pub(crate) fn load(&mut self, path: &Path) -> Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for line in content.lines() {
+            self.push(line);
+        }
+        // Loaded entries are already in the file
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    // Appends to the file only the entries added in this session.
+    pub(crate) fn save(&mut self, path: &Path) -> Result<()> {
+        let new = self.unsaved.min(self.entries.len());
+        if new == 0 {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        for entry in &self.entries[self.entries.len() - new..] {
+            writeln!(file, "{entry}")?;
+        }
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    // Replaces the history references of the line: !! with the previous line and !n with
+    // the nth entry. None if the line has no references, they are not expanded inside single
+    // quotes or after a backslash.
+    pub(crate) fn expand(&self, line: &str) -> Result<Option<String>> {
+        let mut expanded = String::with_capacity(line.len());
+        let mut found = false;
+        let mut single_quoted = false;
+        let mut double_quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if !single_quoted => {
+                    expanded.push(c);
+                    expanded.extend(chars.next());
+                    continue;
+                }
+                '\'' if !double_quoted => single_quoted = !single_quoted,
+                '"' if !single_quoted => double_quoted = !double_quoted,
+                '!' if !single_quoted => {
+                    let reference = match chars.peek() {
+                        Some('!') => {
+                            chars.next();
+                            "!".to_string()
+                        }
+                        Some(c) if c.is_ascii_digit() => {
+                            let mut digits = String::new();
+                            while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                                digits.push(c);
+                            }
+                            digits
+                        }
+                        // A ! on its own is literal
+                        _ => {
+                            expanded.push(c);
+                            continue;
+                        }
+                    };
+                    let entry = match reference.as_str() {
+                        "!" => self.entries.last(),
+                        n => n
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|n| n.checked_sub(1))
+                            .and_then(|i| self.entries.get(i)),
+                    };
+                    let entry = entry.ok_or_else(|| anyhow!("!{reference}: event not found"))?;
+                    expanded.push_str(entry);
+                    found = true;
+                    continue;
+                }
+                _ => {}
+            }
+            expanded.push(c);
+        }
+        Ok(found.then_some(expanded))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // Lists the entries with their 1-based index, only the last ones if given.
+    pub(crate) fn format(&self, last: Option<usize>) -> String {
+        let skip = last.map_or(0, |n| self.entries.len().saturating_sub(n));
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .map(|(i, entry)| format!("{:>5}  {}\n", i + 1, entry))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let mut history = History::default();
+        assert!(history.entries().is_empty());
+        history.push("echo hello\n");
+        history.push("   ");
+        history.push("");
+        history.push("pwd");
+        assert_eq!(2, history.entries().len());
+        assert_eq!(&["echo hello", "pwd"], history.entries());
+    }
+
+    #[test]
+    fn test_push_evicts_oldest() {
+        let mut history = History::new(2);
+        history.push("one");
+        history.push("two");
+        history.push("three");
+        assert_eq!(2, history.entries().len());
+        assert_eq!(&["two", "three"], history.entries());
+
+        let mut history = History::new(0);
+        history.push("one");
+        assert!(history.entries().is_empty());
+    }
+
+    #[test]
+    fn test_format() {
+        let mut history = History::default();
+        history.push("echo one");
+        history.push("pwd");
+        history.push("history");
+        assert_eq!(
+            "    1  echo one\n    2  pwd\n    3  history\n",
+            history.format(None)
+        );
+        assert_eq!("    2  pwd\n    3  history\n", history.format(Some(2)));
+        assert_eq!(history.format(None), history.format(Some(10)));
+        assert_eq!("", history.format(Some(0)));
+    }
+
+    #[test]
+    fn test_expand() -> Result<()> {
+        let mut history = History::default();
+        assert!(history.expand("!!").is_err());
+        history.push("echo one");
+        history.push("pwd");
+        assert_eq!(Some("pwd".to_string()), history.expand("!!")?);
+        assert_eq!(Some("echo one".to_string()), history.expand("!1")?);
+        assert_eq!(
+            Some("echo one && pwd; pwd".to_string()),
+            history.expand("!1 && !2; !!")?
+        );
+        assert_eq!(Some("pwd | x".to_string()), history.expand("!! | x")?);
+
+        // Nothing to expand
+        assert_eq!(None, history.expand("echo hi!")?);
+        assert_eq!(None, history.expand("echo '!!' \\!1")?);
+        assert_eq!(
+            Some("echo \"pwd\"".to_string()),
+            history.expand("echo \"!!\"")?
+        );
+
+        let error = history.expand("echo !3").unwrap_err();
+        assert_eq!("!3: event not found", error.to_string());
+        assert!(history.expand("!0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let path = env::temp_dir().join(format!("shell_history_test_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // A missing file is an empty history
+        let mut history = History::default();
+        history.load(&path)?;
+        assert!(history.entries().is_empty());
+
+        history.push("echo one");
+        history.push("echo two");
+        history.save(&path)?;
+        // Nothing new to append
+        history.save(&path)?;
+
+        let mut reloaded = History::default();
+        reloaded.load(&path)?;
+        assert_eq!(&["echo one", "echo two"], reloaded.entries());
+
+        // Only the entries of the new session are appended
+        reloaded.push("pwd");
+        reloaded.save(&path)?;
+        let mut history = History::default();
+        history.load(&path)?;
+        assert_eq!(&["echo one", "echo two", "pwd"], history.entries());
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
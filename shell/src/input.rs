@@ -0,0 +1,75 @@
+use crate::tokenizer::{incomplete, Incomplete};
+
+// Prompt shown while the input continues in the next line
+pub(crate) const CONTINUATION_PROMPT: &str = "> ";
+
+// Reads a logical line, asking for more lines with the continuation prompt while the input
+// ends with a \ (removed with the newline), has an open quote or a here-document waiting
+// for its delimiter (the newline is kept).
+pub(crate) fn read_logical_line<E>(
+    prompt: &str,
+    mut read_line: impl FnMut(&str) -> Result<String, E>,
+) -> Result<String, E> {
+    let mut input = read_line(prompt)?;
+    while let Some(incomplete) = incomplete(&input) {
+        match incomplete {
+            Incomplete::Backslash => {
+                input.pop();
+            }
+            Incomplete::Quote(_) | Incomplete::HereDoc => input.push('\n'),
+        }
+        input.push_str(&read_line(CONTINUATION_PROMPT)?);
+    }
+    Ok(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads the lines in order, recording the prompts used
+    fn read_lines(lines: &[&str]) -> (Result<String, ()>, Vec<String>) {
+        let mut lines = lines.iter();
+        let mut prompts = vec![];
+        let input = read_logical_line("$ ", |prompt| {
+            prompts.push(prompt.to_string());
+            lines.next().map(|line| line.to_string()).ok_or(())
+        });
+        (input, prompts)
+    }
+
+    #[test]
+    fn test_backslash_continuation() {
+        let (input, prompts) = read_lines(&["echo hello \\", "world \\", "again"]);
+        assert_eq!(Ok("echo hello world again".to_string()), input);
+        assert_eq!(vec!["$ ", "> ", "> "], prompts);
+    }
+
+    #[test]
+    fn test_quote_continuation() {
+        let (input, prompts) = read_lines(&["echo 'hello", "world'"]);
+        assert_eq!(Ok("echo 'hello\nworld'".to_string()), input);
+        assert_eq!(vec!["$ ", "> "], prompts);
+
+        let (input, _) = read_lines(&["echo \"a", "b", "c\" done"]);
+        assert_eq!(Ok("echo \"a\nb\nc\" done".to_string()), input);
+    }
+
+    #[test]
+    fn test_here_doc_continuation() {
+        let (input, prompts) = read_lines(&["cat << EOF", "it's", "EOF", "not read"]);
+        assert_eq!(Ok("cat << EOF\nit's\nEOF".to_string()), input);
+        assert_eq!(vec!["$ ", "> ", "> "], prompts);
+    }
+
+    #[test]
+    fn test_no_continuation() {
+        let (input, prompts) = read_lines(&["echo hello", "not read"]);
+        assert_eq!(Ok("echo hello".to_string()), input);
+        assert_eq!(vec!["$ "], prompts);
+
+        // The input ended while continuing
+        let (input, _) = read_lines(&["echo 'hello"]);
+        assert_eq!(Err(()), input);
+    }
+}
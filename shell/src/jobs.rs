@@ -0,0 +1,322 @@
+use std::io::{self, IsTerminal};
+use std::process::Child;
+
+use anyhow::{anyhow, Result};
+
+use crate::signals;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum JobStatus {
+    Running,
+    // Suspended by a signal (SIGSTOP, SIGTSTP, ...)
+    Stopped,
+    // Finished with the exit status
+    Done(i32),
+}
+
+// A command running in the background.
+#[derive(Debug)]
+pub(crate) struct Job {
+    pub(crate) id: usize,
+    pub(crate) command: String,
+    pub(crate) child: Child,
+    pub(crate) status: JobStatus,
+}
+
+impl Job {
+    fn pid(&self) -> i32 {
+        self.child.id() as i32
+    }
+
+    // The job runs in its own process group, with the same id as the child.
+    fn pgid(&self) -> i32 {
+        self.pid()
+    }
+}
+
+// The background jobs launched by the shell.
+#[derive(Debug, Default)]
+pub(crate) struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    // Tracks the child as a new job, the job number is the next after the highest in use.
+    pub(crate) fn add(&mut self, command: &str, child: Child) -> &Job {
+        let id = self.jobs.last().map_or(1, |job| job.id + 1);
+        self.jobs.push(Job {
+            id,
+            command: command.to_string(),
+            child,
+            status: JobStatus::Running,
+        });
+        self.jobs.last().expect("job just added")
+    }
+
+    #[cfg(test)]
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Job> {
+        self.jobs.iter_mut()
+    }
+
+    // Checks without blocking which jobs changed their state, finished jobs are marked as done.
+    pub(crate) fn reap(&mut self) {
+        for job in self.jobs.iter_mut() {
+            if matches!(job.status, JobStatus::Done(_)) {
+                continue;
+            }
+            if let Some(status) = wait_job(job.pid(), libc::WNOHANG) {
+                job.status = status;
+            }
+        }
+    }
+
+    // Brings the job (or the current one) to the foreground, waiting until it finishes or stops.
+    // Returns the command and its status.
+    pub(crate) fn foreground(&mut self, id: Option<usize>) -> Result<(String, i32)> {
+        let index = self.index(id)?;
+        let job = &mut self.jobs[index];
+        let pgid = job.pgid();
+        let pid = job.pid();
+        let command = job.command.clone();
+        let terminal = io::stdin().is_terminal();
+        if terminal {
+            set_terminal_foreground(pgid);
+        }
+        // Safety: only sends a signal to the process group of the job
+        unsafe { libc::kill(-pgid, libc::SIGCONT) };
+        signals::set_foreground(Some(pid as u32));
+        // The resumed job reports that it continued first, it's waited until it finishes or
+        // stops again
+        let mut status = JobStatus::Running;
+        while status == JobStatus::Running {
+            status = wait_job(pid, 0).unwrap_or(JobStatus::Done(1));
+        }
+        signals::set_foreground(None);
+        if terminal {
+            // Safety: getpgrp has no preconditions
+            set_terminal_foreground(unsafe { libc::getpgrp() });
+        }
+        job.status = status;
+        match status {
+            JobStatus::Done(code) => {
+                self.jobs.remove(index);
+                Ok((command, code))
+            }
+            // Stopped again, it stays in the list (128 + SIGTSTP like bash)
+            _ => Ok((command, 128 + libc::SIGTSTP)),
+        }
+    }
+
+    // Resumes the stopped job (or the current one) in the background.
+    pub(crate) fn resume(&mut self, id: Option<usize>) -> Result<&Job> {
+        let index = self.index(id)?;
+        let job = &mut self.jobs[index];
+        // Safety: only sends a signal to the process group of the job
+        if unsafe { libc::kill(-job.pgid(), libc::SIGCONT) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        job.status = JobStatus::Running;
+        Ok(job)
+    }
+
+    // Waits until the job (or the current one) finishes, removing it. Returns its exit status.
+    pub(crate) fn wait(&mut self, id: Option<usize>) -> Result<i32> {
+        let index = match id {
+            Some(id) => self.jobs.iter().position(|job| job.id == id),
+            None => self.jobs.len().checked_sub(1),
+        };
+        let index = index.ok_or_else(|| match id {
+            Some(id) => anyhow!("%{id}: no such job"),
+            None => anyhow!("current: no such job"),
+        })?;
+        let code = wait_done(&mut self.jobs[index]);
+        self.jobs.remove(index);
+        Ok(code)
+    }
+
+    // Waits until all the jobs finish, removing them.
+    pub(crate) fn wait_all(&mut self) {
+        for job in self.jobs.iter_mut() {
+            wait_done(job);
+        }
+        self.jobs.clear();
+    }
+
+    // Index of the job with the id, or the current (last) job if there is none.
+    fn index(&mut self, id: Option<usize>) -> Result<usize> {
+        self.reap();
+        let index = match id {
+            Some(id) => self.jobs.iter().position(|job| job.id == id),
+            None => self.jobs.len().checked_sub(1),
+        };
+        // A finished job can't be brought back
+        index
+            .filter(|i| !matches!(self.jobs[*i].status, JobStatus::Done(_)))
+            .ok_or_else(|| match id {
+                Some(id) => anyhow!("%{id}: no such job"),
+                None => anyhow!("current: no such job"),
+            })
+    }
+
+    // Lists the jobs like bash does, the finished ones are reported once and then removed.
+    pub(crate) fn list(&mut self) -> String {
+        self.report(|_| true)
+    }
+
+    // Reports the jobs that finished since the last check, removing them. It's checked
+    // before each prompt, so the finished children don't stay as zombies until jobs runs.
+    pub(crate) fn finished(&mut self) -> String {
+        self.report(|job| matches!(job.status, JobStatus::Done(_)))
+    }
+
+    // Lists the jobs that pass the filter, then removes the finished ones that were listed.
+    // The current job (the last one) is marked with +, the previous one with -.
+    fn report(&mut self, filter: impl Fn(&Job) -> bool) -> String {
+        self.reap();
+        let len = self.jobs.len();
+        let list = self
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| filter(job))
+            .map(|(i, job)| {
+                let marker = match len - i {
+                    1 => '+',
+                    2 => '-',
+                    _ => ' ',
+                };
+                let (status, suffix) = match job.status {
+                    JobStatus::Running => ("Running".to_string(), " &"),
+                    JobStatus::Stopped => ("Stopped".to_string(), ""),
+                    JobStatus::Done(0) => ("Done".to_string(), ""),
+                    JobStatus::Done(code) => (format!("Exit {code}"), ""),
+                };
+                format!(
+                    "[{}]{}  {:<24}{}{}\n",
+                    job.id, marker, status, job.command, suffix
+                )
+            })
+            .collect();
+        self.jobs
+            .retain(|job| !(matches!(job.status, JobStatus::Done(_)) && filter(job)));
+        list
+    }
+}
+
+// Parses a job spec like %1 (or just 1), an empty spec refers to the current job.
+pub(crate) fn parse_job_spec(spec: &str) -> Result<Option<usize>> {
+    let spec = spec.trim();
+    match spec {
+        "" | "%" | "%+" | "%%" => Ok(None),
+        _ => spec
+            .strip_prefix('%')
+            .unwrap_or(spec)
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("{spec}: no such job")),
+    }
+}
+
+// Waits for a state change of the child, returns None if there is no change (WNOHANG).
+fn wait_job(pid: i32, flags: i32) -> Option<JobStatus> {
+    let mut status = 0;
+    // Safety: waitpid only writes the status of the child
+    let result =
+        unsafe { libc::waitpid(pid, &mut status, flags | libc::WUNTRACED | libc::WCONTINUED) };
+    match result {
+        0 => None,
+        // The child doesn't exist anymore
+        -1 => Some(JobStatus::Done(1)),
+        _ if libc::WIFEXITED(status) => Some(JobStatus::Done(libc::WEXITSTATUS(status))),
+        _ if libc::WIFSIGNALED(status) => Some(JobStatus::Done(128 + libc::WTERMSIG(status))),
+        _ if libc::WIFSTOPPED(status) => Some(JobStatus::Stopped),
+        _ => Some(JobStatus::Running),
+    }
+}
+
+// Blocks until the job finishes, returning its exit status.
+fn wait_done(job: &mut Job) -> i32 {
+    loop {
+        if let JobStatus::Done(code) = job.status {
+            return code;
+        }
+        job.status = wait_job(job.pid(), 0).unwrap_or(JobStatus::Done(1));
+    }
+}
+
+// Gives the terminal to the process group, SIGTTOU is ignored so the shell can take it back
+// while it is not in the foreground.
+fn set_terminal_foreground(pgid: i32) {
+    // Safety: ignoring and restoring a signal, and tcsetpgrp on stdin
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_list() -> anyhow::Result<()> {
+        let mut jobs = Jobs::default();
+        let child = Command::new("sleep").arg("5").spawn()?;
+        jobs.add("sleep 5", child);
+        let child = Command::new("true").spawn()?;
+        jobs.add("true", child);
+        let child = Command::new("false").spawn()?;
+        jobs.add("false", child);
+        thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(
+            "[1]   Running                 sleep 5 &\n\
+             [2]-  Done                    true\n\
+             [3]+  Exit 1                  false\n",
+            jobs.list()
+        );
+        // The finished jobs are removed after being reported
+        assert_eq!("[1]+  Running                 sleep 5 &\n", jobs.list());
+
+        for job in jobs.iter_mut() {
+            job.child.kill()?;
+            job.child.wait()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_finished() -> anyhow::Result<()> {
+        let mut jobs = Jobs::default();
+        let child = Command::new("sleep").arg("5").spawn()?;
+        jobs.add("sleep 5", child);
+        let child = Command::new("false").spawn()?;
+        jobs.add("false", child);
+        thread::sleep(Duration::from_millis(200));
+
+        // Only the finished ones are reported, once
+        assert_eq!("[2]+  Exit 1                  false\n", jobs.finished());
+        assert_eq!("", jobs.finished());
+        assert_eq!("[1]+  Running                 sleep 5 &\n", jobs.list());
+
+        for job in jobs.iter_mut() {
+            job.child.kill()?;
+            job.child.wait()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_spec() -> anyhow::Result<()> {
+        assert_eq!(None, parse_job_spec("")?);
+        assert_eq!(None, parse_job_spec("%+")?);
+        assert_eq!(Some(2), parse_job_spec("%2")?);
+        assert_eq!(Some(2), parse_job_spec("2")?);
+        assert!(parse_job_spec("%a").is_err());
+        Ok(())
+    }
+}
@@ -1,87 +1,2572 @@
+mod alias;
+mod arith;
+mod brace;
+mod chain;
+mod completion;
+mod condition;
+mod glob;
+mod history;
+mod input;
+mod jobs;
+mod path;
+mod prompt;
+mod redirect;
+mod signals;
+mod tokenizer;
+mod variables;
+
+use alias::Aliases;
 use anyhow::Result;
-use std::io::{self, Write};
-use std::path::Path;
-use std::process::Command;
+use chain::{
+    run_chain, split_chain, Chain, Command as ChainCommand, Pipeline, Redirect, SimpleCommand,
+    Subshell,
+};
+use completion::ShellHelper;
+use history::{history_file, History};
+use jobs::{parse_job_spec, Jobs};
+use path::{executables_in_path, PathCache};
+use redirect::Redirections;
+use rustyline::config::Config;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{CompletionType, Editor};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::OnceLock;
 use std::{env, fs, process};
+use tokenizer::{tokenize, Incomplete, Word};
+use variables::{is_name, parse_assignment, Variables};
+
+// A builtin runs in the shell itself with the arguments after its name, returning its exit status.
+struct Builtin {
+    run: fn(&mut ShellState, &[String]) -> Result<i32>,
+    // The usage and the one line description shown by help
+    usage: &'static str,
+    description: &'static str,
+}
+
+// The builtins by their name.
+fn builtins() -> &'static HashMap<&'static str, Builtin> {
+    static BUILTINS: OnceLock<HashMap<&'static str, Builtin>> = OnceLock::new();
+    BUILTINS.get_or_init(|| {
+        let builtin = |usage, description, run| Builtin {
+            run,
+            usage,
+            description,
+        };
+        HashMap::from([
+            (
+                "echo",
+                builtin(
+                    "echo [-ne] [arg ...]",
+                    "Write the arguments to the output.",
+                    |_, args| {
+                        print!("{}", echo(args));
+                        Ok(0)
+                    },
+                ),
+            ),
+            (
+                "type",
+                builtin(
+                    "type [-a] name ...",
+                    "Tell how each name would be run.",
+                    |state, args| Ok(type_builtin(state, args)),
+                ),
+            ),
+            (
+                "exit",
+                builtin(
+                    "exit [n]",
+                    "Exit the shell with the status n.",
+                    |state, args| exit(state, first_arg(args)),
+                ),
+            ),
+            (
+                "cd",
+                builtin("cd [-m] [dir]", "Change the current directory.", cd_builtin),
+            ),
+            (
+                "pwd",
+                builtin(
+                    "pwd [-LP]",
+                    "Print the current directory.",
+                    |state, args| pwd(state, first_arg(args)),
+                ),
+            ),
+            (
+                "pushd",
+                builtin(
+                    "pushd dir",
+                    "Change to the directory, saving the current one.",
+                    |state, args| pushd(state, first_arg(args)),
+                ),
+            ),
+            (
+                "popd",
+                builtin(
+                    "popd",
+                    "Go back to the directory saved by pushd.",
+                    |state, _| popd(state),
+                ),
+            ),
+            (
+                "dirs",
+                builtin("dirs", "Print the directory stack.", |state, _| {
+                    println!("{}", dirs(state)?);
+                    Ok(0)
+                }),
+            ),
+            (
+                "history",
+                builtin(
+                    "history [n]",
+                    "Print the last n lines of the history.",
+                    |state, args| Ok(history(state, first_arg(args))),
+                ),
+            ),
+            (
+                "jobs",
+                builtin("jobs", "List the background jobs.", |state, _| {
+                    print!("{}", state.jobs.list());
+                    Ok(0)
+                }),
+            ),
+            (
+                "fg",
+                builtin(
+                    "fg [%n]",
+                    "Wait for the job in the foreground.",
+                    |state, args| Ok(fg(state, first_arg(args))),
+                ),
+            ),
+            (
+                "bg",
+                builtin(
+                    "bg [%n]",
+                    "Resume the stopped job in the background.",
+                    |state, args| Ok(bg(state, first_arg(args))),
+                ),
+            ),
+            (
+                "wait",
+                builtin(
+                    "wait [%n]",
+                    "Wait for the job, or for all the jobs.",
+                    |state, args| Ok(wait(state, first_arg(args))),
+                ),
+            ),
+            (
+                "alias",
+                builtin(
+                    "alias [name[=value] ...]",
+                    "Define or print aliases.",
+                    |state, args| Ok(alias(state, args)),
+                ),
+            ),
+            (
+                "unalias",
+                builtin("unalias [-a] name ...", "Remove aliases.", |state, args| {
+                    Ok(unalias(state, args))
+                }),
+            ),
+            (
+                "export",
+                builtin(
+                    "export [name[=value] ...]",
+                    "Export variables to the commands run.",
+                    |state, args| Ok(export(state, args)),
+                ),
+            ),
+            (
+                "unset",
+                builtin("unset name ...", "Remove variables.", |state, args| {
+                    args.iter().for_each(|name| state.variables.unset(name));
+                    Ok(0)
+                }),
+            ),
+            (
+                "source",
+                builtin(
+                    "source file",
+                    "Run the lines of the file in this shell.",
+                    |state, args| source(state, first_arg(args)),
+                ),
+            ),
+            (
+                ".",
+                builtin(
+                    ". file",
+                    "Run the lines of the file in this shell.",
+                    |state, args| source(state, first_arg(args)),
+                ),
+            ),
+            (
+                "read",
+                builtin(
+                    "read [-p prompt] [name]",
+                    "Read a line into a variable.",
+                    |state, args| Ok(read(state, args, &mut io::stdin().lock())),
+                ),
+            ),
+            (
+                "test",
+                builtin(
+                    "test expr",
+                    "Evaluate a conditional expression.",
+                    |_, args| Ok(test("test", args)),
+                ),
+            ),
+            (
+                "[",
+                builtin(
+                    "[ expr ]",
+                    "Evaluate a conditional expression.",
+                    |_, args| Ok(test("[", args)),
+                ),
+            ),
+            (
+                "exec",
+                // execute passes the redirections, to keep them in place without a command
+                builtin(
+                    "exec [command [arg ...]]",
+                    "Replace the shell with the command.",
+                    |state, args| exec(state, args, Redirections::default()),
+                ),
+            ),
+            (
+                "set",
+                builtin(
+                    "set [-e|+e] [-o|+o option]",
+                    "Enable or disable the shell options.",
+                    |state, args| Ok(set(state, args)),
+                ),
+            ),
+            (
+                "umask",
+                builtin(
+                    "umask [mode]",
+                    "Print or set the file creation mask.",
+                    |_, args| Ok(umask(first_arg(args))),
+                ),
+            ),
+            (
+                "help",
+                builtin(
+                    "help [name]",
+                    "Describe the builtins.",
+                    |_, args| match help(first_arg(args)) {
+                        Some(text) => {
+                            print!("{text}");
+                            Ok(0)
+                        }
+                        None => {
+                            println!("help: no help topics match `{}'", first_arg(args));
+                            Ok(1)
+                        }
+                    },
+                ),
+            ),
+        ])
+    })
+}
+
+// The usage and description of every builtin, or only of the one named, None if it's not one.
+fn help(name: &str) -> Option<String> {
+    if !name.is_empty() {
+        let builtin = builtins().get(name)?;
+        return Some(format!("{}\n    {}\n", builtin.usage, builtin.description));
+    }
+    let lines: Vec<String> = builtin_names()
+        .into_iter()
+        .map(|name| {
+            let builtin = &builtins()[name];
+            format!("{:<28}{}\n", builtin.usage, builtin.description)
+        })
+        .collect();
+    Some(lines.concat())
+}
+
+pub(crate) fn is_builtin(cmd: &str) -> bool {
+    builtins().contains_key(cmd)
+}
+
+// The names of the builtins, sorted.
+pub(crate) fn builtin_names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = builtins().keys().copied().collect();
+    names.sort();
+    names
+}
+
+// Most builtins only use the first argument.
+fn first_arg(args: &[String]) -> &str {
+    args.first().map_or("", String::as_str)
+}
 
-const BUILTINS: [&str; 5] = ["type", "exit", "echo", "pwd", "cd"];
+// State that lives across the lines handled by the repl.
+#[derive(Debug, Default)]
+struct ShellState {
+    last_status: i32,
+    // Current directory as it was reached with cd, keeping the symlinks (the logical one)
+    pwd: Option<PathBuf>,
+    // Directory before the last successful cd, used by `cd -`
+    old_pwd: Option<PathBuf>,
+    // Directories saved by pushd, the last one is the top
+    dir_stack: Vec<PathBuf>,
+    history: History,
+    jobs: Jobs,
+    aliases: Aliases,
+    variables: Variables,
+    path_cache: PathCache,
+    // Running in a forked ( ) subshell, the history belongs to the parent shell
+    subshell: bool,
+    // set -e: the shell exits when a command fails
+    errexit: bool,
+    // set -o pipefail: a pipeline fails if any of its stages fails
+    pipefail: bool,
+}
 
 fn main() -> Result<()> {
-    repl_loop()
+    signals::install_sigint_handler()?;
+    let args: Vec<String> = env::args().collect();
+    match &args[1..] {
+        [flag, command, params @ ..] if flag == "-c" => {
+            let name = args[0].as_str();
+            process::exit(run_command_string(command, name, params)?)
+        }
+        [flag] if flag == "-c" => {
+            println!("-c: option requires an argument");
+            process::exit(2)
+        }
+        [script, params @ ..] => process::exit(run_script(script, params)?),
+        [] => repl_loop(),
+    }
+}
+
+// Runs the command string once instead of the repl, returning its status.
+// The arguments after it are $0, $1 and so on, $0 is the shell's name without them.
+fn run_command_string(command: &str, name: &str, params: &[String]) -> Result<i32> {
+    let mut state = ShellState::default();
+    match params.split_first() {
+        Some((zero, params)) => state.variables.set_positional(zero, params),
+        None => state.variables.set_positional(name, &[]),
+    }
+    run_lines(&mut state, command)
+}
+
+// Runs the lines of the script instead of the repl, returning the status of the last command.
+// The script is $0 and the arguments after it $1, $2 and so on.
+fn run_script(script: &str, params: &[String]) -> Result<i32> {
+    let content = match fs::read_to_string(script) {
+        Ok(content) => content,
+        Err(e) => {
+            match e.kind() {
+                io::ErrorKind::NotFound => println!("{script}: No such file or directory"),
+                _ => println!("{script}: {e}"),
+            }
+            return Ok(127);
+        }
+    };
+    let mut state = ShellState::default();
+    state.variables.set_positional(script, params);
+    // A #! first line is a comment, like any other line starting with #
+    run_lines(&mut state, &content)
 }
 
 fn repl_loop() -> Result<()> {
+    let mut state = ShellState::default();
+    if let Some(file) = history_file() {
+        if let Err(e) = state.history.load(&file) {
+            println!("could not load the history: {e}");
+        }
+    }
+    // The list completion type completes the common prefix, and lists the candidates on a second tab
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::with_config(config)?;
+    editor.set_helper(Some(ShellHelper::default()));
+    let status = repl(&mut state, |prompt, path| {
+        if let Some(helper) = editor.helper_mut() {
+            helper.path = path.to_string();
+        }
+        editor.readline(prompt)
+    })?;
+    save_history(&mut state);
+    process::exit(status)
+}
+
+// Reads and runs lines until the end of the input (Ctrl-D), returning the status to exit with.
+// The lines are read with the PATH of the shell, for the completion of the commands.
+fn repl(
+    state: &mut ShellState,
+    mut read_line: impl FnMut(&str, &str) -> Result<String, ReadlineError>,
+) -> Result<i32> {
     loop {
-        print!("$ ");
-        io::stdout().flush().unwrap();
+        // The background jobs that finished are reaped and reported before the prompt
+        print!("{}", state.jobs.finished());
         // Wait for user input
-        let stdin = io::stdin();
-        let mut input = String::new();
-        stdin.read_line(&mut input).unwrap();
-        handle_line(input)?
-    }
-}
-
-fn handle_line(input: String) -> Result<()> {
-    let input = input.trim();
-    let lines = input.split_once(" ");
-    match lines {
-        Some(("echo", rest)) => println!("{rest}"),
-        Some(("type", cmd)) => match BUILTINS.contains(&cmd) {
-            true => println!("{} is a shell builtin", cmd),
-            false => match handle_paths(cmd) {
-                Ok(path) => println!("{} is {}", cmd, path),
-                Err(_) => {
-                    println!("{}: not found", cmd);
+        let cwd = working_dir(state, false).unwrap_or_default();
+        let prompt = prompt::prompt(&state.variables, &cwd);
+        let path = state.variables.get("PATH").unwrap_or_default();
+        let input = match input::read_logical_line(&prompt, |prompt| read_line(prompt, &path)) {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => {
+                println!();
+                return Ok(state.last_status);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // History references are replaced before running, showing the line that runs
+        let input = match state.history.expand(&input) {
+            Ok(Some(expanded)) => {
+                println!("{expanded}");
+                expanded
+            }
+            Ok(None) => input,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
+        };
+        state.history.push(&input);
+        handle_line(state, input)?;
+        // The interrupted command didn't finish its line
+        if signals::take_interrupted() {
+            println!();
+        }
+    }
+}
+
+fn handle_line(state: &mut ShellState, input: String) -> Result<()> {
+    let commands = tokenize(&input)
+        .and_then(|tokens| alias::expand(&state.aliases, tokens))
+        .and_then(split_chain);
+    let commands = match commands {
+        Ok(commands) => commands,
+        Err(e) => {
+            println!("{e}");
+            state.last_status = 2;
+            return Ok(());
+        }
+    };
+    run_list(state, &commands)?;
+    Ok(())
+}
+
+// Runs the commands of the chain, keeping the status of each one.
+fn run_list(state: &mut ShellState, commands: &Chain) -> Result<i32> {
+    run_chain(commands, |command, tested| {
+        let status = run_chain_command(state, command)?;
+        state.last_status = status;
+        // A failure tested by && or || doesn't exit
+        if status != 0 && state.errexit && !tested {
+            exit(state, "");
+        }
+        Ok(status)
+    })
+}
+
+fn run_chain_command(state: &mut ShellState, command: &ChainCommand) -> Result<i32> {
+    match command {
+        ChainCommand::Simple(command) => run_command(state, command),
+        ChainCommand::Subshell(subshell) => run_subshell(state, subshell),
+        ChainCommand::Pipeline(pipeline) => run_pipeline(state, pipeline),
+    }
+}
+
+// Runs the stages of the pipeline at the same time, each one in a forked copy of the shell
+// with its output going to the input of the next stage.
+fn run_pipeline(state: &mut ShellState, pipeline: &Pipeline) -> Result<i32> {
+    if pipeline.background {
+        println!("background pipelines are not supported");
+        return Ok(1);
+    }
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+    let mut pids = vec![];
+    // The read end of the pipe from the previous stage
+    let mut input: Option<OwnedFd> = None;
+    for (i, stage) in pipeline.stages.iter().enumerate() {
+        let output = match i == pipeline.stages.len() - 1 {
+            true => None,
+            false => Some(pipe()?),
+        };
+        state.variables.pin_shell_pid();
+        // Safety: the child only runs the shell's own code and exits without returning
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if pid == 0 {
+            state.subshell = true;
+            // Safety: dup2 replaces the standard descriptors of the child with the pipes
+            unsafe {
+                if let Some(input) = &input {
+                    libc::dup2(input.as_raw_fd(), 0);
                 }
-            },
-        },
-        Some(("exit", _)) => process::exit(0),
-        Some(("cd", path)) => {
-            let home = env::var("HOME")?;
-            let path = path.replace("~", home.as_str());
-            match env::set_current_dir(Path::new(path.as_str())) {
-                Ok(_) => {}
-                Err(e) => {
-                    if matches!(e.kind(), io::ErrorKind::NotFound) {
-                        println!("cd: {path}: No such file or directory")
-                    }
+                if let Some((_, write)) = &output {
+                    libc::dup2(write.as_raw_fd(), 1);
+                }
+            }
+            // Only the standard descriptors are kept, or the readers wouldn't see the end
+            drop((input, output));
+            let status = run_chain_command(state, stage).unwrap_or_else(|e| {
+                println!("{e}");
+                1
+            });
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            // Safety: leaves the child without running the parent's exit handlers
+            unsafe { libc::_exit(status) };
+        }
+        pids.push(pid);
+        input = output.map(|(read, _)| read);
+    }
+    drop(input);
+    signals::set_foreground(pids.last().map(|pid| *pid as u32));
+    let mut statuses = vec![];
+    for pid in pids {
+        let mut status = 0;
+        // Safety: waits for the children just forked
+        let result = unsafe { libc::waitpid(pid, &mut status, 0) };
+        if result < 0 {
+            signals::set_foreground(None);
+            return Err(io::Error::last_os_error().into());
+        }
+        statuses.push(exit_status(ExitStatus::from_raw(status)));
+    }
+    signals::set_foreground(None);
+    Ok(pipeline_status(&statuses, state.pipefail))
+}
+
+// The status of the pipeline is the one of its last stage. With pipefail it's the status
+// of the first stage that failed, or 0 if all of them succeeded.
+fn pipeline_status(statuses: &[i32], pipefail: bool) -> i32 {
+    let last = statuses.last().copied().unwrap_or_default();
+    match pipefail {
+        true => statuses
+            .iter()
+            .find(|status| **status != 0)
+            .copied()
+            .unwrap_or(0),
+        false => last,
+    }
+}
+
+// A new pipe, as its read and write ends.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // Safety: pipe fills the two descriptors, owned by the returned values
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])))
+    }
+}
+
+// Sets the shell options: -e (errexit) exits on the first failing command and pipefail fails
+// the pipelines with a failing stage. -o enables an option by its name and + disables it.
+// Without arguments, or a bare -o, the options are listed.
+fn set(state: &mut ShellState, args: &[String]) -> i32 {
+    if args.is_empty() {
+        print!("{}", options(state));
+        return 0;
+    }
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (enable, flag) = match (arg.strip_prefix('-'), arg.strip_prefix('+')) {
+            (Some(flag), _) => (true, flag),
+            (_, Some(flag)) => (false, flag),
+            _ => {
+                println!("set: {arg}: invalid option");
+                return 2;
+            }
+        };
+        let name = match flag {
+            "e" => "errexit",
+            "o" => match args.next() {
+                Some(name) => name.as_str(),
+                None => {
+                    print!("{}", options(state));
+                    continue;
                 }
+            },
+            _ => {
+                println!("set: {arg}: invalid option");
+                return 2;
+            }
+        };
+        match name {
+            "errexit" => state.errexit = enable,
+            "pipefail" => state.pipefail = enable,
+            _ => {
+                println!("set: {name}: invalid option name");
+                return 2;
+            }
+        }
+    }
+    0
+}
+
+// The shell options and if they are on, one per line.
+fn options(state: &ShellState) -> String {
+    [("errexit", state.errexit), ("pipefail", state.pipefail)]
+        .iter()
+        .map(|(name, on)| format!("{name:<15}\t{}\n", if *on { "on" } else { "off" }))
+        .collect()
+}
+
+// Runs the commands of the group in a forked copy of the shell, so cd, variables or exit
+// don't affect the shell itself.
+fn run_subshell(state: &mut ShellState, subshell: &Subshell) -> Result<i32> {
+    if subshell.background {
+        println!("background subshells are not supported");
+        return Ok(1);
+    }
+    let redirections = match open_redirects(state, &subshell.redirects) {
+        Ok(redirections) => redirections,
+        Err(status) => return Ok(status),
+    };
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+    state.variables.pin_shell_pid();
+    // Safety: the child only runs the shell's own code and exits without returning
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    if pid == 0 {
+        state.subshell = true;
+        let status = match redirections.apply() {
+            Ok(guard) => {
+                guard.keep();
+                run_list(state, &subshell.commands).unwrap_or_else(|e| {
+                    println!("{e}");
+                    1
+                })
+            }
+            Err(e) => {
+                println!("{e}");
+                1
+            }
+        };
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        // Safety: leaves the child without running the parent's exit handlers
+        unsafe { libc::_exit(status) };
+    }
+    drop(redirections);
+    signals::set_foreground(Some(pid as u32));
+    let mut status = 0;
+    // Safety: waits for the child just forked
+    let result = unsafe { libc::waitpid(pid, &mut status, 0) };
+    signals::set_foreground(None);
+    if result < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(exit_status(ExitStatus::from_raw(status)))
+}
+
+// Expands and runs a single command, returning its exit status.
+fn run_command(state: &mut ShellState, command: &SimpleCommand) -> Result<i32> {
+    let variables = &state.variables;
+    let status = state.last_status;
+    let words: Result<Vec<Word>> = command
+        .words
+        .iter()
+        .map(|word| arith::expand_word(word, variables, status))
+        .collect();
+    let words = match words {
+        Ok(words) => words,
+        Err(e) => {
+            println!("{e}");
+            return Ok(1);
+        }
+    };
+    // A command with only NAME=value words sets shell variables
+    let assignments: Option<Vec<_>> = words
+        .iter()
+        .map(|word| variables::expand_assignment(word, variables, status))
+        .collect();
+    if let Some(assignments) = assignments.filter(|a| !a.is_empty()) {
+        for (name, value) in assignments {
+            state.variables.set(&name, &value);
+        }
+        return Ok(0);
+    }
+    let argv: Vec<String> = words
+        .iter()
+        .flat_map(brace::expand)
+        .flat_map(|word| {
+            let fields = variables::expand_word(&word, variables, status);
+            match glob::has_pattern(&word) {
+                true => fields
+                    .iter()
+                    .flat_map(|field| glob::expand(field))
+                    .collect(),
+                false => fields,
             }
+        })
+        .collect();
+    let redirections = match open_redirects(state, &command.redirects) {
+        Ok(redirections) => redirections,
+        Err(status) => return Ok(status),
+    };
+    if argv.is_empty() {
+        return Ok(0);
+    }
+    match command.background {
+        true => run_background(state, &argv, redirections),
+        false => execute(state, &argv, redirections),
+    }
+}
+
+// Expands the targets and opens the redirections, or prints the error and returns the status
+// the command fails with.
+fn open_redirects(
+    state: &ShellState,
+    redirects: &[Redirect],
+) -> std::result::Result<Redirections, i32> {
+    let mut opened = vec![];
+    for redirect in redirects {
+        let mut target =
+            variables::expand_word(&redirect.target, &state.variables, state.last_status);
+        if target.len() != 1 {
+            println!("{}: ambiguous redirect", redirect.target.literal());
+            return Err(1);
+        }
+        opened.push((redirect.fd, redirect.op, target.remove(0)));
+    }
+    Redirections::open(&opened).map_err(|e| {
+        println!("{e}");
+        1
+    })
+}
+
+// Executes the builtin or external command in the foreground.
+fn execute(state: &mut ShellState, argv: &[String], mut redirections: Redirections) -> Result<i32> {
+    let (cmd, args) = (argv[0].as_str(), &argv[1..]);
+    if cmd == "exec" {
+        return exec(state, args, redirections);
+    }
+    if let Some(builtin) = builtins().get(cmd) {
+        let _guard = redirections.apply()?;
+        return (builtin.run)(state, args);
+    }
+    match lookup(state, cmd) {
+        Ok(path) => {
+            // Without redirections the child shares the terminal, so interactive programs work
+            let mut command = external_command(state, &path, cmd, args);
+            redirections.redirect_child(&mut command);
+            io::stdout().flush()?;
+            let mut child = match command.spawn() {
+                Ok(child) => child,
+                Err(e) => return Ok(spawn_error(cmd, e)),
+            };
+            signals::set_foreground(Some(child.id()));
+            let status = child.wait();
+            signals::set_foreground(None);
+            Ok(exit_status(status?))
+        }
+        Err(_) => {
+            println!("{cmd}: command not found");
+            Ok(127)
+        }
+    }
+}
+
+// Reports the command that couldn't be started (like a file without a shebang), returning
+// 127 if it doesn't exist and 126 otherwise, like bash.
+fn spawn_error(cmd: &str, e: io::Error) -> i32 {
+    eprintln!("{cmd}: {e}");
+    match e.kind() {
+        io::ErrorKind::NotFound => 127,
+        _ => 126,
+    }
+}
+
+// The status of the finished child, 128 + the signal number if it was killed by a signal.
+fn exit_status(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+// Replaces the shell with the command, with the redirections applied to the shell first.
+// Without a command the redirections stay in place for the rest of the session.
+// If the command can't be executed the shell keeps running.
+fn exec(state: &mut ShellState, args: &[String], redirections: Redirections) -> Result<i32> {
+    let guard = redirections.apply()?;
+    let Some((cmd, args)) = args.split_first() else {
+        guard.keep();
+        return Ok(0);
+    };
+    let path = match lookup(state, cmd) {
+        Ok(path) => path,
+        Err(_) => {
+            drop(guard);
+            println!("exec: {cmd}: not found");
+            return Ok(127);
+        }
+    };
+    // Only returns if it failed
+    let error = external_command(state, &path, cmd, args).exec();
+    drop(guard);
+    println!("exec: {cmd}: {error}");
+    Ok(126)
+}
+
+// Tells how each command would be run: as an alias, a builtin or an executable in the PATH.
+// With -a every way is listed, including all the matches in PATH.
+fn type_builtin(state: &mut ShellState, args: &[String]) -> i32 {
+    let (all, cmds) = match args {
+        [flag, cmds @ ..] if flag == "-a" => (true, cmds),
+        cmds => (false, cmds),
+    };
+    let mut status = 0;
+    for cmd in cmds {
+        let descriptions = describe_command(state, cmd, all);
+        if descriptions.is_empty() {
+            println!("{}: not found", cmd);
+            status = 1;
         }
-        Some((cmd, args)) => match handle_paths(cmd) {
-            Ok(path) => {
-                let args: Vec<&str> = args.split_whitespace().collect();
-                let output = Command::new(path).args(args).output()?;
-                io::stdout().write_all(&output.stdout)?;
-                io::stderr().write_all(&output.stderr)?;
+        descriptions.iter().for_each(|line| println!("{line}"));
+    }
+    status
+}
+
+// The ways the command would be run in the order they are tried, only the first one unless
+// all are asked.
+fn describe_command(state: &mut ShellState, cmd: &str, all: bool) -> Vec<String> {
+    let mut descriptions = vec![];
+    if let Some(value) = state.aliases.get(cmd) {
+        descriptions.push(format!("{} is aliased to `{}'", cmd, value));
+    }
+    if is_builtin(cmd) {
+        descriptions.push(format!("{} is a shell builtin", cmd));
+    }
+    if all && !cmd.contains('/') {
+        let path = state.variables.get("PATH").unwrap_or_default();
+        descriptions.extend(executables_in_path(cmd, &path).map(|file| format!("{cmd} is {file}")));
+    } else if all || descriptions.is_empty() {
+        if let Ok(file) = lookup(state, cmd) {
+            descriptions.push(format!("{} is {}", cmd, file));
+        }
+    }
+    if !all {
+        descriptions.truncate(1);
+    }
+    descriptions
+}
+
+// cd, with -m creating the missing directories first.
+fn cd_builtin(state: &mut ShellState, args: &[String]) -> Result<i32> {
+    match args {
+        [flag, ..] if flag == "-m" => match args.get(1).map_or("", String::as_str) {
+            "" => {
+                println!("cd: -m: directory argument required");
+                Ok(2)
             }
-            Err(_) => println!("{}: command not found", input),
+            path => change_dir(state, path, true),
         },
+        _ => cd(state, first_arg(args)),
+    }
+}
+
+// Resolves the command with the PATH of the shell, which can be set without exporting it.
+fn lookup(state: &mut ShellState, cmd: &str) -> Result<String> {
+    let path = state.variables.get("PATH").unwrap_or_default();
+    state.path_cache.lookup(cmd, &path)
+}
+
+// Builds the command for the executable, with the exported variables in its environment.
+// The child sees the command as it was typed as its name.
+fn external_command(state: &ShellState, path: &str, cmd: &str, args: &[String]) -> Command {
+    let mut command = Command::new(path);
+    command
+        .arg0(cmd)
+        .args(args)
+        .envs(state.variables.exported());
+    command
+}
+
+// Starts an external command without waiting for it, tracking it as a job.
+// Builtins run in the shell itself, so they are not sent to the background.
+fn run_background(
+    state: &mut ShellState,
+    argv: &[String],
+    mut redirections: Redirections,
+) -> Result<i32> {
+    let (cmd, args) = (argv[0].as_str(), &argv[1..]);
+    if is_builtin(cmd) {
+        return execute(state, argv, redirections);
+    }
+    match lookup(state, cmd) {
+        Ok(path) => {
+            let mut command = external_command(state, &path, cmd, args);
+            redirections.redirect_child(&mut command);
+            // In its own process group, so the job control signals only reach the job
+            let child = match command.process_group(0).spawn() {
+                Ok(child) => child,
+                Err(e) => return Ok(spawn_error(cmd, e)),
+            };
+            state.variables.set_last_background(child.id());
+            let job = state.jobs.add(&argv.join(" "), child);
+            println!("[{}] {}", job.id, job.child.id());
+            Ok(0)
+        }
+        Err(_) => {
+            println!("{}: command not found", cmd);
+            Ok(127)
+        }
+    }
+}
+
+// The output of echo for the arguments. Leading flags combine, like -n -e or -ne:
+// -n suppresses the trailing newline and -e interprets the escapes \n, \t and \\.
+fn echo(args: &[String]) -> String {
+    let is_flag = |arg: &&String| {
+        arg.strip_prefix('-')
+            .is_some_and(|flags| !flags.is_empty() && flags.chars().all(|c| c == 'n' || c == 'e'))
+    };
+    let count = args.iter().take_while(is_flag).count();
+    let flags: String = args[..count].iter().map(|arg| &arg[1..]).collect();
+    let text = args[count..].join(" ");
+    let mut output = if flags.contains('e') {
+        unescape(&text)
+    } else {
+        text
+    };
+    if !flags.contains('n') {
+        output.push('\n');
+    }
+    output
+}
+
+// Interprets the backslash escapes \n, \t and \\, other escapes are kept as they are.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('n')) => unescaped.push('\n'),
+            ('\\', Some('t')) => unescaped.push('\t'),
+            ('\\', Some('\\')) => unescaped.push('\\'),
+            _ => {
+                unescaped.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    unescaped
+}
+
+// Runs the lines of the file in the current shell, so the variables and aliases it defines persist.
+// Returns the status of the last command.
+fn source(state: &mut ShellState, file: &str) -> Result<i32> {
+    if file.is_empty() {
+        println!("source: filename argument required");
+        return Ok(2);
+    }
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            match e.kind() {
+                io::ErrorKind::NotFound => println!("source: {file}: No such file or directory"),
+                _ => println!("source: {file}: {e}"),
+            }
+            return Ok(1);
+        }
+    };
+    run_lines(state, &content)
+}
+
+// Runs the lines of a file one after the other, returning the status of the last command.
+// Content ending in the middle of a line (an open quote or a trailing \) is a syntax error.
+fn run_lines(state: &mut ShellState, content: &str) -> Result<i32> {
+    state.last_status = 0;
+    // The lines continue like in the repl, so quotes and here-documents can span lines
+    let mut lines = content.lines();
+    // The lines read for the logical line, to know how it was left when the content ends
+    let mut pending = String::new();
+    let mut next_line = |prompt: &str| {
+        if prompt != input::CONTINUATION_PROMPT {
+            pending.clear();
+        }
+        let line = lines.next().ok_or(())?;
+        if !pending.is_empty() {
+            pending.push('\n');
+        }
+        pending.push_str(line);
+        Ok::<_, ()>(line.to_string())
+    };
+    while let Ok(line) = input::read_logical_line("", &mut next_line) {
+        handle_line(state, line)?;
+    }
+    match tokenizer::incomplete(&pending) {
+        Some(Incomplete::Quote(quote)) => {
+            eprintln!("unexpected EOF while looking for matching '{quote}'");
+        }
+        Some(_) => eprintln!("syntax error: unexpected end of file"),
+        None => return Ok(state.last_status),
+    }
+    state.last_status = 2;
+    Ok(2)
+}
+
+// Defines an alias with name=value, prints one with name, or lists all of them without arguments.
+fn alias(state: &mut ShellState, args: &[String]) -> i32 {
+    if args.is_empty() {
+        for (name, value) in &state.aliases {
+            println!("{}", alias::format(name, value));
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for arg in args {
+        if let Some((name, value)) = alias::parse_definition(arg) {
+            state.aliases.insert(name, value);
+            continue;
+        }
+        match state.aliases.get(arg) {
+            Some(value) => println!("{}", alias::format(arg, value)),
+            None => {
+                println!("alias: {arg}: not found");
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+// Removes the aliases given as arguments, or all of them with -a.
+fn unalias(state: &mut ShellState, args: &[String]) -> i32 {
+    if args.first().is_some_and(|arg| arg == "-a") {
+        state.aliases.clear();
+        return 0;
+    }
+    if args.is_empty() {
+        println!("unalias: usage: unalias [-a] name [name ...]");
+        return 2;
+    }
+    let mut status = 0;
+    for name in args {
+        if state.aliases.remove(name).is_none() {
+            println!("unalias: {name}: not found");
+            status = 1;
+        }
+    }
+    status
+}
+
+// Marks the variables to be passed to the child processes, setting them with NAME=value.
+// Without arguments lists the exported variables.
+fn export(state: &mut ShellState, args: &[String]) -> i32 {
+    if args.is_empty() {
+        for (name, value) in state.variables.exported() {
+            println!("declare -x {name}=\"{value}\"");
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for arg in args {
+        match parse_assignment(arg) {
+            Some((name, value)) => {
+                state.variables.set(name, value);
+                state.variables.export(name);
+            }
+            None if arg.contains('=') => {
+                println!("export: `{arg}': not a valid identifier");
+                status = 1;
+            }
+            None => state.variables.export(arg),
+        }
+    }
+    status
+}
+
+// Reads a line of the input into the variable (REPLY by default), without the trailing newline.
+// With -p the prompt is printed first. At the end of the input the status is 1.
+fn read(state: &mut ShellState, args: &[String], input: &mut impl BufRead) -> i32 {
+    let (prompt, name) = match args {
+        [flag, prompt, rest @ ..] if flag == "-p" => (Some(prompt), rest.first()),
+        [flag] if flag == "-p" => {
+            println!("read: -p: option requires an argument");
+            return 2;
+        }
+        _ => (None, args.first()),
+    };
+    let name = name.map_or("REPLY", String::as_str);
+    if !is_name(name) {
+        println!("read: `{name}': not a valid identifier");
+        return 1;
+    }
+    if let Some(prompt) = prompt {
+        print!("{prompt}");
+        let _ = io::stdout().flush();
+    }
+    let mut line = String::new();
+    let status = match input.read_line(&mut line) {
+        Ok(0) => 1,
+        Ok(_) => 0,
+        Err(e) => {
+            println!("read: {e}");
+            1
+        }
+    };
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    state.variables.set(name, line);
+    status
+}
+
+// Evaluates the conditional expression, the status is 0 if it's true and 1 if it's false.
+// Invalid expressions give 2. With [ the last argument has to be ].
+fn test(cmd: &str, args: &[String]) -> i32 {
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if cmd == "[" && args.pop() != Some("]") {
+        println!("[: missing `]'");
+        return 2;
+    }
+    match condition::evaluate(&args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            println!("{cmd}: {e}");
+            2
+        }
+    }
+}
+
+// Waits in the foreground for the job given as %N, or the current job.
+fn fg(state: &mut ShellState, spec: &str) -> i32 {
+    let result = parse_job_spec(spec).and_then(|id| state.jobs.foreground(id));
+    match result {
+        Ok((command, status)) => {
+            println!("{command}");
+            status
+        }
+        Err(e) => {
+            println!("fg: {e}");
+            1
+        }
+    }
+}
+
+// Waits for the job given as %N to finish, returning its status.
+// Without a job it waits for all of them.
+fn wait(state: &mut ShellState, spec: &str) -> i32 {
+    if spec.is_empty() {
+        state.jobs.wait_all();
+        return 0;
+    }
+    match parse_job_spec(spec).and_then(|id| state.jobs.wait(id)) {
+        Ok(status) => status,
+        Err(e) => {
+            println!("wait: {e}");
+            127
+        }
+    }
+}
+
+// Resumes in the background the stopped job given as %N, or the current job.
+fn bg(state: &mut ShellState, spec: &str) -> i32 {
+    let result = parse_job_spec(spec).and_then(|id| state.jobs.resume(id));
+    match result {
+        Ok(job) => {
+            println!("[{}] {} &", job.id, job.command);
+            0
+        }
+        Err(e) => {
+            println!("bg: {e}");
+            1
+        }
+    }
+}
+
+// Prints the file creation mask in octal, or sets it to the octal mode.
+fn umask(mode: &str) -> i32 {
+    if mode.is_empty() {
+        println!("{:04o}", current_umask());
+        return 0;
+    }
+    match u32::from_str_radix(mode, 8) {
+        Ok(mask) if mask <= 0o777 => {
+            // Safety: umask only changes the mask of the process
+            unsafe { libc::umask(mask as libc::mode_t) };
+            0
+        }
         _ => {
-            // Add at the beginning?
-            if input == "pwd" {
-                let current = env::current_dir()?;
+            println!("umask: {mode}: octal number out of range");
+            1
+        }
+    }
+}
+
+// The mask can only be read by replacing it, so it's set back right away.
+fn current_umask() -> u32 {
+    // Safety: umask only changes the mask of the process
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+// Changes the current directory, keeping track of PWD and OLDPWD.
+// `cd -` goes back to the previous directory and prints it, a bare `cd` goes to HOME.
+fn cd(state: &mut ShellState, path: &str) -> Result<i32> {
+    change_dir(state, path, false)
+}
+
+// Changes to the directory, creating it (and its parents) first if it's missing with create.
+fn change_dir(state: &mut ShellState, path: &str, create: bool) -> Result<i32> {
+    let back = path == "-";
+    let path = if path.is_empty() {
+        match state.variables.get("HOME") {
+            Some(home) => home,
+            None => {
+                println!("cd: HOME not set");
+                return Ok(1);
+            }
+        }
+    } else if back {
+        match &state.old_pwd {
+            Some(old) => old.display().to_string(),
+            None => {
+                println!("cd: OLDPWD not set");
+                return Ok(1);
+            }
+        }
+    } else if path.contains('~') {
+        match state.variables.get("HOME") {
+            Some(home) => path.replace("~", home.as_str()),
+            None => {
+                println!("cd: HOME not set");
+                return Ok(1);
+            }
+        }
+    } else {
+        path.to_string()
+    };
+    let previous = working_dir(state, false)?;
+    // The directory is followed logically, so .. after a symlink goes back to where it was
+    let mut current = logical_join(&previous, Path::new(path.as_str()));
+    // The directory found in CDPATH is printed, as it's not the one typed
+    let mut print = back;
+    if create && !current.is_dir() {
+        if let Err(e) = fs::create_dir_all(&current) {
+            println!("cd: {path}: {e}");
+            return Ok(1);
+        }
+    }
+    if !current.is_dir() {
+        if let Some(found) = cdpath_dir(state, &previous, &path) {
+            current = found;
+            print = true;
+        }
+    }
+    match env::set_current_dir(&current) {
+        Ok(_) => {
+            if print {
                 println!("{}", current.display());
-                return Ok(());
             }
-            println!("{}: command not found", input)
+            env::set_var("OLDPWD", &previous);
+            env::set_var("PWD", &current);
+            state.pwd = Some(current);
+            state.old_pwd = Some(previous);
+            Ok(0)
+        }
+        Err(e) => {
+            println!("{}", cd_error(&path, &e));
+            Ok(1)
         }
     }
-    Ok(())
 }
 
-fn handle_paths(cmd: &str) -> Result<String> {
-    let path = env::var("PATH")?;
-    let paths = path.split(":");
-    for path in paths {
-        let entries = fs::read_dir(path)?;
-        for e in entries {
-            let entry = e?;
-            if entry.file_name() == cmd {
-                return Ok(entry.path().display().to_string());
+// Changes to the directory like cd, saving the previous one in the directory stack.
+fn pushd(state: &mut ShellState, path: &str) -> Result<i32> {
+    if path.is_empty() {
+        println!("pushd: no other directory");
+        return Ok(1);
+    }
+    let previous = working_dir(state, false)?;
+    let status = change_dir(state, path, false)?;
+    if status == 0 {
+        state.dir_stack.push(previous);
+        println!("{}", dirs(state)?);
+    }
+    Ok(status)
+}
+
+// Goes back to the directory on the top of the stack, removing it.
+fn popd(state: &mut ShellState) -> Result<i32> {
+    let Some(dir) = state.dir_stack.pop() else {
+        println!("popd: directory stack empty");
+        return Ok(1);
+    };
+    let status = change_dir(state, &dir.display().to_string(), false)?;
+    if status == 0 {
+        println!("{}", dirs(state)?);
+    }
+    Ok(status)
+}
+
+// The current directory followed by the directory stack from the top.
+fn dirs(state: &ShellState) -> Result<String> {
+    let current = working_dir(state, false)?;
+    let dirs: Vec<String> = std::iter::once(&current)
+        .chain(state.dir_stack.iter().rev())
+        .map(|dir| dir.display().to_string())
+        .collect();
+    Ok(dirs.join(" "))
+}
+
+// The message for a failed cd, with the same wording as bash.
+fn cd_error(path: &str, error: &io::Error) -> String {
+    let reason = match error.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        io::ErrorKind::NotADirectory => "Not a directory".to_string(),
+        _ => error.to_string(),
+    };
+    format!("cd: {path}: {reason}")
+}
+
+// Looks for the relative directory in the bases of CDPATH.
+// Absolute paths and paths starting with . or .. are not looked up.
+fn cdpath_dir(state: &ShellState, cwd: &Path, path: &str) -> Option<PathBuf> {
+    let relative = !matches!(
+        Path::new(path).components().next(),
+        Some(Component::RootDir | Component::CurDir | Component::ParentDir) | None
+    );
+    if !relative {
+        return None;
+    }
+    let cdpath = state.variables.get("CDPATH")?;
+    cdpath
+        .split(':')
+        // The current directory was already tried
+        .filter(|base| !base.is_empty() && *base != ".")
+        .map(|base| logical_join(cwd, &Path::new(base).join(path)))
+        .find(|dir| dir.is_dir())
+}
+
+// Prints the current directory, with -L (the default) the logical one followed by cd
+// and with -P the physical one, with the symlinks resolved.
+fn pwd(state: &ShellState, flag: &str) -> Result<i32> {
+    let physical = match flag {
+        "" | "-L" => false,
+        "-P" => true,
+        _ => {
+            println!("pwd: {flag}: invalid option");
+            return Ok(2);
+        }
+    };
+    println!("{}", working_dir(state, physical)?.display());
+    Ok(0)
+}
+
+// The current directory, the logical one tracked by cd unless the physical one is asked.
+// Before any cd the logical directory is the inherited PWD. It's only used while it still
+// refers to the current directory.
+fn working_dir(state: &ShellState, physical: bool) -> io::Result<PathBuf> {
+    let current = env::current_dir()?.canonicalize()?;
+    if physical {
+        return Ok(current);
+    }
+    let inherited = || env::var_os("PWD").map(PathBuf::from);
+    match state.pwd.clone().or_else(inherited) {
+        Some(pwd) if pwd.is_absolute() && pwd.canonicalize().is_ok_and(|pwd| pwd == current) => {
+            Ok(pwd)
+        }
+        _ => Ok(current),
+    }
+}
+
+// Joins the path to the directory, removing the . and .. components without resolving symlinks.
+fn logical_join(dir: &Path, path: &Path) -> PathBuf {
+    let mut joined = PathBuf::new();
+    for component in dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                joined.pop();
+            }
+            component => joined.push(component),
+        }
+    }
+    joined
+}
+
+// Prints the history, only the last N entries if given as argument.
+fn history(state: &ShellState, arg: &str) -> i32 {
+    let last = if arg.is_empty() {
+        None
+    } else {
+        match arg.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                println!("history: {arg}: numeric argument required");
+                return 1;
             }
         }
+    };
+    print!("{}", state.history.format(last));
+    0
+}
+
+// Exits the shell with the code given as argument, or the last status if there is none.
+// Like bash, a non numeric argument exits with 2.
+// The history of the session is appended to the history file before exiting.
+fn exit(state: &mut ShellState, arg: &str) -> ! {
+    if !state.subshell {
+        save_history(state);
+    }
+    match exit_code(arg, state.last_status) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            println!("{e}");
+            process::exit(2)
+        }
+    }
+}
+
+// Appends the history of the session to the history file.
+fn save_history(state: &mut ShellState) {
+    if let Some(file) = history_file() {
+        if let Err(e) = state.history.save(&file) {
+            println!("exit: could not save the history: {e}");
+        }
+    }
+}
+
+fn exit_code(arg: &str, last_status: i32) -> Result<i32> {
+    if arg.is_empty() {
+        return Ok(last_status);
+    }
+    // Only the low 8 bits reach the parent, so 256 is 0 and -1 is 255
+    arg.parse::<i32>()
+        .map(|code| code & 0xFF)
+        .map_err(|_| anyhow::anyhow!("exit: {arg}: numeric argument required"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+    use tokenizer::RedirectOp;
+
+    #[test]
+    fn test_exit_code() -> Result<()> {
+        assert_eq!(42, exit_code("42", 0)?);
+        assert_eq!(0, exit_code("0", 1)?);
+        // Without argument uses the last status
+        assert_eq!(127, exit_code("", 127)?);
+        assert_eq!(0, exit_code("256", 1)?);
+        assert_eq!(255, exit_code("-1", 0)?);
+        assert!(exit_code("abc", 0).is_err());
+        Ok(())
+    }
+
+    // Tests changing process wide state (current directory, env vars) can't run in parallel
+    pub(crate) static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_cd_back() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let first = env::temp_dir().canonicalize()?;
+        let second = first.parent().unwrap().to_path_buf();
+        let mut state = ShellState::default();
+
+        assert_eq!(1, cd(&mut state, "-")?);
+        assert_eq!(0, cd(&mut state, &first.display().to_string())?);
+        assert_eq!(0, cd(&mut state, &second.display().to_string())?);
+        assert_eq!(0, cd(&mut state, "-")?);
+        assert_eq!(first, env::current_dir()?);
+        assert_eq!(Some(second.clone()), state.old_pwd);
+        assert_eq!(second.as_os_str(), env::var_os("OLDPWD").unwrap());
+        assert_eq!(first.as_os_str(), env::var_os("PWD").unwrap());
+
+        env::set_current_dir(original)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_background() -> Result<()> {
+        let mut state = ShellState::default();
+        let start = Instant::now();
+        handle_line(&mut state, "sleep 1 &".to_string())?;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(0, state.last_status);
+
+        let jobs: Vec<_> = state.jobs.iter_mut().collect();
+        assert_eq!(1, jobs.len());
+        let job = jobs.into_iter().next().unwrap();
+        assert_eq!(1, job.id);
+        assert_eq!("sleep 1", job.command);
+        // Still running
+        assert!(job.child.try_wait()?.is_none());
+        job.child.kill()?;
+        job.child.wait()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reap_before_prompt() -> Result<()> {
+        let mut lines = vec!["true &", "sleep 0.2"].into_iter();
+        let mut state = ShellState::default();
+        repl(&mut state, |_, _| {
+            lines.next().map(str::to_string).ok_or(ReadlineError::Eof)
+        })?;
+        // The finished job was reaped at the next prompt, without running jobs
+        assert_eq!(0, state.jobs.iter_mut().count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait() -> Result<()> {
+        let mut state = ShellState::default();
+        let start = Instant::now();
+        handle_line(&mut state, "sleep 0.2 &".to_string())?;
+        handle_line(&mut state, "sh -c 'sleep 0.1; exit 3' &".to_string())?;
+        handle_line(&mut state, "wait %2".to_string())?;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(3, state.last_status);
+
+        handle_line(&mut state, "wait".to_string())?;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(0, state.last_status);
+        assert_eq!(0, state.jobs.iter_mut().count());
+
+        handle_line(&mut state, "wait %1".to_string())?;
+        assert_eq!(127, state.last_status);
+        Ok(())
+    }
+
+    #[test]
+    fn test_special_pids() -> Result<()> {
+        // Forking while another test changes the environment could copy it half-way
+        let _lock = ENV_LOCK.lock().unwrap();
+        let out = env::temp_dir().join(format!("shell_pids_test_{}", process::id()));
+        let mut state = ShellState::default();
+        assert_eq!(Some(process::id().to_string()), state.variables.get("$"));
+        assert_eq!(None, state.variables.get("!"));
+
+        handle_line(&mut state, "sleep 1 &".to_string())?;
+        let job = state.jobs.iter_mut().next().unwrap();
+        let pid = job.child.id();
+        job.child.kill()?;
+        job.child.wait()?;
+        assert_eq!(Some(pid.to_string()), state.variables.get("!"));
+
+        // A subshell still expands $$ to the pid of the shell
+        handle_line(&mut state, format!("(printf $$-$!) > {}", out.display()))?;
+        assert_eq!(
+            format!("{}-{pid}", process::id()),
+            fs::read_to_string(&out)?
+        );
+
+        fs::remove_file(&out)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(&mut state, "sleep 1 &".to_string())?;
+        assert_eq!(
+            "[1]+  Running                 sleep 1 &\n",
+            state.jobs.list()
+        );
+        for job in state.jobs.iter_mut() {
+            job.child.kill()?;
+            job.child.wait()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fg() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(&mut state, "sleep 0.3 &".to_string())?;
+        let start = Instant::now();
+        handle_line(&mut state, "fg %1".to_string())?;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(0, state.last_status);
+        assert_eq!("", state.jobs.list());
+
+        handle_line(&mut state, "fg %1".to_string())?;
+        assert_eq!(1, state.last_status);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fg_stopped() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(&mut state, "sleep 0.3 &".to_string())?;
+        let start = Instant::now();
+        handle_line(&mut state, "kill -STOP $!".to_string())?;
+        // The stop is reported before resuming it
+        while !state.jobs.list().contains("Stopped") {
+            std::thread::sleep(Duration::from_millis(10));
+            state.jobs.reap();
+        }
+        handle_line(&mut state, "fg".to_string())?;
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(0, state.last_status);
+        assert_eq!("", state.jobs.list());
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let target = env::temp_dir().canonicalize()?;
+        let mut state = ShellState::default();
+
+        handle_line(&mut state, format!("alias go='cd {}'", target.display()))?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(
+            Some(&format!("cd {}", target.display())),
+            state.aliases.get("go")
+        );
+        handle_line(&mut state, "alias go".to_string())?;
+        assert_eq!(0, state.last_status);
+        handle_line(&mut state, "alias missing".to_string())?;
+        assert_eq!(1, state.last_status);
+
+        // The alias is expanded before running the command
+        handle_line(&mut state, "go".to_string())?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(target, env::current_dir()?);
+
+        env::set_current_dir(original)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_export() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(&mut state, "LOCAL_VAR=local".to_string())?;
+        handle_line(&mut state, "EXPORTED_VAR=exported".to_string())?;
+        handle_line(
+            &mut state,
+            "export EXPORTED_VAR OTHER_VAR=other".to_string(),
+        )?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(Some("local".to_string()), state.variables.get("LOCAL_VAR"));
+
+        let output = external_command(&state, "env", "env", &[]).output()?;
+        let env = String::from_utf8(output.stdout)?;
+        assert!(env.lines().any(|line| line == "EXPORTED_VAR=exported"));
+        assert!(env.lines().any(|line| line == "OTHER_VAR=other"));
+        assert!(!env.contains("LOCAL_VAR"));
+
+        handle_line(&mut state, "export 1A=b".to_string())?;
+        assert_eq!(1, state.last_status);
+
+        handle_line(
+            &mut state,
+            "unset EXPORTED_VAR LOCAL_VAR NOT_EXISTING".to_string(),
+        )?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(None, state.variables.get("LOCAL_VAR"));
+        let output = external_command(&state, "env", "env", &[]).output()?;
+        let env = String::from_utf8(output.stdout)?;
+        assert!(!env.contains("EXPORTED_VAR"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_source() -> Result<()> {
+        let file = env::temp_dir().join(format!("shell_source_test_{}", std::process::id()));
+        fs::write(
+            &file,
+            "# Comments are ignored\nSOURCED_VAR=sourced # trailing comment\n\nalias sourced_alias='echo sourced'\n",
+        )?;
+        let mut state = ShellState::default();
+
+        handle_line(&mut state, format!("source {}", file.display()))?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(
+            Some("sourced".to_string()),
+            state.variables.get("SOURCED_VAR")
+        );
+        assert!(state.aliases.contains_key("sourced_alias"));
+
+        handle_line(&mut state, format!(". {}", file.display()))?;
+        assert_eq!(0, state.last_status);
+
+        fs::remove_file(&file)?;
+        handle_line(&mut state, format!("source {}", file.display()))?;
+        assert_eq!(1, state.last_status);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cd_home() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let home = env::temp_dir().canonicalize()?;
+        let previous_home = env::var_os("HOME");
+        env::set_var("HOME", &home);
+        let mut state = ShellState::default();
+
+        handle_line(&mut state, "cd".to_string())?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(home, env::current_dir()?);
+
+        env::remove_var("HOME");
+        assert_eq!(1, cd(&mut state, "")?);
+        // The error doesn't end the shell
+        handle_line(&mut state, "cd ~/somewhere".to_string())?;
+        assert_eq!(1, state.last_status);
+        handle_line(&mut state, "cd ~".to_string())?;
+        assert_eq!(1, state.last_status);
+        assert_eq!(home, env::current_dir()?);
+
+        if let Some(previous) = previous_home {
+            env::set_var("HOME", previous);
+        }
+        env::set_current_dir(original)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_quoting_and_redirections() -> Result<()> {
+        let dir = env::temp_dir().join(format!("shell_redirect_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let out = dir.join("out");
+        let mut state = ShellState::default();
+
+        // Quoted arguments are passed as one argument, without the quotes
+        handle_line(
+            &mut state,
+            format!("printf '%s|' 'a  b' \"c d\" e\\ f > {}", out.display()),
+        )?;
+        assert_eq!(0, state.last_status);
+        assert_eq!("a  b|c d|e f|", fs::read_to_string(&out)?);
+
+        // Quoted operators are passed literally
+        handle_line(
+            &mut state,
+            format!("printf '%s|' \"a | b > c\" > {}", out.display()),
+        )?;
+        assert_eq!("a | b > c|", fs::read_to_string(&out)?);
+        assert!(!Path::new("c").exists());
+
+        handle_line(
+            &mut state,
+            format!("printf '%s|' 'a  b' \"c d\" e\\ f > {}", out.display()),
+        )?;
+        handle_line(&mut state, format!("printf more >> {}", out.display()))?;
+        assert_eq!("a  b|c d|e f|more", fs::read_to_string(&out)?);
+
+        let copy = dir.join("copy");
+        handle_line(
+            &mut state,
+            format!("cat < {} > {}", out.display(), copy.display()),
+        )?;
+        assert_eq!("a  b|c d|e f|more", fs::read_to_string(&copy)?);
+
+        let err = dir.join("err");
+        handle_line(
+            &mut state,
+            format!("ls {} 2> {}", dir.join("missing").display(), err.display()),
+        )?;
+        assert_ne!(0, state.last_status);
+        assert!(fs::read_to_string(&err)?.contains("missing"));
+
+        handle_line(
+            &mut state,
+            format!("cat < {}", dir.join("missing").display()),
+        )?;
+        assert_eq!(1, state.last_status);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read() {
+        let mut state = ShellState::default();
+        let mut input = io::Cursor::new("first line\nsecond\n");
+        let read_line = |state: &mut ShellState, args: &[&str], input: &mut io::Cursor<&str>| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            read(state, &args, input)
+        };
+
+        assert_eq!(0, read_line(&mut state, &["LINE"], &mut input));
+        assert_eq!(Some("first line".to_string()), state.variables.get("LINE"));
+        assert_eq!(0, read_line(&mut state, &["-p", "> ", "LINE"], &mut input));
+        assert_eq!(Some("second".to_string()), state.variables.get("LINE"));
+        // End of the input
+        assert_eq!(1, read_line(&mut state, &[], &mut input));
+        assert_eq!(Some(String::new()), state.variables.get("REPLY"));
+        assert_eq!(1, read_line(&mut state, &["1A"], &mut input));
+    }
+
+    #[test]
+    fn test_conditionals() -> Result<()> {
+        let file = env::temp_dir().join(format!("shell_test_builtin_{}", process::id()));
+        fs::write(&file, "")?;
+        let mut state = ShellState::default();
+
+        handle_line(&mut state, format!("test -f {}", file.display()))?;
+        assert_eq!(0, state.last_status);
+        handle_line(&mut state, format!("[ -e {} ]", file.display()))?;
+        assert_eq!(0, state.last_status);
+        handle_line(&mut state, "[ 10 -lt 2 ]".to_string())?;
+        assert_eq!(1, state.last_status);
+        handle_line(&mut state, "X=3; [ $X -gt 2 ] && Y=yes".to_string())?;
+        assert_eq!(Some("yes".to_string()), state.variables.get("Y"));
+        handle_line(&mut state, "[ 1 -eq 1".to_string())?;
+        assert_eq!(2, state.last_status);
+
+        fs::remove_file(&file)?;
+        handle_line(&mut state, format!("test -e {}", file.display()))?;
+        assert_eq!(1, state.last_status);
+        Ok(())
+    }
+
+    #[test]
+    fn test_echo() {
+        let echo_args = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            echo(&args)
+        };
+        assert_eq!("hello world\n", echo_args(&["hello", "world"]));
+        assert_eq!("\n", echo_args(&[]));
+        assert_eq!("a\\nb\n", echo_args(&["a\\nb"]));
+        assert_eq!("hello", echo_args(&["-n", "hello"]));
+        assert_eq!("a\nb\tc\\\n", echo_args(&["-e", "a\\nb\\tc\\\\"]));
+        // Flags combine, only the leading ones are flags
+        assert_eq!("a\nb", echo_args(&["-n", "-e", "a\\nb"]));
+        assert_eq!("a\nb", echo_args(&["-ne", "a\\nb"]));
+        assert_eq!("x -n\n", echo_args(&["x", "-n"]));
+        assert_eq!("-x a\n", echo_args(&["-x", "a"]));
+        assert_eq!("-\n", echo_args(&["-"]));
+    }
+
+    #[test]
+    fn test_builtin_table() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let dir = env::temp_dir().join(format!("shell_builtin_table_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let dir = dir.canonicalize()?;
+        let original = env::current_dir()?;
+        let mut state = ShellState::default();
+
+        let args = vec!["-n".to_string(), "hello".to_string()];
+        assert_eq!(0, (builtins()["echo"].run)(&mut state, &args)?);
+        assert_eq!(0, (builtins()["pwd"].run)(&mut state, &[])?);
+        assert_eq!(2, (builtins()["pwd"].run)(&mut state, &["-x".to_string()])?);
+
+        // The lookup in execute finds them
+        handle_line(&mut state, "pwd -x".to_string())?;
+        assert_eq!(2, state.last_status);
+        handle_line(&mut state, format!("cd {}", dir.display()))?;
+        assert_eq!(dir, env::current_dir()?);
+
+        assert!(is_builtin("[") && !is_builtin("ls"));
+        assert_eq!(Some(&"."), builtin_names().first());
+
+        env::set_current_dir(original)?;
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_across_lines() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(
+            &mut state,
+            "count=1; alias greet='echo hi'; false".to_string(),
+        )?;
+        assert_eq!(1, state.last_status);
+
+        // The next line sees the variables, aliases and status left by the previous one
+        handle_line(&mut state, "count=$count$?; unalias greet".to_string())?;
+        assert_eq!(Some("11".to_string()), state.variables.get("count"));
+        assert!(state.aliases.is_empty());
+        assert_eq!(0, state.last_status);
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_cache_invalidated() -> Result<()> {
+        let root = env::temp_dir().join(format!("shell_cache_test_{}", process::id()));
+        let (first, second) = (root.join("first"), root.join("second"));
+        for dir in [&first, &second] {
+            fs::create_dir_all(dir)?;
+            fs::write(dir.join("tool"), "")?;
+            fs::set_permissions(dir.join("tool"), fs::Permissions::from_mode(0o755))?;
+        }
+        let mut state = ShellState::default();
+        handle_line(&mut state, format!("PATH={}", first.display()))?;
+        assert_eq!(
+            first.join("tool").display().to_string(),
+            lookup(&mut state, "tool")?
+        );
+        // Setting PATH in the shell resolves the command again, while it's still in the first one
+        handle_line(&mut state, format!("PATH={}", second.display()))?;
+        assert_eq!(
+            second.join("tool").display().to_string(),
+            lookup(&mut state, "tool")?
+        );
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_all() {
+        let mut state = ShellState::default();
+        state
+            .aliases
+            .insert("echo".to_string(), "echo -n".to_string());
+        // echo is also an executable in PATH
+        let all = describe_command(&mut state, "echo", true);
+        assert_eq!("echo is aliased to `echo -n'", all[0]);
+        assert_eq!("echo is a shell builtin", all[1]);
+        assert!(all.len() > 2);
+        assert!(all[2..]
+            .iter()
+            .all(|line| line.starts_with("echo is /") && line.ends_with("/echo")));
+        assert_eq!(
+            vec!["echo is aliased to `echo -n'"],
+            describe_command(&mut state, "echo", false)
+        );
+
+        let sh = describe_command(&mut state, "sh", false);
+        assert_eq!(1, sh.len());
+        assert_eq!(sh[0], describe_command(&mut state, "sh", true)[0]);
+        assert!(describe_command(&mut state, "shell_test_missing_command", true).is_empty());
+
+        assert_eq!(
+            0,
+            type_builtin(&mut state, &["-a".to_string(), "cd".to_string()])
+        );
+        assert_eq!(
+            1,
+            type_builtin(
+                &mut state,
+                &["-a".to_string(), "shell_test_missing_command".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_command_string() -> Result<()> {
+        assert_eq!(0, run_command_string("true", "shell", &[])?);
+        assert_eq!(3, run_command_string("sh -c 'exit 3'", "shell", &[])?);
+        let params = ["name".to_string(), "one".to_string()];
+        assert_eq!(
+            0,
+            run_command_string("test $0$1 = nameone", "shell", &params)?
+        );
+        assert_eq!(0, run_command_string("test $0 = shell", "shell", &[])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_stack() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let mut state = ShellState::default();
+        let original = working_dir(&state, false)?;
+        let dir = env::temp_dir().join(format!("shell_dir_stack_test_{}", process::id()));
+        let inner = dir.join("inner");
+        fs::create_dir_all(&inner)?;
+        let (dir, inner) = (dir.canonicalize()?, inner.canonicalize()?);
+
+        assert_eq!(1, popd(&mut state)?);
+        assert_eq!(0, pushd(&mut state, &dir.display().to_string())?);
+        assert_eq!(0, pushd(&mut state, "inner")?);
+        assert_eq!(inner, env::current_dir()?);
+        assert_eq!(
+            format!(
+                "{} {} {}",
+                inner.display(),
+                dir.display(),
+                original.display()
+            ),
+            dirs(&state)?
+        );
+        // A failed pushd leaves the stack as it was
+        assert_eq!(1, pushd(&mut state, "missing")?);
+        assert_eq!(2, state.dir_stack.len());
+
+        assert_eq!(0, popd(&mut state)?);
+        assert_eq!(dir, env::current_dir()?);
+        handle_line(&mut state, "popd".to_string())?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(original.canonicalize()?, env::current_dir()?);
+        assert_eq!(original.display().to_string(), dirs(&state)?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_help() {
+        let all = help("").unwrap();
+        assert!(all.lines().any(|line| line.starts_with("echo [-ne]")));
+        assert!(all.lines().any(|line| line.starts_with("cd [-m] [dir]")));
+        assert_eq!(builtins().len(), all.lines().count());
+        assert_eq!(
+            "cd [-m] [dir]\n    Change the current directory.\n",
+            help("cd").unwrap()
+        );
+        assert_eq!(None, help("ls"));
+    }
+
+    #[test]
+    fn test_umask() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = current_umask();
+        let dir = env::temp_dir().join(format!("shell_umask_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let mut state = ShellState::default();
+
+        handle_line(&mut state, "umask 027".to_string())?;
+        assert_eq!(0, state.last_status);
+        assert_eq!(0o027, current_umask());
+        // The files created by the redirections follow it
+        let file = dir.join("file");
+        handle_line(&mut state, format!("true > {}", file.display()))?;
+        assert_eq!(0o640, fs::metadata(&file)?.permissions().mode() & 0o777);
+
+        assert_eq!(1, umask("999"));
+        assert_eq!(1, umask("1000"));
+        assert_eq!(0o027, current_umask());
+
+        umask(&format!("{original:o}"));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob() -> Result<()> {
+        let dir = env::temp_dir().join(format!("shell_glob_line_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        for file in ["b.txt", "a.txt", "c.rs"] {
+            fs::write(dir.join(file), "")?;
+        }
+        let out = dir.join("out");
+        let mut state = ShellState::default();
+
+        let d = dir.display();
+        handle_line(
+            &mut state,
+            format!("printf '%s|' {d}/*.txt > {}", out.display()),
+        )?;
+        assert_eq!(format!("{d}/a.txt|{d}/b.txt|"), fs::read_to_string(&out)?);
+        // Quoted and unmatched patterns stay literal
+        handle_line(
+            &mut state,
+            format!("printf '%s|' '{d}/*.txt' {d}/*.md > {}", out.display()),
+        )?;
+        assert_eq!(format!("{d}/*.txt|{d}/*.md|"), fs::read_to_string(&out)?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_arithmetic() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(&mut state, "x=$((2 + 3 * 4))".to_string())?;
+        assert_eq!(Some("14".to_string()), state.variables.get("x"));
+        handle_line(&mut state, "y=$(( (x - 7) * $x ))".to_string())?;
+        assert_eq!(Some("98".to_string()), state.variables.get("y"));
+        handle_line(&mut state, "test \"$((y / 2))\" = 49".to_string())?;
+        assert_eq!(0, state.last_status);
+
+        // The command doesn't run
+        handle_line(&mut state, "z=$((1 / 0))".to_string())?;
+        assert_eq!(1, state.last_status);
+        assert_eq!(None, state.variables.get("z"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pwd_symlink() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let root = env::temp_dir()
+            .canonicalize()?
+            .join(format!("shell_pwd_test_{}", process::id()));
+        let real = root.join("real");
+        let link = root.join("link");
+        fs::create_dir_all(&real)?;
+        std::os::unix::fs::symlink(&real, &link)?;
+        let mut state = ShellState::default();
+
+        assert_eq!(0, cd(&mut state, &link.display().to_string())?);
+        assert_eq!(Some(link.clone()), state.pwd);
+        assert_eq!(link, working_dir(&state, false)?);
+        assert_eq!(real, working_dir(&state, true)?);
+        assert_eq!(0, pwd(&state, "-P")?);
+        assert_eq!(2, pwd(&state, "-x")?);
+        // The prompt shows the logical directory
+        state.variables.set("PS1", "\\w");
+        let prompt = prompt::prompt(&state.variables, &working_dir(&state, false)?);
+        assert!(prompt.ends_with("/link"));
+        // .. goes back logically, to the parent of the link
+        assert_eq!(0, cd(&mut state, "..")?);
+        assert_eq!(root, working_dir(&state, false)?);
+
+        assert_eq!(
+            PathBuf::from("/a/c"),
+            logical_join(Path::new("/a/b"), Path::new("./../c/."))
+        );
+        assert_eq!(
+            PathBuf::from("/x"),
+            logical_join(Path::new("/a/b"), Path::new("/x"))
+        );
+
+        env::set_current_dir(original)?;
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_status() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(&mut state, "false".to_string())?;
+        assert_eq!(1, state.last_status);
+        handle_line(&mut state, "sh -c 'exit 3'".to_string())?;
+        assert_eq!(3, state.last_status);
+        handle_line(&mut state, "true".to_string())?;
+        assert_eq!(0, state.last_status);
+        // Killed by SIGTERM
+        handle_line(&mut state, "sh -c 'kill -TERM $$'".to_string())?;
+        assert_eq!(128 + libc::SIGTERM, state.last_status);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inherited_stdio() -> Result<()> {
+        // The standard descriptors of the test process are replaced
+        let _lock = ENV_LOCK.lock().unwrap();
+        let dir = env::temp_dir().join(format!("shell_stdio_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let input = dir.join("input");
+        let output = dir.join("output");
+        fs::write(&input, "from stdin\n")?;
+        let mut state = ShellState::default();
+
+        let redirections = Redirections::open(&[
+            (0, RedirectOp::Input, input.display().to_string()),
+            (1, RedirectOp::Output, output.display().to_string()),
+        ])?;
+        let guard = redirections.apply()?;
+        // cat reads the stdin of the shell and writes to its stdout
+        let result = handle_line(&mut state, "cat".to_string());
+        drop(guard);
+        result?;
+        assert_eq!(0, state.last_status);
+        assert_eq!("from stdin\n", fs::read_to_string(&output)?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_here_doc() -> Result<()> {
+        let dir = env::temp_dir().join(format!("shell_here_doc_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let out = dir.join("out");
+        let mut state = ShellState::default();
+        state.variables.set("NAME", "world");
+
+        handle_line(
+            &mut state,
+            format!(
+                "cat << EOF > {}\nhello $NAME\nit's here\nEOF",
+                out.display()
+            ),
+        )?;
+        assert_eq!(0, state.last_status);
+        assert_eq!("hello world\nit's here\n", fs::read_to_string(&out)?);
+
+        // The leading tabs are stripped, the quoted delimiter keeps the body literal
+        handle_line(
+            &mut state,
+            format!("cat <<- 'END' > {}\n\t$NAME\n\tEND", out.display()),
+        )?;
+        assert_eq!("$NAME\n", fs::read_to_string(&out)?);
+
+        // Here-documents in sourced files
+        let script = dir.join("script");
+        fs::write(
+            &script,
+            format!("cat << EOF > {}\nsourced\nEOF\nAFTER=1\n", out.display()),
+        )?;
+        handle_line(&mut state, format!("source {}", script.display()))?;
+        assert_eq!("sourced\n", fs::read_to_string(&out)?);
+        assert_eq!(Some("1".to_string()), state.variables.get("AFTER"));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unalias() -> Result<()> {
+        let mut state = ShellState::default();
+        handle_line(&mut state, "alias a=echo b=pwd c=ls".to_string())?;
+        assert_eq!(3, state.aliases.len());
+
+        handle_line(&mut state, "unalias a".to_string())?;
+        assert_eq!(0, state.last_status);
+        assert!(!state.aliases.contains_key("a"));
+        handle_line(&mut state, "unalias a".to_string())?;
+        assert_eq!(1, state.last_status);
+
+        handle_line(&mut state, "unalias -a".to_string())?;
+        assert_eq!(0, state.last_status);
+        assert!(state.aliases.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cdpath() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let base = env::temp_dir()
+            .canonicalize()?
+            .join(format!("shell_cdpath_test_{}", process::id()));
+        fs::create_dir_all(base.join("sub"))?;
+        let elsewhere = env::temp_dir().canonicalize()?;
+        env::set_current_dir(&elsewhere)?;
+        let mut state = ShellState::default();
+
+        assert_eq!(1, cd(&mut state, "sub")?);
+        state.variables.set(
+            "CDPATH",
+            &format!(".:/shell_missing_dir:{}", base.display()),
+        );
+        assert_eq!(0, cd(&mut state, "sub")?);
+        assert_eq!(base.join("sub"), env::current_dir()?);
+
+        // Paths starting with . don't use CDPATH
+        env::set_current_dir(&elsewhere)?;
+        assert_eq!(1, cd(&mut state, "./sub")?);
+
+        env::set_current_dir(original)?;
+        fs::remove_dir_all(&base)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_cd_create() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let base = env::temp_dir()
+            .canonicalize()?
+            .join(format!("shell_cd_create_test_{}", process::id()));
+        fs::create_dir_all(&base)?;
+        env::set_current_dir(&base)?;
+        let mut state = ShellState::default();
+
+        // Without the flag nothing is created
+        assert_eq!(1, cd(&mut state, "newdir")?);
+        assert!(!base.join("newdir").exists());
+
+        let args = ["-m".to_string(), "newdir/nested".to_string()];
+        assert_eq!(0, (builtins()["cd"].run)(&mut state, &args)?);
+        assert_eq!(base.join("newdir/nested"), env::current_dir()?);
+        // An existing directory is just entered
+        let args = ["-m".to_string(), base.display().to_string()];
+        assert_eq!(0, (builtins()["cd"].run)(&mut state, &args)?);
+        assert_eq!(base, env::current_dir()?);
+
+        // It can't be created under a file
+        fs::write(base.join("file"), "")?;
+        let args = ["-m".to_string(), "file/dir".to_string()];
+        assert_eq!(1, (builtins()["cd"].run)(&mut state, &args)?);
+        assert_eq!(2, (builtins()["cd"].run)(&mut state, &["-m".to_string()])?);
+        assert_eq!(base, env::current_dir()?);
+
+        env::set_current_dir(original)?;
+        fs::remove_dir_all(&base)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_cd_errors() -> Result<()> {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let dir = env::temp_dir().join(format!("shell_cd_errors_test_{}", process::id()));
+        let file = dir.join("file");
+        let locked = dir.join("locked");
+        fs::create_dir_all(&locked)?;
+        fs::write(&file, "")?;
+        let mut state = ShellState::default();
+
+        let file = file.display().to_string();
+        assert_eq!(1, cd(&mut state, &file)?);
+        let error = env::set_current_dir(&file).unwrap_err();
+        assert_eq!(
+            format!("cd: {file}: Not a directory"),
+            cd_error(&file, &error)
+        );
+
+        let locked_path = locked.display().to_string();
+        let denied = io::Error::from_raw_os_error(libc::EACCES);
+        assert_eq!(
+            format!("cd: {locked_path}: Permission denied"),
+            cd_error(&locked_path, &denied)
+        );
+        // Root can enter any directory
+        // Safety: geteuid has no preconditions
+        if unsafe { libc::geteuid() } != 0 {
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))?;
+            assert_eq!(1, cd(&mut state, &locked_path)?);
+            let error = env::set_current_dir(&locked).unwrap_err();
+            assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+        }
+        assert_eq!(original, env::current_dir()?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_redirections() -> Result<()> {
+        let dir = env::temp_dir().join(format!("shell_dup_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let out = dir.join("out");
+        let both = "sh -c 'echo out; echo err >&2'";
+        let mut state = ShellState::default();
+
+        // stderr goes where stdout points at that moment
+        handle_line(&mut state, format!("{both} > {} 2>&1", out.display()))?;
+        assert_eq!("out\nerr\n", fs::read_to_string(&out)?);
+        handle_line(
+            &mut state,
+            format!("{both} 2> {} > /dev/null", out.display()),
+        )?;
+        assert_eq!("err\n", fs::read_to_string(&out)?);
+        handle_line(&mut state, format!("{both} > /dev/null 2>&1"))?;
+        assert_eq!(0, state.last_status);
+
+        // Any descriptor number
+        handle_line(
+            &mut state,
+            format!("sh -c 'echo three >&3' 3> {}", out.display()),
+        )?;
+        assert_eq!("three\n", fs::read_to_string(&out)?);
+        handle_line(&mut state, "echo a 2>&200".to_string())?;
+        assert_eq!(1, state.last_status);
+        handle_line(&mut state, "echo a 2>&x".to_string())?;
+        assert_eq!(1, state.last_status);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec() -> Result<()> {
+        let dir = env::temp_dir().join(format!("shell_exec_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let out = dir.join("out");
+        let mut state = ShellState::default();
+
+        // A failed exec keeps the shell running
+        handle_line(&mut state, "exec shell_test_missing_command".to_string())?;
+        assert_eq!(127, state.last_status);
+
+        // Without a command the redirection stays open in the shell
+        handle_line(&mut state, format!("exec 60> {}", out.display()))?;
+        assert_eq!(0, state.last_status);
+        handle_line(&mut state, "sh -c 'echo kept' >&60".to_string())?;
+        assert_eq!(0, state.last_status);
+        // Safety: closing the descriptor opened by exec
+        unsafe { libc::close(60) };
+        assert_eq!("kept\n", fs::read_to_string(&out)?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_line() -> Result<()> {
+        let mut state = ShellState::default();
+        for line in ["", "   ", " \t ", "# only a comment"] {
+            handle_line(&mut state, line.to_string())?;
+            // Nothing runs, so it's not a command not found
+            assert_eq!(0, state.last_status);
+        }
+        // The status of the previous command is kept, like bash
+        handle_line(&mut state, "false".to_string())?;
+        handle_line(&mut state, "  ".to_string())?;
+        assert_eq!(1, state.last_status);
+        Ok(())
+    }
+
+    #[test]
+    fn test_eof() -> Result<()> {
+        let mut state = ShellState {
+            last_status: 3,
+            ..Default::default()
+        };
+        assert_eq!(3, repl(&mut state, |_, _| Err(ReadlineError::Eof))?);
+
+        // Exits with the status of the last command
+        let mut lines = vec!["true", "false"].into_iter();
+        let mut state = ShellState::default();
+        let status = repl(&mut state, |_, _| {
+            lines.next().map(str::to_string).ok_or(ReadlineError::Eof)
+        })?;
+        assert_eq!(1, status);
+        assert_eq!(2, state.history.entries().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_subshell() -> Result<()> {
+        // Forking while another test changes the environment could copy it half-way
+        let _lock = ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let dir = env::temp_dir().join(format!("shell_subshell_test_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let mut state = ShellState::default();
+
+        let line = format!("(cd {} && touch marker; X=inner)", dir.display());
+        handle_line(&mut state, line)?;
+        assert_eq!(0, state.last_status);
+        assert!(dir.join("marker").exists());
+        assert_eq!(original, env::current_dir()?);
+        assert_eq!(None, state.variables.get("X"));
+
+        handle_line(&mut state, "(exit 3)".to_string())?;
+        assert_eq!(3, state.last_status);
+        handle_line(&mut state, "(false) || Y=1".to_string())?;
+        assert_eq!(Some("1".to_string()), state.variables.get("Y"));
+
+        // The redirections of the group apply to all its commands
+        let out = dir.join("out");
+        let line = format!("(printf a; printf b) > {}", out.display());
+        handle_line(&mut state, line)?;
+        assert_eq!("ab", fs::read_to_string(&out)?);
+
+        handle_line(&mut state, "( echo".to_string())?;
+        assert_eq!(2, state.last_status);
+        handle_line(&mut state, "echo )".to_string())?;
+        assert_eq!(2, state.last_status);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipelines() -> Result<()> {
+        // Forking while another test changes the environment could copy it half-way
+        let _lock = ENV_LOCK.lock().unwrap();
+        let out = env::temp_dir().join(format!("shell_pipeline_test_{}", process::id()));
+        let mut state = ShellState::default();
+
+        let line = format!("printf 'b\\na\\n' | sort | tr a-z A-Z > {}", out.display());
+        handle_line(&mut state, line)?;
+        assert_eq!(0, state.last_status);
+        assert_eq!("A\nB\n", fs::read_to_string(&out)?);
+        // Builtins and subshells are stages too
+        let line = format!(
+            "printf 'hi\\n' | (read line; printf $line-$line) > {}",
+            out.display()
+        );
+        handle_line(&mut state, line)?;
+        assert_eq!("hi-hi", fs::read_to_string(&out)?);
+
+        // By default the status is the one of the last stage
+        handle_line(&mut state, "true | sh -c 'exit 3' | true".to_string())?;
+        assert_eq!(0, state.last_status);
+        handle_line(&mut state, "true | true | sh -c 'exit 4'".to_string())?;
+        assert_eq!(4, state.last_status);
+        // With pipefail a failing middle stage fails the pipeline
+        handle_line(&mut state, "set -o pipefail".to_string())?;
+        handle_line(&mut state, "true | sh -c 'exit 3' | true".to_string())?;
+        assert_eq!(3, state.last_status);
+        handle_line(&mut state, "true | true".to_string())?;
+        assert_eq!(0, state.last_status);
+        handle_line(&mut state, "set +o pipefail".to_string())?;
+        handle_line(&mut state, "true | sh -c 'exit 3' | true".to_string())?;
+        assert_eq!(0, state.last_status);
+
+        fs::remove_file(&out)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_set() {
+        let mut state = ShellState::default();
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(0, set(&mut state, &args(&["-e", "-o", "pipefail"])));
+        assert!(state.errexit && state.pipefail);
+        assert_eq!(0, set(&mut state, &args(&["+o", "errexit"])));
+        assert!(!state.errexit && state.pipefail);
+        assert_eq!(
+            0,
+            set(&mut state, &args(&["-o", "errexit", "+o", "pipefail"]))
+        );
+        assert!(state.errexit && !state.pipefail);
+        assert_eq!(0, set(&mut state, &args(&["+e"])));
+        assert!(!state.errexit);
+        assert_eq!(
+            "errexit        \toff\npipefail       \toff\n",
+            options(&state)
+        );
+
+        assert_eq!(2, set(&mut state, &args(&["-x"])));
+        assert_eq!(2, set(&mut state, &args(&["-o", "missing"])));
+        assert_eq!(2, set(&mut state, &args(&["e"])));
+    }
+
+    #[test]
+    fn test_pipeline_status() {
+        assert_eq!(0, pipeline_status(&[1, 2, 0], false));
+        assert_eq!(2, pipeline_status(&[0, 2, 0], true));
+        // The first failing stage
+        assert_eq!(1, pipeline_status(&[1, 3, 0], true));
+        assert_eq!(0, pipeline_status(&[0, 0], true));
+    }
+
+    #[test]
+    fn test_history_expansion() -> Result<()> {
+        let out = env::temp_dir().join(format!("shell_history_expansion_{}", process::id()));
+        let _ = fs::remove_file(&out);
+        let first = format!("printf a >> {}", out.display());
+        let second = format!("printf b >> {}", out.display());
+        // Out of range, nothing runs
+        let mut lines = vec![first.as_str(), second.as_str(), "!!", "!1", "!9"].into_iter();
+        let mut state = ShellState::default();
+        repl(&mut state, |_, _| {
+            lines.next().map(str::to_string).ok_or(ReadlineError::Eof)
+        })?;
+        assert_eq!("abba", fs::read_to_string(&out)?);
+        // The expanded lines are the ones recorded
+        assert_eq!(
+            &[first.clone(), second.clone(), second, first],
+            state.history.entries()
+        );
+        fs::remove_file(&out)?;
+        Ok(())
     }
-    Err(anyhow::anyhow!("Not found"))
 }
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fs::{self, DirEntry};
+use std::path::Path;
+
+use anyhow::Result;
+
+// Iterates over the executable files found in the directories of the given PATH.
+// Directories that can't be read are skipped.
+pub(crate) fn executable_entries(path: &str) -> impl Iterator<Item = DirEntry> + '_ {
+    path.split(':')
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()))
+        .filter(|entry| {
+            let path = entry.path();
+            path.is_file() && is_executable(&path)
+        })
+}
+
+// Returns if the file can be executed by someone, following symlinks.
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+// Without the permission bits any existing file is taken as executable.
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.exists()
+}
+
+// Iterates over the executable files in the given PATH matching the command, in the PATH order.
+// Only the command is checked in each directory, the directories are not listed.
+pub(crate) fn executables_in_path<'a>(
+    cmd: &'a str,
+    path: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    path.split(':')
+        .map(move |dir| Path::new(dir).join(cmd))
+        .filter(|file| file.is_file() && is_executable(file))
+        .map(|file| file.display().to_string())
+}
+
+// Returns the first executable file in the given PATH matching the command.
+fn find_in_path(cmd: &str, path: &str) -> Option<String> {
+    executables_in_path(cmd, path).next()
+}
+
+// Commands already resolved in PATH, cleared when a different PATH is given.
+#[derive(Debug, Default)]
+pub(crate) struct PathCache {
+    // The PATH the commands were resolved with
+    path: String,
+    resolved: HashMap<String, String>,
+}
+
+impl PathCache {
+    // Returns the path of the first executable file in the given PATH matching the command.
+    // A command with a / is a path itself (like ./script.sh), it's not searched in PATH.
+    pub(crate) fn lookup(&mut self, cmd: &str, path: &str) -> Result<String> {
+        if cmd.contains('/') {
+            let file = Path::new(cmd);
+            return (file.is_file() && is_executable(file))
+                .then(|| cmd.to_string())
+                .ok_or(anyhow::anyhow!("Not found"));
+        }
+        self.resolve(cmd, path, find_in_path)
+            .ok_or(anyhow::anyhow!("Not found"))
+    }
+
+    // Resolves the command with the scan unless it's cached.
+    // A cached file that is not executable anymore is resolved again.
+    fn resolve(
+        &mut self,
+        cmd: &str,
+        path: &str,
+        scan: impl FnOnce(&str, &str) -> Option<String>,
+    ) -> Option<String> {
+        if self.path != path {
+            self.path = path.to_string();
+            self.resolved.clear();
+        }
+        if let Some(file) = self.resolved.get(cmd) {
+            if is_executable(Path::new(file)) {
+                return Some(file.clone());
+            }
+        }
+        let file = scan(cmd, path)?;
+        self.resolved.insert(cmd.to_string(), file.clone());
+        Some(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_skips_not_executable() -> Result<()> {
+        let root = env::temp_dir().join(format!("shell_path_test_{}", std::process::id()));
+        let first = root.join("first");
+        let second = root.join("second");
+        fs::create_dir_all(&first)?;
+        fs::create_dir_all(&second)?;
+        let not_executable = first.join("tool");
+        fs::write(&not_executable, "")?;
+        fs::set_permissions(&not_executable, fs::Permissions::from_mode(0o644))?;
+        let executable = second.join("tool");
+        fs::write(&executable, "")?;
+        fs::set_permissions(&executable, fs::Permissions::from_mode(0o755))?;
+
+        let path = format!("{}:{}", first.display(), second.display());
+        assert_eq!(
+            Some(executable.display().to_string()),
+            find_in_path("tool", &path)
+        );
+        assert_eq!(None, find_in_path("tool", &first.display().to_string()));
+
+        // Every executable match, in the PATH order
+        let other = first.join("other");
+        fs::write(&other, "")?;
+        fs::set_permissions(&other, fs::Permissions::from_mode(0o755))?;
+        fs::copy(&other, second.join("other"))?;
+        let all: Vec<String> = executables_in_path("other", &path).collect();
+        assert_eq!(
+            vec![
+                other.display().to_string(),
+                second.join("other").display().to_string()
+            ],
+            all
+        );
+        let all: Vec<String> = executables_in_path("tool", &path).collect();
+        assert_eq!(vec![executable.display().to_string()], all);
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut cache = PathCache::default();
+        let path = env::var("PATH").unwrap();
+        let sh = cache.lookup("sh", &path).expect("sh is in PATH");
+        assert!(sh.ends_with("/sh"));
+        assert!(cache.lookup("sh", "").is_err());
+        let missing = cache.lookup("shell_test_missing_command", &path);
+        assert_eq!("Not found", missing.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_lookup_relative() -> Result<()> {
+        let _lock = crate::tests::ENV_LOCK.lock().unwrap();
+        let original = env::current_dir()?;
+        let dir = env::temp_dir().join(format!("shell_relative_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("localfile"), "")?;
+        fs::set_permissions(dir.join("localfile"), fs::Permissions::from_mode(0o755))?;
+        fs::write(dir.join("data"), "")?;
+        fs::set_permissions(dir.join("data"), fs::Permissions::from_mode(0o644))?;
+        env::set_current_dir(&dir)?;
+
+        let mut cache = PathCache::default();
+        let path = env::var("PATH")?;
+        assert_eq!("./localfile", cache.lookup("./localfile", &path)?);
+        let absolute = dir.join("localfile").display().to_string();
+        assert_eq!(absolute, cache.lookup(&absolute, &path)?);
+        assert!(cache.lookup("./data", &path).is_err());
+        assert!(cache.lookup("./missing", &path).is_err());
+        // Without a / it's only searched in PATH
+        assert!(cache.lookup("localfile", &path).is_err());
+
+        env::set_current_dir(original)?;
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut cache = PathCache::default();
+        let scans = std::cell::Cell::new(0);
+        let path = env::var("PATH").unwrap();
+        let sh = cache.lookup("sh", &path).expect("sh is in PATH");
+        let scan = |cmd: &str, path: &str| {
+            scans.set(scans.get() + 1);
+            find_in_path(cmd, path)
+        };
+
+        assert_eq!(Some(sh.clone()), cache.resolve("sh", &path, scan));
+        assert_eq!(Some(sh.clone()), cache.resolve("sh", &path, scan));
+        // Missing commands are not cached
+        cache.resolve("shell_test_missing_command", &path, scan);
+        cache.resolve("shell_test_missing_command", &path, scan);
+        assert_eq!(2, scans.get());
+
+        // A different PATH resolves again
+        let dir = Path::new(&sh).parent().unwrap().display().to_string();
+        assert_eq!(Some(sh), cache.resolve("sh", &dir, scan));
+        assert_eq!(3, scans.get());
+    }
+}
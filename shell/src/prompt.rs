@@ -0,0 +1,81 @@
+use std::env;
+use std::path::Path;
+
+use crate::variables::Variables;
+
+const DEFAULT_PROMPT: &str = "$ ";
+
+// The prompt for the next line, from PS1 (shell variable or environment) or the default one.
+pub(crate) fn prompt(variables: &Variables, cwd: &Path) -> String {
+    let Some(ps1) = variables.get("PS1") else {
+        return DEFAULT_PROMPT.to_string();
+    };
+    let home = env::var("HOME").ok();
+    let user = env::var("USER").unwrap_or_default();
+    render(&ps1, cwd, home.as_deref().map(Path::new), &user)
+}
+
+// Expands the escapes of PS1:
+// \w the current directory (with the home as ~), \u the username, \$ a $ and \\ a backslash.
+// Any other escape is kept as it is.
+pub(crate) fn render(ps1: &str, cwd: &Path, home: Option<&Path>, user: &str) -> String {
+    let mut rendered = String::with_capacity(ps1.len());
+    let mut chars = ps1.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            rendered.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('w') => {
+                let dir = match home.and_then(|home| cwd.strip_prefix(home).ok()) {
+                    Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+                    Some(rest) => format!("~/{}", rest.display()),
+                    None => cwd.display().to_string(),
+                };
+                rendered.push_str(&dir);
+            }
+            Some('u') => rendered.push_str(user),
+            Some('$') => rendered.push('$'),
+            Some('\\') => rendered.push('\\'),
+            Some(other) => {
+                rendered.push('\\');
+                rendered.push(other);
+            }
+            None => rendered.push('\\'),
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let home = Some(Path::new("/home/user"));
+        assert_eq!(
+            "user:~/src$ ",
+            render("\\u:\\w\\$ ", Path::new("/home/user/src"), home, "user")
+        );
+        assert_eq!(
+            "[~] ",
+            render("[\\w] ", Path::new("/home/user"), home, "user")
+        );
+        assert_eq!("/tmp > ", render("\\w > ", Path::new("/tmp"), home, "user"));
+        // A directory only sharing the prefix is not in the home
+        assert_eq!(
+            "/home/username",
+            render("\\w", Path::new("/home/username"), home, "user")
+        );
+        assert_eq!("\\x \\", render("\\x \\\\", Path::new("/"), None, ""));
+    }
+
+    #[test]
+    fn test_prompt() {
+        let mut variables = Variables::default();
+        variables.set("PS1", "custom> ");
+        assert_eq!("custom> ", prompt(&variables, Path::new("/")));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, process};
+
+use anyhow::{anyhow, Result};
+
+use crate::tokenizer::RedirectOp;
+
+// The files opened for the redirections of a command, by the file descriptor they replace.
+#[derive(Debug, Default)]
+pub(crate) struct Redirections {
+    files: BTreeMap<u32, File>,
+}
+
+impl Redirections {
+    // Opens the targets left to right. Like bash every file is opened (or created) but the
+    // last redirection of a descriptor wins, and n>&m duplicates where m points at that moment.
+    pub(crate) fn open(redirects: &[(u32, RedirectOp, String)]) -> Result<Self> {
+        let mut files: BTreeMap<u32, File> = BTreeMap::new();
+        for (fd, op, target) in redirects {
+            let file = match op {
+                RedirectOp::Output => File::create(target),
+                RedirectOp::Append => OpenOptions::new().append(true).create(true).open(target),
+                RedirectOp::Input => File::open(target),
+                RedirectOp::HereDoc { .. } => {
+                    files.insert(*fd, here_doc(target)?);
+                    continue;
+                }
+                RedirectOp::DupOutput | RedirectOp::DupInput => {
+                    let source: u32 = target
+                        .parse()
+                        .map_err(|_| anyhow!("{target}: ambiguous redirect"))?;
+                    let file = match files.get(&source) {
+                        Some(file) => file.try_clone()?,
+                        None => duplicate_shell_fd(source)
+                            .map_err(|_| anyhow!("{source}: Bad file descriptor"))?,
+                    };
+                    files.insert(*fd, file);
+                    continue;
+                }
+            };
+            let file = file.map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => anyhow!("{target}: No such file or directory"),
+                _ => anyhow!("{target}: {e}"),
+            })?;
+            files.insert(*fd, file);
+        }
+        Ok(Redirections { files })
+    }
+
+    // Redirects the descriptors of the child, the rest are inherited from the shell.
+    // The files have to live until the child is spawned.
+    pub(crate) fn redirect_child(&mut self, command: &mut Command) {
+        if let Some(file) = self.files.remove(&0) {
+            command.stdin(file);
+        }
+        if let Some(file) = self.files.remove(&1) {
+            command.stdout(file);
+        }
+        if let Some(file) = self.files.remove(&2) {
+            command.stderr(file);
+        }
+        if self.files.is_empty() {
+            return;
+        }
+        let mut fds: Vec<(i32, i32)> = self
+            .files
+            .iter()
+            .map(|(fd, file)| (file.as_raw_fd(), *fd as i32))
+            .collect();
+        let min = self.above_targets();
+        // Safety: only dup2 and fcntl run in the child, they are async-signal-safe
+        unsafe {
+            command.pre_exec(move || {
+                // A source can be the target of another redirection, so they are all moved out
+                // of the way before any is overwritten
+                for (source, _) in fds.iter_mut() {
+                    *source = libc::fcntl(*source, libc::F_DUPFD_CLOEXEC, min);
+                    if *source < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                for (source, fd) in &fds {
+                    if libc::dup2(*source, *fd) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Redirects the descriptors of the shell itself, for the builtins.
+    // They are restored when the guard is dropped.
+    pub(crate) fn apply(self) -> io::Result<RestoreGuard> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+        let min = self.above_targets();
+        // A file can be open in the target of another redirection, so they are all moved out of
+        // the way first. The original descriptors are closed, to save the ones of the shell.
+        let mut files = vec![];
+        for (fd, file) in self.files {
+            // Safety: fcntl returns a new descriptor owned by the file
+            let moved = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, min) };
+            if moved < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            files.push((fd as i32, unsafe { OwnedFd::from_raw_fd(moved) }));
+        }
+        let mut guard = RestoreGuard { saved: vec![] };
+        for (fd, file) in &files {
+            // Safety: fcntl and dup2 only work with descriptors owned by the shell
+            unsafe {
+                // Saved out of the way of the targets, a closed one is closed again
+                let saved = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, min);
+                let saved = (saved >= 0).then(|| OwnedFd::from_raw_fd(saved));
+                guard.saved.push((*fd, saved));
+                if libc::dup2(file.as_raw_fd(), *fd) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(guard)
+    }
+
+    // The lowest descriptor above all the targets, and the ones a program can expect to use.
+    fn above_targets(&self) -> i32 {
+        let last = self.files.keys().last().map_or(0, |fd| *fd as i32 + 1);
+        last.max(SAVED_FD_MIN)
+    }
+}
+
+// The shell's descriptors are saved from this number on while a builtin is redirected, like bash.
+const SAVED_FD_MIN: i32 = 10;
+
+// A new file for the open descriptor of the shell.
+fn duplicate_shell_fd(fd: u32) -> io::Result<File> {
+    // Safety: fcntl returns a new descriptor owned by the file
+    unsafe {
+        let duplicate = libc::fcntl(fd as i32, libc::F_DUPFD_CLOEXEC, 0);
+        if duplicate < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(File::from_raw_fd(duplicate))
+    }
+}
+
+// A file with the body of the here-document, to read it as stdin.
+// It's removed right away, it lives until the file is closed.
+fn here_doc(body: &str) -> Result<File> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("shell_here_doc_{}_{count}", process::id()));
+    let mut file = File::create(&path)?;
+    file.write_all(body.as_bytes())?;
+    let file = File::open(&path);
+    fs::remove_file(&path)?;
+    Ok(file?)
+}
+
+// Restores the descriptors of the shell replaced by the redirections.
+pub(crate) struct RestoreGuard {
+    // The saved copy of each descriptor, None if it was closed
+    saved: Vec<(i32, Option<OwnedFd>)>,
+}
+
+impl RestoreGuard {
+    // Keeps the redirections of the shell instead of restoring them.
+    pub(crate) fn keep(mut self) {
+        self.saved.clear();
+    }
+}
+
+impl Drop for RestoreGuard {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        for (fd, saved) in self.saved.iter().rev() {
+            // Safety: the saved descriptor is a duplicate owned by the guard
+            unsafe {
+                match saved {
+                    Some(saved) => libc::dup2(saved.as_raw_fd(), *fd),
+                    None => libc::close(*fd),
+                }
+            };
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+// Pid of the child running in the foreground, 0 when the shell is waiting for input.
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+// Set each time the shell receives a SIGINT
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Installs the SIGINT handler, so Ctrl-C interrupts the running command instead of the shell.
+// The foreground children are in the shell's process group, so a SIGINT from the terminal
+// already reaches them. A SIGINT sent just to the shell (kill) is forwarded to the child.
+pub(crate) fn install_sigint_handler() -> io::Result<()> {
+    // Safety: the handler only uses atomics and kill, which are async-signal-safe
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_sigint as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn handle_sigint(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    // Safety: the kernel passes a valid siginfo with SA_SIGINFO
+    let sent_by_user = unsafe { (*info).si_code == libc::SI_USER };
+    interrupt(&INTERRUPTED, &FOREGROUND, sent_by_user);
+}
+
+// Records the SIGINT and forwards it to the foreground child if it was only sent to the shell.
+fn interrupt(interrupted: &AtomicBool, foreground: &AtomicI32, sent_by_user: bool) {
+    interrupted.store(true, Ordering::SeqCst);
+    let pid = foreground.load(Ordering::SeqCst);
+    if pid > 0 && sent_by_user {
+        // Safety: only signals the foreground child
+        unsafe { libc::kill(pid, libc::SIGINT) };
+    }
+}
+
+// Tracks the child running in the foreground, None once it finished.
+pub(crate) fn set_foreground(pid: Option<u32>) {
+    FOREGROUND.store(pid.map_or(0, |pid| pid as i32), Ordering::SeqCst);
+}
+
+// Returns if a SIGINT was received since the last call.
+pub(crate) fn take_interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    // Without raising a real SIGINT, that could reach the children of the other tests
+    #[test]
+    fn test_interrupt() -> anyhow::Result<()> {
+        install_sigint_handler()?;
+        let interrupted = AtomicBool::new(false);
+        let foreground = AtomicI32::new(0);
+
+        // Without a child it's only recorded
+        interrupt(&interrupted, &foreground, true);
+        assert!(interrupted.swap(false, Ordering::SeqCst));
+
+        // Sent to the whole process group by the terminal, the child already got it
+        let mut child = Command::new("sleep").arg("5").spawn()?;
+        foreground.store(child.id() as i32, Ordering::SeqCst);
+        interrupt(&interrupted, &foreground, false);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(None, child.try_wait()?);
+
+        // Sent just to the shell, it's forwarded to the foreground child
+        interrupt(&interrupted, &foreground, true);
+        let status = child.wait()?;
+        assert_eq!(Some(libc::SIGINT), status.signal());
+        assert!(interrupted.load(Ordering::SeqCst));
+        Ok(())
+    }
+}
@@ -0,0 +1,588 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use anyhow::{anyhow, Result};
+
+// How the text of a part of a word was quoted, it decides which expansions apply to it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Quoting {
+    Unquoted,
+    // Single quotes and escaped characters, taken literally
+    Single,
+    Double,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Part {
+    pub(crate) text: String,
+    pub(crate) quoting: Quoting,
+}
+
+// A word made of differently quoted parts, like a"b c"'d'.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct Word {
+    pub(crate) parts: Vec<Part>,
+}
+
+impl Word {
+    pub(crate) fn push(&mut self, c: char, quoting: Quoting) {
+        match self.parts.last_mut() {
+            Some(part) if part.quoting == quoting => part.text.push(c),
+            _ => self.parts.push(Part {
+                text: c.to_string(),
+                quoting,
+            }),
+        }
+    }
+
+    // The text of the word after removing the quotes, without any expansion.
+    pub(crate) fn literal(&self) -> String {
+        self.parts.iter().map(|part| part.text.as_str()).collect()
+    }
+
+    // The unquoted text of the word, None if any part of it is quoted.
+    pub(crate) fn unquoted(&self) -> Option<String> {
+        self.parts
+            .iter()
+            .all(|part| part.quoting == Quoting::Unquoted)
+            .then(|| self.literal())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Operator {
+    And,        // &&
+    Or,         // ||
+    Semicolon,  // ;
+    Background, // &
+    Pipe,       // |
+    OpenParen,  // (
+    CloseParen, // )
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum RedirectOp {
+    Output, // >
+    Append, // >>
+    Input,  // <
+    // << or <<- (stripping the leading tabs of the body)
+    HereDoc { strip_tabs: bool },
+    // >& and <&, the target is the descriptor to duplicate
+    DupOutput,
+    DupInput,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Token {
+    Word(Word),
+    Operator(Operator),
+    // The file descriptor is given by the digits right before the operator, like 2>
+    // The target is the next word, for a here-document it's replaced by the body once read
+    Redirect { fd: Option<u32>, op: RedirectOp },
+}
+
+// Why the input is not complete, it continues in the next line.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Incomplete {
+    // Trailing \ outside of quotes
+    Backslash,
+    // The quote left open
+    Quote(char),
+    // A here-document without its delimiter line
+    HereDoc,
+}
+
+#[derive(Default)]
+struct Tokenizer {
+    tokens: Vec<Token>,
+    word: Option<Word>,
+    // The next word is the delimiter of a here-document, stripping tabs or not
+    delimiter_next: Option<bool>,
+    // Index of the delimiter words of the here-documents waiting for their body
+    here_docs: Vec<(usize, bool)>,
+}
+
+impl Tokenizer {
+    fn push(&mut self, c: char, quoting: Quoting) {
+        self.word.get_or_insert_with(Word::default).push(c, quoting);
+    }
+
+    // Starts an empty word if there is none, so empty quotes ("") are still a word
+    fn start_word(&mut self) {
+        self.word.get_or_insert_with(Word::default);
+    }
+
+    fn finish_word(&mut self) {
+        if let Some(word) = self.word.take() {
+            if let Some(strip_tabs) = self.delimiter_next.take() {
+                self.here_docs.push((self.tokens.len(), strip_tabs));
+            }
+            self.tokens.push(Token::Word(word));
+        }
+    }
+
+    fn operator(&mut self, operator: Operator) {
+        self.finish_word();
+        self.delimiter_next = None;
+        self.tokens.push(Token::Operator(operator));
+    }
+
+    // An unquoted word with only digits right before the redirection is its file descriptor
+    fn redirect(&mut self, op: RedirectOp) {
+        let fd = self
+            .word
+            .as_ref()
+            .and_then(Word::unquoted)
+            .filter(|text| text.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|text| text.parse().ok());
+        if fd.is_some() {
+            self.word = None;
+        }
+        self.finish_word();
+        self.delimiter_next = match op {
+            RedirectOp::HereDoc { strip_tabs } => Some(strip_tabs),
+            _ => None,
+        };
+        self.tokens.push(Token::Redirect { fd, op });
+    }
+
+    // Reads the bodies of the pending here-documents from the next lines, replacing their
+    // delimiters with them. With a quoted delimiter the body is literal, otherwise it's expanded
+    // like in double quotes. Returns false if the input ended before any delimiter line.
+    fn read_here_docs(&mut self, chars: &mut Peekable<Chars>) -> bool {
+        let mut complete = true;
+        for (index, strip_tabs) in std::mem::take(&mut self.here_docs) {
+            let Token::Word(delimiter) = &self.tokens[index] else {
+                unreachable!("here-document delimiters are words")
+            };
+            let quoting = match delimiter.unquoted() {
+                Some(_) => Quoting::Double,
+                None => Quoting::Single,
+            };
+            let delimiter = delimiter.literal();
+            let mut body = String::new();
+            let mut found = false;
+            while chars.peek().is_some() {
+                let line: String = chars.by_ref().take_while(|c| *c != '\n').collect();
+                let line = match strip_tabs {
+                    true => line.trim_start_matches('\t'),
+                    false => &line,
+                };
+                if line == delimiter {
+                    found = true;
+                    break;
+                }
+                body.push_str(line);
+                body.push('\n');
+            }
+            complete &= found;
+            let mut word = Word::default();
+            if !body.is_empty() {
+                word.parts.push(Part {
+                    text: body,
+                    quoting,
+                });
+            }
+            self.tokens[index] = Token::Word(word);
+        }
+        complete
+    }
+}
+
+// Splits the input in words and operators, in one pass.
+// Quotes and escapes are removed from the words, but their parts keep how they were quoted.
+// Operators and whitespace are only recognized outside of quotes.
+pub(crate) fn tokenize(input: &str) -> Result<Vec<Token>> {
+    match scan(input) {
+        (_, Some(Incomplete::Quote(quote))) => Err(anyhow!(
+            "unexpected EOF while looking for matching `{quote}'"
+        )),
+        // Like bash, a trailing \ is literal and a here-document without delimiter ends with the input
+        (tokens, _) => Ok(tokens),
+    }
+}
+
+// Returns why the input continues in the next line, if it does.
+pub(crate) fn incomplete(input: &str) -> Option<Incomplete> {
+    scan(input).1
+}
+
+fn scan(input: &str) -> (Vec<Token>, Option<Incomplete>) {
+    let mut tokenizer = Tokenizer::default();
+    let mut incomplete = None;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // The bodies of the here-documents start in the next line
+            '\n' => {
+                tokenizer.finish_word();
+                if !tokenizer.here_docs.is_empty() && !tokenizer.read_here_docs(&mut chars) {
+                    incomplete = Some(Incomplete::HereDoc);
+                }
+            }
+            c if c.is_whitespace() => tokenizer.finish_word(),
+            // A comment starts at the beginning of a word and goes until the end of the line
+            '#' if tokenizer.word.is_none() => while chars.next_if(|c| *c != '\n').is_some() {},
+            '\'' => {
+                tokenizer.start_word();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => tokenizer.push(c, Quoting::Single),
+                        None => return (tokenizer.tokens, Some(Incomplete::Quote('\''))),
+                    }
+                }
+            }
+            '"' => {
+                tokenizer.start_word();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // Inside double quotes only these characters can be escaped
+                        Some('\\') => {
+                            match chars.next_if(|c| matches!(c, '$' | '`' | '"' | '\\' | '\n')) {
+                                Some('\n') => {}
+                                Some(c) => tokenizer.push(c, Quoting::Single),
+                                None => tokenizer.push('\\', Quoting::Double),
+                            }
+                        }
+                        Some(c) => tokenizer.push(c, Quoting::Double),
+                        None => return (tokenizer.tokens, Some(Incomplete::Quote('"'))),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                // Line continuation
+                Some('\n') => {}
+                Some(c) => tokenizer.push(c, Quoting::Single),
+                None => {
+                    tokenizer.push('\\', Quoting::Unquoted);
+                    incomplete = Some(Incomplete::Backslash);
+                }
+            },
+            '&' if chars.next_if_eq(&'&').is_some() => tokenizer.operator(Operator::And),
+            '&' => tokenizer.operator(Operator::Background),
+            '|' if chars.next_if_eq(&'|').is_some() => tokenizer.operator(Operator::Or),
+            '|' => tokenizer.operator(Operator::Pipe),
+            ';' => tokenizer.operator(Operator::Semicolon),
+            '(' => tokenizer.operator(Operator::OpenParen),
+            ')' => tokenizer.operator(Operator::CloseParen),
+            '>' if chars.next_if_eq(&'>').is_some() => tokenizer.redirect(RedirectOp::Append),
+            '>' if chars.next_if_eq(&'&').is_some() => tokenizer.redirect(RedirectOp::DupOutput),
+            '>' => tokenizer.redirect(RedirectOp::Output),
+            '<' if chars.next_if_eq(&'<').is_some() => {
+                let strip_tabs = chars.next_if_eq(&'-').is_some();
+                tokenizer.redirect(RedirectOp::HereDoc { strip_tabs })
+            }
+            '<' if chars.next_if_eq(&'&').is_some() => tokenizer.redirect(RedirectOp::DupInput),
+            '<' => tokenizer.redirect(RedirectOp::Input),
+            // An arithmetic expansion is part of the word until its parentheses are closed
+            '$' if chars.clone().take(2).eq("((".chars()) => {
+                tokenizer.push(c, Quoting::Unquoted);
+                let mut depth = 0;
+                for c in chars.by_ref() {
+                    tokenizer.push(c, Quoting::Unquoted);
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            c => tokenizer.push(c, Quoting::Unquoted),
+        }
+    }
+    tokenizer.finish_word();
+    // The line with the here-document operator is the last one
+    if !tokenizer.here_docs.is_empty() {
+        tokenizer.read_here_docs(&mut chars);
+        incomplete = Some(Incomplete::HereDoc);
+    }
+    (tokenizer.tokens, incomplete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The literal text of the words, and the operators as they are written
+    fn tokens(input: &str) -> Vec<String> {
+        tokenize(input)
+            .expect("valid input")
+            .iter()
+            .map(|token| match token {
+                Token::Word(word) => word.literal(),
+                Token::Operator(op) => match op {
+                    Operator::And => "&&",
+                    Operator::Or => "||",
+                    Operator::Semicolon => ";",
+                    Operator::Background => "&",
+                    Operator::Pipe => "|",
+                    Operator::OpenParen => "(",
+                    Operator::CloseParen => ")",
+                }
+                .to_string(),
+                Token::Redirect { fd, op } => {
+                    let op = match op {
+                        RedirectOp::Output => ">",
+                        RedirectOp::Append => ">>",
+                        RedirectOp::Input => "<",
+                        RedirectOp::HereDoc { strip_tabs: false } => "<<",
+                        RedirectOp::HereDoc { strip_tabs: true } => "<<-",
+                        RedirectOp::DupOutput => ">&",
+                        RedirectOp::DupInput => "<&",
+                    };
+                    fd.map_or(op.to_string(), |fd| format!("{fd}{op}"))
+                }
+            })
+            .collect()
+    }
+
+    fn word(parts: &[(&str, Quoting)]) -> Token {
+        Token::Word(Word {
+            parts: parts
+                .iter()
+                .map(|(text, quoting)| Part {
+                    text: text.to_string(),
+                    quoting: *quoting,
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(vec!["echo", "hello", "world"], tokens("echo hello world"));
+        assert_eq!(
+            vec!["echo", "hello", "world"],
+            tokens("  echo   hello\tworld  ")
+        );
+        assert!(tokens("").is_empty());
+        assert!(tokens("   ").is_empty());
+    }
+
+    #[test]
+    fn test_quotes() {
+        assert_eq!(
+            vec!["echo", "hello   world"],
+            tokens("echo 'hello   world'")
+        );
+        assert_eq!(
+            vec!["echo", "hello   world"],
+            tokens("echo \"hello   world\"")
+        );
+        // Adjacent quoted and unquoted text is the same word
+        assert_eq!(vec!["echo", "helloworld"], tokens("echo 'hello'\"world\""));
+        assert_eq!(vec!["echo", "abcd"], tokens("echo a'b'\"c\"d"));
+        assert_eq!(vec!["echo", "it's"], tokens("echo \"it's\""));
+        assert_eq!(vec!["echo", "say \"hi\""], tokens("echo 'say \"hi\"'"));
+        // Empty quotes are an empty word
+        assert_eq!(vec!["echo", "", "x"], tokens("echo '' x"));
+        assert_eq!(vec!["echo", ""], tokens("echo \"\""));
+        assert!(tokenize("echo 'hello").is_err());
+        assert!(tokenize("echo \"hello").is_err());
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(vec!["echo", "hello world"], tokens("echo hello\\ world"));
+        assert_eq!(vec!["echo", "'quoted'"], tokens("echo \\'quoted\\'"));
+        assert_eq!(vec!["echo", "a\\b"], tokens("echo 'a\\b'"));
+        // In double quotes only some characters are escaped
+        assert_eq!(vec!["echo", "a\\b"], tokens("echo \"a\\b\""));
+        assert_eq!(vec!["echo", "a\"b\\c$"], tokens("echo \"a\\\"b\\\\c\\$\""));
+        assert_eq!(vec!["echo", "ab"], tokens("echo a\\\nb"));
+        assert_eq!(vec!["echo", "a&&b;c"], tokens("echo a\\&\\&b\\;c"));
+    }
+
+    #[test]
+    fn test_parts() -> Result<()> {
+        assert_eq!(
+            vec![
+                word(&[("echo", Quoting::Unquoted)]),
+                word(&[
+                    ("a", Quoting::Unquoted),
+                    ("$b c", Quoting::Single),
+                    ("$d", Quoting::Double),
+                    ("$", Quoting::Single),
+                    ("e", Quoting::Unquoted),
+                ]),
+            ],
+            tokenize("echo a'$b c'\"$d\"\\$e")?
+        );
+        let tokens = tokenize("echo \"\"")?;
+        assert_eq!(Token::Word(Word::default()), tokens[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_comments() {
+        assert!(tokens("# a comment").is_empty());
+        assert!(tokens("  # it's not a quote").is_empty());
+        assert_eq!(vec!["echo", "a"], tokens("echo a # the rest is ignored"));
+        assert_eq!(vec!["echo", "a", ";"], tokens("echo a;# comment"));
+        // Inside a word or quoted, # is literal
+        assert_eq!(vec!["echo", "foo#bar", "#"], tokens("echo foo#bar '#'"));
+        assert_eq!(
+            vec!["echo", "# not a comment"],
+            tokens("echo \"# not a comment\"")
+        );
+        assert_eq!(vec!["echo", "#"], tokens("echo \\#"));
+        // Only until the end of the line
+        assert_eq!(vec!["a", "b"], tokens("a # comment\nb"));
+        assert_eq!(None, incomplete("echo a # it's"));
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(
+            vec!["true", "&&", "echo", "yes"],
+            tokens("true && echo yes")
+        );
+        assert_eq!(vec!["true", "&&", "echo", "yes"], tokens("true&&echo yes"));
+        assert_eq!(vec!["a", "||", "b", ";", "c", "&"], tokens("a || b; c &"));
+        assert_eq!(vec!["ls", "|", "wc", "-l"], tokens("ls | wc -l"));
+        assert_eq!(vec!["a", "&", "b"], tokens("a&b"));
+        assert_eq!(vec!["(", "cd", "x", ";", "ls", ")"], tokens("(cd x; ls)"));
+        // Quoted operators are words
+        assert_eq!(
+            vec!["echo", "a && b", "|", ";"],
+            tokens("echo 'a && b' \"|\" ';'")
+        );
+    }
+
+    #[test]
+    fn test_quoted_operators() -> Result<()> {
+        assert_eq!(
+            vec![
+                word(&[("echo", Quoting::Unquoted)]),
+                word(&[("a | b > c", Quoting::Double)]),
+            ],
+            tokenize("echo \"a | b > c\"")?
+        );
+        assert_eq!(
+            vec!["echo", "a", "|", "b", ">", "c"],
+            tokens("echo a | b > c")
+        );
+        // Only the quoted part is literal
+        assert_eq!(vec!["echo", "a|b", ">", "c"], tokens("echo \"a|\"b>c"));
+        assert_eq!(
+            vec!["echo", "<&", "2>&1", "&"],
+            tokens("echo '<&' \"2>&1\" &")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(
+            vec!["echo", "$((2 + 3 * (4 - 1)))", "x"],
+            tokens("echo $((2 + 3 * (4 - 1))) x")
+        );
+        assert_eq!(vec!["a=$((1+2))b", ";"], tokens("a=$((1+2))b;"));
+        // A subshell after a $ is still a subshell
+        assert_eq!(vec!["echo", "$", "(", "a", ")"], tokens("echo $ (a)"));
+    }
+
+    #[test]
+    fn test_redirections() -> Result<()> {
+        assert_eq!(vec!["echo", "a", ">", "file"], tokens("echo a > file"));
+        assert_eq!(vec!["echo", "a", ">", "file"], tokens("echo a>file"));
+        assert_eq!(vec!["echo", "a", ">>", "file"], tokens("echo a >> file"));
+        assert_eq!(vec!["cat", "<", "file"], tokens("cat < file"));
+        assert_eq!(
+            vec!["ls", "2>", "err", "1>", "out"],
+            tokens("ls 2> err 1>out")
+        );
+        assert_eq!(vec!["ls", "2>>", "err"], tokens("ls 2>>err"));
+        assert_eq!(
+            vec!["ls", ">", "/dev/null", "2>&", "1"],
+            tokens("ls > /dev/null 2>&1")
+        );
+        assert_eq!(vec!["cat", "0<&", "3", "3>", "f"], tokens("cat 0<&3 3>f"));
+        // Only a whole unquoted number is a file descriptor
+        assert_eq!(vec!["echo", "a2", ">", "f"], tokens("echo a2>f"));
+        assert_eq!(vec!["echo", "2", ">", "f"], tokens("echo '2'>f"));
+        assert_eq!(vec!["echo", "a > b"], tokens("echo 'a > b'"));
+        assert_eq!(
+            vec![Token::Redirect {
+                fd: Some(2),
+                op: RedirectOp::Output
+            }],
+            tokenize("2>")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_word_unquoted() -> Result<()> {
+        let tokens = tokenize("plain 'quoted' mi'x'")?;
+        let unquoted: Vec<Option<String>> = tokens
+            .iter()
+            .map(|token| match token {
+                Token::Word(word) => word.unquoted(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![Some("plain".to_string()), None, None], unquoted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_here_docs() {
+        assert_eq!(
+            vec!["cat", "<<", "a $X\nb\n", ">", "out"],
+            tokens("cat << EOF > out\na $X\nb\nEOF")
+        );
+        assert_eq!(vec!["cat", "<<-", "a\n"], tokens("cat <<- EOF\n\ta\n\tEOF"));
+        // Bodies are read in order, after the line with the operators
+        assert_eq!(
+            vec!["cat", "<<", "1\n", ";", "cat", "<<", "2\n", "next"],
+            tokens("cat << A; cat << B\n1\nA\n2\nB\nnext")
+        );
+        assert_eq!(vec!["cat", "<<", ""], tokens("cat << EOF\nEOF"));
+    }
+
+    #[test]
+    fn test_here_doc_quoting() -> Result<()> {
+        let body = |input: &str| match tokenize(input).unwrap().remove(2) {
+            Token::Word(word) => word.parts,
+            _ => vec![],
+        };
+        let part = |text: &str, quoting| Part {
+            text: text.to_string(),
+            quoting,
+        };
+        assert_eq!(
+            vec![part("$X\n", Quoting::Double)],
+            body("cat << EOF\n$X\nEOF")
+        );
+        // A quoted delimiter keeps the body literal
+        assert_eq!(
+            vec![part("$X\n", Quoting::Single)],
+            body("cat << 'EOF'\n$X\nEOF")
+        );
+        // Quotes in the body don't matter
+        assert_eq!(
+            vec![part("it's\n", Quoting::Double)],
+            body("cat << EOF\nit's\nEOF")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_incomplete() {
+        assert_eq!(None, incomplete("echo hello"));
+        assert_eq!(Some(Incomplete::Backslash), incomplete("echo \\"));
+        assert_eq!(None, incomplete("echo \\\\"));
+        assert_eq!(Some(Incomplete::Quote('\'')), incomplete("echo 'hello"));
+        assert_eq!(Some(Incomplete::Quote('"')), incomplete("echo \"it's"));
+        assert_eq!(None, incomplete("echo 'a\\'"));
+        assert_eq!(Some(Incomplete::HereDoc), incomplete("cat << EOF"));
+        assert_eq!(Some(Incomplete::HereDoc), incomplete("cat << EOF\nbody"));
+        assert_eq!(None, incomplete("cat << EOF\nit's\nEOF"));
+    }
+}
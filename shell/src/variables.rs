@@ -0,0 +1,445 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::{env, process};
+
+use crate::tokenizer::{Quoting, Word};
+
+// Shell variables, on top of the environment inherited by the shell.
+// Exported variables are passed to the environment of the child processes.
+#[derive(Debug, Default)]
+pub(crate) struct Variables {
+    values: BTreeMap<String, String>,
+    exported: BTreeSet<String>,
+    // $0 followed by the positional parameters $1, $2...
+    positional: Vec<String>,
+    // $$, kept once a subshell is forked so it's still the pid of the shell
+    shell_pid: Option<u32>,
+    // $!, the pid of the last command started in the background
+    last_background: Option<u32>,
+}
+
+impl Variables {
+    pub(crate) fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    // Sets $0 and the positional parameters after it.
+    pub(crate) fn set_positional(&mut self, zero: &str, params: &[String]) {
+        self.positional = std::iter::once(zero.to_string())
+            .chain(params.iter().cloned())
+            .collect();
+    }
+
+    // Keeps the current pid as $$, before forking a subshell.
+    pub(crate) fn pin_shell_pid(&mut self) {
+        self.shell_pid.get_or_insert_with(process::id);
+    }
+
+    pub(crate) fn set_last_background(&mut self, pid: u32) {
+        self.last_background = Some(pid);
+    }
+
+    // The shell variable, or the environment variable with the name.
+    // The special parameters are a number for the positional ones, # for their count,
+    // @ or * for all of them, $ for the pid of the shell and ! for the last background pid.
+    pub(crate) fn get(&self, name: &str) -> Option<String> {
+        let params = self.positional.get(1..).unwrap_or_default();
+        match name {
+            "#" => return Some(params.len().to_string()),
+            "@" | "*" => return Some(params.join(" ")),
+            "$" => return Some(self.shell_pid.unwrap_or_else(process::id).to_string()),
+            "!" => return self.last_background.map(|pid| pid.to_string()),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                return name
+                    .parse()
+                    .ok()
+                    .and_then(|n: usize| self.positional.get(n).cloned())
+            }
+            _ => {}
+        }
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => env::var(name).ok(),
+        }
+    }
+
+    pub(crate) fn export(&mut self, name: &str) {
+        self.exported.insert(name.to_string());
+    }
+
+    // Removes the variable, if it was exported (or inherited) it's removed from the environment too.
+    pub(crate) fn unset(&mut self, name: &str) {
+        self.values.remove(name);
+        if self.exported.remove(name) || env::var_os(name).is_some() {
+            env::remove_var(name);
+        }
+    }
+
+    // The exported variables with a value, to set in the environment of a child.
+    pub(crate) fn exported(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.exported
+            .iter()
+            .filter_map(|name| self.values.get_key_value(name))
+    }
+}
+
+pub(crate) fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Parses an assignment like NAME=value.
+pub(crate) fn parse_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_name(name).then_some((name, value))
+}
+
+// Expands $NAME, ${NAME}, the positional parameters ($1, $#, $@...), $$, $! and $? in the text, unset variables expand to empty.
+// The quoting is handled by the caller, it decides which text is expanded.
+pub(crate) fn expand(text: &str, variables: &Variables, last_status: i32) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => match chars.peek() {
+                Some('?') => {
+                    chars.next();
+                    expanded.push_str(&last_status.to_string());
+                }
+                Some('{') => {
+                    chars.next();
+                    let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    expanded.push_str(&variables.get(&name).unwrap_or_default());
+                }
+                // The positional parameters take one digit like sh, ${10} needs the braces
+                Some(c) if c.is_ascii_digit() || matches!(c, '#' | '@' | '*' | '$' | '!') => {
+                    let name = chars.next().unwrap_or_default().to_string();
+                    expanded.push_str(&variables.get(&name).unwrap_or_default());
+                }
+                Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    expanded.push_str(&variables.get(&name).unwrap_or_default());
+                }
+                // A lone $ is literal
+                _ => expanded.push(c),
+            },
+            _ => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+// Expands the word into the fields it becomes.
+// Single quoted parts are literal and double quoted parts are expanded as one field,
+// the expansion of the unquoted parts is split on whitespace.
+pub(crate) fn expand_word(word: &Word, variables: &Variables, last_status: i32) -> Vec<String> {
+    // Empty quotes are still an empty field
+    if word.parts.is_empty() {
+        return vec![String::new()];
+    }
+    let mut fields = vec![];
+    let mut field: Option<String> = None;
+    for part in &word.parts {
+        match part.quoting {
+            Quoting::Single => field.get_or_insert_with(String::new).push_str(&part.text),
+            Quoting::Double => {
+                let mut pieces = expand_fields(&part.text, variables, last_status).into_iter();
+                if let Some(first) = pieces.next() {
+                    field.get_or_insert_with(String::new).push_str(&first);
+                }
+                for piece in pieces {
+                    fields.extend(field.take());
+                    field = Some(piece);
+                }
+            }
+            Quoting::Unquoted => {
+                let pieces = expand_fields(&part.text, variables, last_status);
+                for (i, piece) in pieces.iter().enumerate() {
+                    // Each positional parameter of $@ is split on its own
+                    if i > 0 {
+                        fields.extend(field.take());
+                    }
+                    for c in piece.chars() {
+                        if !c.is_whitespace() {
+                            field.get_or_insert_with(String::new).push(c);
+                        } else if let Some(field) = field.take() {
+                            fields.push(field);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    fields.extend(field);
+    fields
+}
+
+// Expands the text like expand, but each $@ is a field per positional parameter: the text
+// before it joins the first one and the text after it the last one. Without parameters a text
+// that is only $@ is no field at all.
+fn expand_fields(text: &str, variables: &Variables, last_status: i32) -> Vec<String> {
+    let params = variables.positional.get(1..).unwrap_or_default();
+    let segments = split_at_params(text);
+    let mut fields = vec![expand(segments[0], variables, last_status)];
+    for segment in &segments[1..] {
+        if let Some((first, rest)) = params.split_first() {
+            fields.last_mut().unwrap().push_str(first);
+            fields.extend(rest.iter().cloned());
+        }
+        let segment = expand(segment, variables, last_status);
+        fields.last_mut().unwrap().push_str(&segment);
+    }
+    if segments.len() > 1 && params.is_empty() && fields == [""] {
+        return vec![];
+    }
+    fields
+}
+
+// Splits the text at each $@ or ${@}, the other expansions are left in the segments.
+fn split_at_params(text: &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'$' {
+            i += 1;
+            continue;
+        }
+        let rest = &text[i + 1..];
+        if let Some(len) = ["@", "{@}"]
+            .iter()
+            .find(|params| rest.starts_with(**params))
+            .map(|params| params.len())
+        {
+            segments.push(&text[start..i]);
+            start = i + 1 + len;
+            i = start;
+        } else if rest.starts_with('{') {
+            i += 1 + rest.find('}').unwrap_or(rest.len());
+        } else {
+            // Skips the next character, so the $ of $$ doesn't start another expansion
+            i += 2;
+        }
+    }
+    segments.push(&text[start..]);
+    segments
+}
+
+// Expands the word as a single field, without splitting it.
+fn expand_joined(word: &Word, variables: &Variables, last_status: i32) -> String {
+    word.parts
+        .iter()
+        .map(|part| match part.quoting {
+            Quoting::Single => part.text.clone(),
+            Quoting::Unquoted | Quoting::Double => expand(&part.text, variables, last_status),
+        })
+        .collect()
+}
+
+// Parses the word as an assignment like NAME=value, the name has to be unquoted.
+// The value is expanded but not split.
+pub(crate) fn expand_assignment(
+    word: &Word,
+    variables: &Variables,
+    last_status: i32,
+) -> Option<(String, String)> {
+    let first = word.parts.first()?;
+    if first.quoting != Quoting::Unquoted {
+        return None;
+    }
+    parse_assignment(&first.text)?;
+    let expanded = expand_joined(word, variables, last_status);
+    let (name, value) = parse_assignment(&expanded)?;
+    Some((name.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{tokenize, Token};
+
+    #[test]
+    fn test_parse_assignment() {
+        assert_eq!(Some(("FOO", "bar")), parse_assignment("FOO=bar"));
+        assert_eq!(Some(("_foo1", "")), parse_assignment("_foo1="));
+        assert_eq!(Some(("A", "b=c")), parse_assignment("A=b=c"));
+        assert_eq!(None, parse_assignment("1A=b"));
+        assert_eq!(None, parse_assignment("=b"));
+        assert_eq!(None, parse_assignment("echo"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut variables = Variables::default();
+        variables.set("FOO", "bar");
+        variables.set("EMPTY", "");
+
+        assert_eq!("echo bar", expand("echo $FOO", &variables, 0));
+        assert_eq!("echo barbaz", expand("echo ${FOO}baz", &variables, 0));
+        assert_eq!("echo bar-bar", expand("echo $FOO-$FOO", &variables, 0));
+        assert_eq!("echo  x", expand("echo $UNSET_SHELL_VAR x", &variables, 0));
+        assert_eq!("echo ", expand("echo $EMPTY", &variables, 0));
+        assert_eq!("echo 127", expand("echo $?", &variables, 127));
+        assert_eq!("echo $ 1$", expand("echo $ 1$", &variables, 0));
+    }
+
+    #[test]
+    fn test_expand_pids() {
+        let mut variables = Variables::default();
+        assert_eq!(process::id().to_string(), expand("$$", &variables, 0));
+        // No background command yet
+        assert_eq!("[]", expand("[$!]", &variables, 0));
+        variables.set_last_background(42);
+        assert_eq!("42 42", expand("$! ${!}", &variables, 0));
+        // A trailing $ is still literal
+        assert_eq!(format!("{}$", process::id()), expand("$$$", &variables, 0));
+    }
+
+    #[test]
+    fn test_expand_positional() {
+        let mut variables = Variables::default();
+        let params: Vec<String> = (1..=10).map(|n| format!("p{n}")).collect();
+        variables.set_positional("script", &params);
+
+        assert_eq!("script p1 p9", expand("$0 $1 $9", &variables, 0));
+        // Only one digit without braces
+        assert_eq!("p10 p10", expand("${10} $10", &variables, 0));
+        assert_eq!("10", expand("$#", &variables, 0));
+        let all = params.join(" ");
+        assert_eq!(all, expand("$@", &variables, 0));
+        assert_eq!(all, expand("$*", &variables, 0));
+        assert_eq!(all, expand("${@}", &variables, 0));
+
+        // Unset ones are empty
+        variables.set_positional("script", &params[..1]);
+        assert_eq!("p1|||1", expand("$1|$2|${11}|$#", &variables, 0));
+        assert_eq!("0", expand("$#", &Variables::default(), 0));
+        assert_eq!("[]", expand("[$0$@]", &Variables::default(), 0));
+    }
+
+    // The fields of each word of the input
+    fn fields(input: &str, variables: &Variables) -> Vec<String> {
+        tokenize(input)
+            .unwrap()
+            .iter()
+            .flat_map(|token| match token {
+                Token::Word(word) => expand_word(word, variables, 0),
+                _ => vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_word() {
+        let mut variables = Variables::default();
+        variables.set("FOO", "bar");
+        variables.set("SPACED", " a  b ");
+        variables.set("EMPTY", "");
+
+        assert_eq!(vec!["echo", "bar"], fields("echo $FOO", &variables));
+        assert_eq!(vec!["$FOO"], fields("'$FOO'", &variables));
+        assert_eq!(vec!["$FOO"], fields("\\$FOO", &variables));
+        assert_eq!(vec!["bar"], fields("\"$FOO\"", &variables));
+        // Unquoted expansions are split in fields, quoted ones are kept as they are
+        assert_eq!(vec!["a", "b"], fields("$SPACED", &variables));
+        assert_eq!(vec![" a  b "], fields("\"$SPACED\"", &variables));
+        assert_eq!(
+            vec!["x", "a", "b", "y"],
+            fields("x$SPACED\"y\"", &variables)
+        );
+        assert_eq!(vec!["xbar"], fields("x$FOO", &variables));
+        // An empty unquoted expansion is no field, empty quotes are an empty field
+        assert!(fields("$EMPTY", &variables).is_empty());
+        assert_eq!(vec![""], fields("\"$EMPTY\"", &variables));
+        assert_eq!(vec![""], fields("''", &variables));
+    }
+
+    #[test]
+    fn test_expand_word_params() {
+        let mut variables = Variables::default();
+        let params = ["a b".to_string(), "c".to_string()];
+        variables.set_positional("script", &params);
+
+        // Quoted $@ is a field per parameter, keeping their spaces
+        assert_eq!(vec!["a b", "c"], fields("\"$@\"", &variables));
+        assert_eq!(vec!["[a b", "c]"], fields("\"[$@]\"", &variables));
+        assert_eq!(vec!["a b", "c"], fields("\"${@}\"", &variables));
+        assert_eq!(vec!["a b c"], fields("\"$*\"", &variables));
+        // Unquoted, each parameter is split
+        assert_eq!(vec!["a", "b", "c"], fields("$@", &variables));
+        assert_eq!(vec!["xa", "b", "cy"], fields("x$@y", &variables));
+        assert_eq!(
+            vec![process::id().to_string() + "@"],
+            fields("\"$$@\"", &variables)
+        );
+
+        // Without parameters "$@" is no field
+        variables.set_positional("script", &[]);
+        assert!(fields("\"$@\"", &variables).is_empty());
+        assert_eq!(vec!["[]"], fields("\"[$@]\"", &variables));
+    }
+
+    #[test]
+    fn test_expand_assignment() {
+        let mut variables = Variables::default();
+        variables.set("FOO", "a b");
+        let assignment = |input: &str| match &tokenize(input).unwrap()[0] {
+            Token::Word(word) => expand_assignment(word, &variables, 0),
+            _ => None,
+        };
+
+        assert_eq!(
+            Some(("X".to_string(), "a b".to_string())),
+            assignment("X=$FOO")
+        );
+        assert_eq!(
+            Some(("X".to_string(), "1 2".to_string())),
+            assignment("X='1 2'")
+        );
+        assert_eq!(None, assignment("'X'=1"));
+        assert_eq!(None, assignment("$FOO=1"));
+    }
+
+    #[test]
+    fn test_exported() {
+        let mut variables = Variables::default();
+        variables.set("EXPORTED", "1");
+        variables.set("LOCAL", "2");
+        variables.export("EXPORTED");
+        // Exported without a value
+        variables.export("NO_VALUE");
+
+        let exported: Vec<_> = variables.exported().collect();
+        assert_eq!(vec![(&"EXPORTED".to_string(), &"1".to_string())], exported);
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut variables = Variables::default();
+        variables.set("FOO", "bar");
+        variables.export("FOO");
+        variables.unset("FOO");
+        assert_eq!(None, variables.get("FOO"));
+        assert_eq!(0, variables.exported().count());
+        assert_eq!("echo ", expand("echo $FOO", &variables, 0));
+
+        // Inherited from the environment
+        env::set_var("SHELL_TEST_UNSET_INHERITED", "1");
+        assert_eq!(
+            Some("1".to_string()),
+            variables.get("SHELL_TEST_UNSET_INHERITED")
+        );
+        variables.unset("SHELL_TEST_UNSET_INHERITED");
+        assert_eq!(None, variables.get("SHELL_TEST_UNSET_INHERITED"));
+        assert!(env::var_os("SHELL_TEST_UNSET_INHERITED").is_none());
+
+        // Not existing is a no-op
+        variables.unset("NOT_EXISTING");
+    }
+}
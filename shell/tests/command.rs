@@ -0,0 +1,193 @@
+use std::os::unix::fs::PermissionsExt;
+use std::process::{self, Command};
+use std::{env, fs};
+
+// Runs the shell with -c and the arguments, returning its status and output.
+fn run(args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_shell-starter-rust"))
+        .arg("-c")
+        .args(args)
+        .output()
+        .expect("the shell runs");
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    (output.status.code(), stdout)
+}
+
+#[test]
+fn test_command_string() {
+    assert_eq!((Some(0), "hi\n".to_string()), run(&["echo hi"]));
+    // Exits with the status of the command, without entering the repl
+    assert_eq!((Some(1), String::new()), run(&["false"]));
+    assert_eq!(Some(2), run(&[]).0);
+}
+
+#[test]
+fn test_pipeline() {
+    // The output of the builtins goes through the pipe too
+    assert_eq!(
+        (Some(0), "HI\n".to_string()),
+        run(&["echo hi | tr a-z A-Z"])
+    );
+    assert_eq!(
+        (Some(1), String::new()),
+        run(&["set -o pipefail; false | true"])
+    );
+    // The first stage that failed
+    assert_eq!(
+        Some(2),
+        run(&["set -o pipefail; sh -c 'exit 2' | sh -c 'exit 3' | true"]).0
+    );
+    assert_eq!(Some(0), run(&["sh -c 'exit 2' | sh -c 'exit 3' | true"]).0);
+}
+
+#[test]
+fn test_errexit() {
+    // Stops at the first failing command, with its status
+    assert_eq!(
+        (Some(3), "before\n".to_string()),
+        run(&["set -e; echo before; sh -c 'exit 3'; echo after"])
+    );
+    // Unless it's tested by && or ||
+    assert_eq!(
+        (Some(0), "rescued\nafter\n".to_string()),
+        run(&["set -e; false || echo rescued; false && echo no; echo after"])
+    );
+    assert_eq!(
+        (Some(0), "after\n".to_string()),
+        run(&["set -e; set +e; false; echo after"])
+    );
+}
+
+#[test]
+fn test_positional_parameters() {
+    let (status, stdout) = run(&["echo $0 $1 $2", "name", "a b", "c"]);
+    assert_eq!(Some(0), status);
+    assert_eq!("name a b c\n", stdout);
+}
+
+#[test]
+fn test_script() {
+    let dir = env::temp_dir().join(format!("shell_script_test_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("script.sh");
+    fs::write(
+        &script,
+        "#!/usr/bin/env shell\necho first $1\necho second $2; exit 3\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shell-starter-rust"))
+        .arg(&script)
+        .args(["a", "b"])
+        .output()
+        .expect("the shell runs");
+    assert_eq!(Some(3), output.status.code());
+    assert_eq!(
+        "first a\nsecond b\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shell-starter-rust"))
+        .arg(dir.join("missing.sh"))
+        .output()
+        .expect("the shell runs");
+    assert_eq!(Some(127), output.status.code());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_redirections_out_of_order() {
+    let dir = env::temp_dir().join(format!("shell_redirect_test_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (
+        dir.join("a").display().to_string(),
+        dir.join("b").display().to_string(),
+    );
+    // The file of 4 is opened first, in the descriptor 3
+    let child = format!("sh -c 'echo A >&3; echo B >&4' 4>{b} 3>{a}");
+    assert_eq!(Some(0), run(&[&child]).0);
+    assert_eq!("A\n", fs::read_to_string(&a).unwrap());
+    assert_eq!("B\n", fs::read_to_string(&b).unwrap());
+
+    // The same for the descriptors of the shell itself
+    let shell = format!("exec 4>{b} 3>{a}; sh -c 'echo C >&3; echo D >&4'");
+    assert_eq!(Some(0), run(&[&shell]).0);
+    assert_eq!("C\n", fs::read_to_string(&a).unwrap());
+    assert_eq!("D\n", fs::read_to_string(&b).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_quoted_positional_parameters() {
+    let script = "printf '[%s]\\n' \"$@\"; printf '[%s]\\n' $@";
+    let (status, stdout) = run(&[script, "name", "a b", "c"]);
+    assert_eq!(Some(0), status);
+    assert_eq!("[a b]\n[c]\n[a]\n[b]\n[c]\n", stdout);
+}
+
+#[test]
+fn test_spawn_error() {
+    let dir = env::temp_dir().join(format!("shell_spawn_test_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("noshebang");
+    // Not a script nor a binary the kernel can run
+    fs::write(&file, [0u8, 1, 2, 3]).unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+    let line = format!(
+        "{}; echo status $?; {} & echo after",
+        file.display(),
+        file.display()
+    );
+    let (status, stdout) = run(&[&line]);
+    assert_eq!(Some(0), status);
+    assert_eq!("status 126\nafter\n", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_path_variable() {
+    let dir = env::temp_dir().join(format!("shell_path_variable_test_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("custom_tool");
+    fs::write(&file, "#!/bin/sh\necho tool\n").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+    // Set in the shell without exporting it, and then exported
+    let line = format!(
+        "PATH={}; type custom_tool; custom_tool; export PATH=/does/not/exist; custom_tool; echo $?",
+        dir.display()
+    );
+    let (status, stdout) = run(&[&line]);
+    assert_eq!(Some(0), status);
+    assert_eq!(
+        format!(
+            "custom_tool is {}\ntool\ncustom_tool: command not found\n127\n",
+            file.display()
+        ),
+        stdout
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_home_variable() {
+    // Set in the shell without exporting it
+    let (status, stdout) = run(&["HOME=/tmp; cd; pwd; cd /; cd ~; pwd"]);
+    assert_eq!(Some(0), status);
+    assert_eq!("/tmp\n/tmp\n", stdout);
+}
+
+#[test]
+fn test_unexpected_eof() {
+    let output = Command::new(env!("CARGO_BIN_EXE_shell-starter-rust"))
+        .args(["-c", "echo before\necho \"open"])
+        .output()
+        .expect("the shell runs");
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!("before\n", String::from_utf8_lossy(&output.stdout));
+    assert_eq!(
+        "unexpected EOF while looking for matching '\"'\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!((Some(2), String::new()), run(&["echo a \\"]));
+}